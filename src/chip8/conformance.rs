@@ -0,0 +1,146 @@
+// Compares the final state of a headless run against an .expected file, which is the
+// non-graphical way of checking arithmetic and flag test ROMs. Expected files contain
+// `key = value` lines, where every number is hex (the 0x prefix is optional):
+//
+//      # Comments and empty lines are ignored
+//      pc = 0x20c
+//      i = 0x300
+//      v0 = 0x05
+//      vf = 0x01
+//      [0x300] = 0x12
+//
+// Only the listed registers and memory addresses are compared
+
+use std::fs;
+
+use crate::chip8::cpu::Cpu;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Location {
+    Pc,
+    I,
+    V(usize),
+    Memory(usize),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Expected {
+    values : Vec<(Location, usize)>,
+}
+
+impl Expected {
+    pub fn from_path(path : &str) -> Result<Expected, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read expected file {}: {}", path, e))?;
+
+        Expected::parse(&contents).map_err(|e| format!("Invalid expected file {}: {}", path, e))
+    }
+
+    /// Parses the contents of an expected file
+    pub fn parse(contents : &str) -> Result<Expected, String> {
+        let mut values = Vec::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("line {}: expected `key = value`", number + 1))
+            };
+
+            let location = parse_location(key).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            let value = parse_hex(value).map_err(|e| format!("line {}: {}", number + 1, e))?;
+
+            let max = match location {
+                Location::V(_) | Location::Memory(_) => 0xFF,
+                Location::Pc | Location::I => 0xFFF,
+            };
+            if value > max {
+                return Err(format!("line {}: {:#x} doesn't fit in {}", number + 1, value, key));
+            }
+
+            values.push((location, value));
+        }
+
+        Ok(Expected { values })
+    }
+
+    /// Returns a line for each value that doesn't match the cpu state, empty if everything matches
+    pub fn diff(&self, cpu : &Cpu) -> Vec<String> {
+        let mut diffs = Vec::new();
+
+        for &(location, expected) in self.values.iter() {
+            let (name, actual) = match location {
+                Location::Pc => ("pc".to_string(), cpu.pc()),
+                Location::I => ("i".to_string(), cpu.i()),
+                Location::V(x) => (format!("v{:x}", x), cpu.v()[x] as usize),
+                Location::Memory(addr) => (format!("[{:#05x}]", addr), cpu.memory()[addr] as usize),
+            };
+
+            if actual != expected {
+                diffs.push(format!("{}: expected {:#04x}, got {:#04x}", name, expected, actual));
+            }
+        }
+
+        diffs
+    }
+}
+
+fn parse_location(key : &str) -> Result<Location, String> {
+    let key = key.to_lowercase();
+
+    match key.as_str() {
+        "pc" => Ok(Location::Pc),
+        "i" => Ok(Location::I),
+        _ if key.starts_with('[') && key.ends_with(']') => {
+            let addr = parse_hex(&key[1..key.len() - 1])?;
+            if addr > 0xFFF {
+                return Err(format!("{:#x} is outside of memory", addr));
+            }
+
+            Ok(Location::Memory(addr))
+        },
+        _ if key.len() == 2 && key.starts_with('v') => {
+            usize::from_str_radix(&key[1..], 16)
+                .map(Location::V)
+                .map_err(|_| format!("unknown register `{}`", key))
+        },
+        _ => Err(format!("unknown key `{}`", key))
+    }
+}
+
+fn parse_hex(src : &str) -> Result<usize, String> {
+    let src = src.trim();
+
+    usize::from_str_radix(src.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("'{}' is not a hex number", src))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_registers_and_memory() {
+        let expected = Expected::parse("# test\npc = 0x20c\n\ni = 300\nvA = 0x05\n[0x300] = ff\n").unwrap();
+
+        assert_eq!(expected.values, vec![
+            (Location::Pc, 0x20c),
+            (Location::I, 0x300),
+            (Location::V(0xa), 0x05),
+            (Location::Memory(0x300), 0xff),
+        ]);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(Expected::parse("v0").is_err());
+        assert!(Expected::parse("vg = 1").is_err());
+        assert!(Expected::parse("v0 = 0x100").is_err());
+        assert!(Expected::parse("[0x1000] = 0").is_err());
+        assert!(Expected::parse("sp = 0").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+// The VM without any frontend, for embedding it in other projects that do their own rendering,
+// input and sound. Nothing here touches SDL: the screen is kept in memory (NullGraphics), the
+// keypad is only driven through set_key, and the timers are ticked by whoever owns the core.
+// The SDL frontend (Emulator) builds on the same Cpu, just with real subsystems plugged in
+
+use std::sync::{Arc, Mutex};
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use crate::chip8::cpu::Cpu;
+use crate::chip8::screen::Framebuffer;
+use crate::chip8::timer;
+use crate::config;
+
+pub struct Core<'a> {
+    cpu : Cpu<'a>,
+    timers : Arc<Mutex<(u8, u8)>>, // (delay_timer, sound_timer), only ticked by tick_timers
+}
+
+impl<'a> Core<'a> {
+    /// Builds the core from the config, loading its ROM if it has one. Config::default() gives a
+    /// core without a ROM, which can be loaded afterwards with load_rom
+    pub fn new(config : &'a config::Config) -> Result<Core<'a>, String> {
+        let timers = Arc::new(Mutex::new((0, 0)));
+        let pause = Arc::new(Mutex::new(false));
+        let freq_period = Rc::new(RefCell::new(0)); // the embedder decides how fast to step
+
+        let cpu = Cpu::new_headless(config, Arc::clone(&timers), pause, freq_period)?;
+
+        Ok(Core {
+            cpu,
+            timers,
+        })
+    }
+
+    /// Replaces the program in memory, starting from 0x200
+    pub fn load_rom(&mut self, rom : &[u8]) {
+        self.cpu.load_rom_from_bytes(rom);
+    }
+
+    /// Runs a single instruction. At the usual speed that's around 10 per tick_timers call
+    pub fn step(&mut self) {
+        self.cpu.cycle();
+    }
+
+    /// The current screen, indexed by row and then column. Only the top left part given by
+    /// resolution() is in use, which is 64x32 unless the game switched to the SUPER-CHIP 128x64 mode
+    pub fn framebuffer(&self) -> &Framebuffer {
+        self.cpu.screen()
+    }
+
+    /// The (width, height) of the screen in use
+    pub fn resolution(&self) -> (usize, usize) {
+        self.cpu.resolution()
+    }
+
+    /// Presses or releases a key of the keypad (0x0-0xF), others are ignored
+    pub fn set_key(&mut self, key : usize, pressed : bool) {
+        if pressed {
+            self.cpu.key_down(key);
+        } else {
+            self.cpu.key_up(key);
+        }
+    }
+
+    /// Decrements the delay and sound timers and starts a new frame, meant to be called at 60hz.
+    /// With --deterministic-timers the cpu already ticks them as it runs, so this shouldn't be called then
+    pub fn tick_timers(&mut self) {
+        timer::tick(&mut self.timers.lock().unwrap());
+        self.cpu.frame_started();
+    }
+
+    /// True while the sound timer is running
+    pub fn should_beep(&self) -> bool {
+        self.cpu.is_beeping()
+    }
+
+    /// The cpu itself, for everything else (registers, memory, disassembly...)
+    pub fn cpu(&self) -> &Cpu<'a> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu<'a> {
+        &mut self.cpu
+    }
+}
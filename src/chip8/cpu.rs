@@ -2,18 +2,99 @@
 /// https://en.wikipedia.org/wiki/CHIP-8#Opcode_table, with a couple renamings
 /// and a few instruction rewrites.
 
+use crate::chip8::disasm;
+#[cfg(feature = "sdl")]
 use crate::chip8::graphics::Graphics;
-use crate::chip8::keypad::Keypad;
-
-use rand::Rng;
-use std::fs::File;
-use std::io::prelude::*;
+use crate::chip8::instruction::{decode, Instruction};
+use crate::chip8::keypad::{self, Keypad};
+use crate::chip8::quirks::{ClipMode, Quirks};
+use crate::chip8::rewind::{Rewind, RunCounters, Snapshot};
+use crate::chip8::savestate;
+use crate::chip8::screen::{self, Framebuffer};
+use crate::chip8::sound::AudioPattern;
+#[cfg(feature = "sdl")]
+use crate::chip8::terminal::TerminalGraphics;
+use crate::chip8::replay::{InputRecorder, InputReplay};
+use crate::chip8::renderer::{CpuState, FrameHook, NullGraphics, Renderer, SPRITE_PREVIEW_HEIGHT};
+use crate::chip8::timer::{self, CycleTimer};
+use crate::chip8::trace::{ReferenceTrace, TraceLog};
+use crate::chip8::view::ViewOptions;
+
+use log::{error, info, warn};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io;
 use std::sync::{Arc, Mutex};
 use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::cell::RefCell;
 
 use crate::config;
 
+// ROMs are loaded at 0x200, everything below is reserved for the interpreter
+const PROGRAM_START : usize = 0x200;
+const MAX_ROM_SIZE : usize = 4096 - PROGRAM_START;
+
+// Addresses are 12 bits wide, so masking with this wraps them around the 4096 bytes of memory
+const ADDRESS_MASK : usize = 0xFFF;
+
+// How many of the last executed instructions are kept for the debug panel
+const INSTR_LOG_LENGTH : usize = 12;
+
+// How deep subroutine calls can nest, like on SUPER-CHIP. A ROM going past it is broken
+// (e.g. calling itself forever), so the cpu stops there
+const STACK_DEPTH : usize = 16;
+
+// How many instructions are dumped when pressing the disassembly key
+const DISASM_DUMP_LENGTH : usize = 32;
+
+// The interpreter area below 0x200 holds the small 4x5 font at 0x000-0x04F (5 bytes per glyph,
+// 0 to F), followed by the SUPER-CHIP 8x10 font for the digits 0 to 9 at 0x050-0x0B3
+const SMALL_GLYPH_SIZE : usize = 5;
+const BIG_FONT_START : usize = 0x050;
+const BIG_GLYPH_SIZE : usize = 10;
+const BIG_FONT : [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+// How many pixels 00FB and 00FC scroll the screen sideways
+const SCROLL_DISTANCE : isize = 4;
+
+// Maximum number of cycles the turbo until draw key runs at full speed, in case nothing is drawn
+const TURBO_CYCLE_BUDGET : u32 = 100000;
+
+// How long the cycles are counted for each update of the IPS readout in the debug panel
+const IPS_INTERVAL : Duration = Duration::from_secs(1);
+
+// Builds the CpuState passed to the graphics subsystem. A macro instead of a method, since the
+// graphics subsystem is borrowed mutably at the same time
+macro_rules! cpu_state(
+    ($cpu:expr) => (
+        CpuState {
+            v : &$cpu.v,
+            i : $cpu.i,
+            pc : $cpu.pc,
+            timers : *$cpu.timers.lock().unwrap(),
+            stack : &$cpu.stack,
+            instr_log : &$cpu.instr_log,
+            cycles_since_input : $cpu.cycles_since_input,
+            ips : $cpu.ips,
+            sprite : $cpu.sprite_at_i(),
+        }
+    )
+);
+
 /// Memory layout, registers(v), stack and graphics_subsystem matrix
 pub struct Cpu<'a> {
     memory : [u8; 4096],
@@ -21,25 +102,61 @@ pub struct Cpu<'a> {
     i : usize, // I, limited to 12 bits / 0xFFF
     pc : usize, // Needs to be usize (8 bytes in x86_64) in order to index slices, limited to 12 bits / 0xFFF
     timers : Arc<Mutex<(u8, u8)>>, // (delay_timer, sound_timer), behind a shared mutex, since the timer thread updates them
-    pause : Rc<RefCell<bool>>, // shared pause flag, triggered by the keypad subsystem
+    cycle_timer : Option<CycleTimer>, // ticks the timers instead of the timer thread with --deterministic-timers
+    pause : Arc<Mutex<bool>>, // shared pause flag, triggered by the keypad subsystem
+    was_paused : bool, // pause state during the last cycle, to redraw when it changes
+    step_requested : bool, // runs a single instruction on the next cycle even if paused
+    breakpoints : HashSet<usize>, // addresses from --break that pause the cpu before running them
+    stopped_at : Option<usize>, // the breakpoint it last paused at, so resuming doesn't stop there again
+    reported_opcodes : HashSet<usize>, // addresses of the unknown opcodes already logged
+    quirks : Rc<RefCell<Quirks>>, // shared with the graphics subsystem, and toggled by the keypad one
+    slow_motion : Rc<RefCell<u32>>, // how many times the speed has been halved, set by the keypad subsystem
     // Instead of using a stack and a stack pointer, 
     // we can simply use a Vec and push()/pop() values
     // although we lose the sense of using a limited
     // stack and a SP
     stack : Vec<usize>, // limited to 12 bits / 0xFFF
+    rpl_flags : [u8; 8], // the HP48 RPL user flags SUPER-CHIP saves registers to with FX75
+    planes : u8, // the XO-CHIP bit-planes drawing works on, selected with FN01. Only the first one until then
+    rng : StdRng, // for CXNN, seeded with --seed if there's one
 
     // Pointers to subsystems
-    graphics_subsystem : Box<Graphics<'a>>,
+    graphics_subsystem : Box<dyn Renderer + 'a>,
     keypad_subsystem : Box<Keypad>,
 
     wants_to_quit : bool, // Signals that we have to exit the VM,
-    instr_log : Vec<u16>,   // Instruction log for the display, this could be done with a normal array but we don't need
-                            // it to be fast
+    instr_log : VecDeque<u16>, // Instruction log for the display, the most recent one first
+    last_draw : DrawStats,
+    turbo_cycles : u32, // cycles left to run at full speed, until the next draw
+    cycles_since_input : u64, // reset whenever a key is pressed or released
+    stall_cycles : u32, // cycles left doing nothing, to account for slow instructions
+    screen_changed : bool, // the instructions only change the screen, it's presented on the next render
+    vblank_wait : Option<u64>, // the frame in which DXYN started waiting for the next one, with the display wait quirk
+    frames : u64, // the frames started by whoever runs the cpu, see frame_started
+    audio_pattern : Option<AudioPattern>, // XO-CHIP audio, played by whoever owns the sound. The beep plays until it's set
+    audio_pattern_changed : bool,
+    audio_pattern_cleared : bool, // a reset dropped the pattern, so it's back to the beep
+    reference_trace : Option<ReferenceTrace>, // with --compare-trace, what every instruction should be
+    trace_log : Option<TraceLog>, // with --trace, where every instruction is written
+    cycles : u64, // cycles run so far, pauses don't count. The clock of --record and --replay
+    ips_since : (Instant, u64), // when the IPS readout started measuring, and the cycles back then
+    ips : u32, // cycles per second, measured over the last IPS_INTERVAL
+    input_recorder : Option<InputRecorder>,
+    input_replay : Option<InputReplay>,
+    rewind : Option<Rewind>, // the state before each of the last instructions, with --rewind-depth
 
     // Options
     config : &'a config::Config
 }
 
+/// What happened during the last DXYN, mostly useful for testing clipping and wrapping
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DrawStats {
+    pub drawn : usize, // set sprite pixels that landed on the screen
+    pub collided : usize, // pixels that were flipped from set to unset
+    pub clipped_rows : usize, // sprite rows that fell off the bottom of the screen
+}
+
 /// Indicates the next value the PC is going to have, depending on the result of an instruction
 enum NextPCValue {
     Next,
@@ -48,49 +165,799 @@ enum NextPCValue {
 }
 
 impl Cpu<'_> {
-    pub fn new<'a>(sdl_context : &'a sdl2::Sdl, config : &'a config::Config, timers : Arc<Mutex<(u8, u8)>>, pause : Rc<RefCell<bool>>, freq_period : Rc<RefCell<u64>>, ttf_context : sdl2::ttf::Sdl2TtfContext) -> Cpu<'a> {
-        // Pre-allocate fonts in the reserved space (0x000 to 0x199)
-        let mut temp_memory : [u8; 4096] = [0; 4096]; 
-        
-        Cpu::load_fonts(&mut temp_memory);
-        Cpu::load_rom(config.rom_path(), &mut temp_memory);
-    
-        let pause_inner = Rc::clone(&pause);
-        
-        Cpu {
-            memory : temp_memory,
+    /// Fails if the ROM or any of the --load files can't be loaded, with a message for the user
+    #[cfg(feature = "sdl")]
+    pub fn new<'a>(sdl_context : &'a sdl2::Sdl, config : &'a config::Config, timers : Arc<Mutex<(u8, u8)>>, pause : Arc<Mutex<bool>>, freq_period : Rc<RefCell<u64>>, ttf_context : sdl2::ttf::Sdl2TtfContext) -> Result<Cpu<'a>, String> {
+        // Shared between the keypad, which toggles it, and the graphics subsystem
+        let view = Rc::new(RefCell::new(ViewOptions::from_config(config)));
+        let quirks = Rc::new(RefCell::new(Quirks::from_config(config)));
+        let slow_motion = Rc::new(RefCell::new(0));
+
+        let graphics_subsystem = Box::new(Graphics::new(sdl_context, config, ttf_context, Rc::clone(&view), Rc::clone(&quirks), Arc::clone(&pause), Rc::clone(&slow_motion)));
+        let keypad_subsystem = Box::new(Keypad::new(sdl_context, Arc::clone(&pause), freq_period, view, Rc::clone(&quirks), Rc::clone(&slow_motion), config));
+
+        Cpu::with_subsystems(config, timers, pause, quirks, slow_motion, graphics_subsystem, keypad_subsystem)
+    }
+
+    /// A cpu without any window or keyboard, the screen is only kept in memory. Meant for
+    /// benchmarking and testing, the keypad can still be driven with key_down/key_up
+    pub fn new_headless<'a>(config : &'a config::Config, timers : Arc<Mutex<(u8, u8)>>, pause : Arc<Mutex<bool>>, freq_period : Rc<RefCell<u64>>) -> Result<Cpu<'a>, String> {
+        let view = Rc::new(RefCell::new(ViewOptions::from_config(config)));
+        let quirks = Rc::new(RefCell::new(Quirks::from_config(config)));
+        let slow_motion = Rc::new(RefCell::new(0));
+
+        let graphics_subsystem = Box::new(NullGraphics::new(Rc::clone(&quirks)));
+        let keypad_subsystem = Box::new(Keypad::headless(Arc::clone(&pause), freq_period, view, Rc::clone(&quirks), Rc::clone(&slow_motion), config));
+
+        Cpu::with_subsystems(config, timers, pause, quirks, slow_motion, graphics_subsystem, keypad_subsystem)
+    }
+
+    /// A cpu that prints its screen to the terminal instead of opening a window. Like the headless
+    /// one, it has no keyboard of its own, the keys are pressed with key_down/key_up
+    #[cfg(feature = "sdl")]
+    pub fn new_terminal<'a>(config : &'a config::Config, timers : Arc<Mutex<(u8, u8)>>, pause : Arc<Mutex<bool>>, freq_period : Rc<RefCell<u64>>) -> Result<Cpu<'a>, String> {
+        let view = Rc::new(RefCell::new(ViewOptions::from_config(config)));
+        let quirks = Rc::new(RefCell::new(Quirks::from_config(config)));
+        let slow_motion = Rc::new(RefCell::new(0));
+
+        let graphics_subsystem = Box::new(TerminalGraphics::new(Rc::clone(&quirks)));
+        let keypad_subsystem = Box::new(Keypad::headless(Arc::clone(&pause), freq_period, view, Rc::clone(&quirks), Rc::clone(&slow_motion), config));
+
+        Cpu::with_subsystems(config, timers, pause, quirks, slow_motion, graphics_subsystem, keypad_subsystem)
+    }
+
+    fn with_subsystems<'a>(config : &'a config::Config, timers : Arc<Mutex<(u8, u8)>>, pause : Arc<Mutex<bool>>, quirks : Rc<RefCell<Quirks>>, slow_motion : Rc<RefCell<u32>>, graphics_subsystem : Box<dyn Renderer + 'a>, keypad_subsystem : Box<Keypad>) -> Result<Cpu<'a>, String> {
+        let memory = Cpu::initial_memory(config)?;
+
+        let cycle_timer = if config.deterministic_timers() {
+            Some(CycleTimer::new(Arc::clone(&timers), config.cycles_per_frame()))
+        } else {
+            None
+        };
+
+        let trace_log = match config.trace() {
+            Some(path) => Some(TraceLog::create(path).map_err(|e| format!("Could not create the trace {}: {}", path, e))?),
+            None => None
+        };
+
+        let input_recorder = match config.record() {
+            Some(path) => Some(InputRecorder::create(path).map_err(|e| format!("Could not create the recording {}: {}", path, e))?),
+            None => None
+        };
+
+        Ok(Cpu {
+            memory,
             v : [0; 16],
             i : 0,
-            pc : 0x200, // 0x0 to 0x199 is reserved for the interpreter (fonts...)
-            timers : timers,
-            pause : pause,
+            pc : PROGRAM_START, // 0x0 to 0x1FF is reserved for the interpreter (fonts...)
+            timers,
+            cycle_timer,
+            pause,
+            was_paused : false,
+            step_requested : false,
+            breakpoints : config.breakpoints().iter().copied().collect(),
+            stopped_at : None,
+            reported_opcodes : HashSet::new(),
+            quirks,
+            slow_motion,
             stack : Vec::new(),
-            graphics_subsystem : Box::new(Graphics::new(&sdl_context, config, ttf_context)),
-            keypad_subsystem : Box::new(Keypad::new(&sdl_context, pause_inner, freq_period)),
+            rpl_flags : [0; 8],
+            planes : 1,
+            rng : Cpu::new_rng(config),
+            graphics_subsystem,
+            keypad_subsystem,
             wants_to_quit : false,
-            instr_log : Vec::new(),
-            config : config
+            instr_log : VecDeque::with_capacity(INSTR_LOG_LENGTH + 1),
+            last_draw : DrawStats::default(),
+            turbo_cycles : 0,
+            cycles_since_input : 0,
+            stall_cycles : 0,
+            screen_changed : false,
+            vblank_wait : None,
+            frames : 0,
+            audio_pattern : None,
+            audio_pattern_changed : false,
+            audio_pattern_cleared : false,
+            reference_trace : config.compare_trace().cloned(),
+            trace_log,
+            cycles : 0,
+            ips_since : (Instant::now(), 0),
+            ips : 0,
+            input_recorder,
+            input_replay : config.replay().cloned(),
+            rewind : if config.rewind_depth() > 0 { Some(Rewind::new(config.rewind_depth())) } else { None },
+            config
+        })
+    }
+
+    /// Seeded with --seed, so the same numbers come out on every run, or randomly otherwise
+    fn new_rng(config : &config::Config) -> StdRng {
+        match config.seed() {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy()
         }
     }
+
+    /// The memory at startup: the fonts, the ROM and the --load files, with the --patch bytes applied.
+    /// Fails if any of the files can't be loaded, with a message for the user
+    fn initial_memory(config : &config::Config) -> Result<[u8; 4096], String> {
+        // Pre-allocate fonts in the reserved space (0x000 to 0x1FF)
+        let mut temp_memory : [u8; 4096] = [0; 4096]; 
+        
+        Cpu::load_fonts(&mut temp_memory);
+
+        // Memory regions filled so far, to warn about blobs overwriting each other
+        let mut regions : Vec<(usize, usize)> = Vec::new();
+        if let Some(path) = config.rom_path() {
+            let size = Cpu::load_rom(path, &mut temp_memory)
+                .map_err(|e| format!("Could not load ROM: {}: {}", path, e))?;
+            regions.push((PROGRAM_START, PROGRAM_START + size));
+        }
+
+        // Extra blobs go on top of the ROM, the address is already validated to be inside the program space
+        for (path, addr) in config.loads() {
+            let size = Cpu::load_blob(path, *addr, &mut temp_memory)
+                .map_err(|e| format!("Could not load {}: {}", path, e))?;
+            let end = addr + size;
+
+            for &(start, region_end) in regions.iter() {
+                if *addr < region_end && start < end {
+                    warn!("{} ({:#06x}-{:#06x}) overlaps with data loaded at {:#06x}-{:#06x}", path, addr, end - 1, start, region_end - 1);
+                }
+            }
+            regions.push((*addr, end));
+        }
+
+        // Cheats and experiments, already validated to be inside the program space
+        for &(addr, byte) in config.patches() {
+            info!("Patching {:#06x}: {:#04x} -> {:#04x}", addr, temp_memory[addr], byte);
+            temp_memory[addr] = byte;
+        }
+
+        Ok(temp_memory)
+    }
     
     /// Executes a cycle
     pub fn cycle(&mut self)  {
-        if ! *self.pause.borrow() {
-            // Fetch Opcode
-            // Shift the first part of the instr to the left and merge the second part on it
-            let instr : u16 = (self.memory[self.pc] as u16) << 8 | (self.memory[self.pc + 1] as u16);
+        // Nothing else runs once the cpu is done, e.g. after diverging from the reference trace
+        if self.wants_to_quit {
+            return;
+        }
+
+        let paused = *self.pause.lock().unwrap();
+        let stepping = std::mem::replace(&mut self.step_requested, false) && paused;
 
-            // Log it
-            self.instr_log.insert(0, instr);
-            self.instr_log.truncate(12); // Keep a reasonable log size
+        // The game doesn't draw while paused, so the pause overlay has to be redrawn here
+        if paused != self.was_paused {
+            self.was_paused = paused;
+            self.graphics_subsystem.refresh(&cpu_state!(self));
+        }
+
+        if ! paused || stepping {
+            if stepping {
+                // every step runs an instruction, even a slow one or a DXYN waiting for the next frame
+                self.stall_cycles = 0;
+                self.vblank_wait = None;
+            }
+
+            // Pause before running the instruction, which runs once the cpu is resumed. The cycle
+            // isn't counted and the input isn't read, so breakpoints don't change how a run goes
+            if self.stall_cycles == 0 && ! stepping && self.breakpoints.contains(&self.pc) && self.stopped_at != Some(self.pc) {
+                self.stop_at_breakpoint(self.fetch());
+                return;
+            }
+
+            // Where the run was before this cycle, which undoing the instruction goes back to
+            let counters = self.counters();
+
+            self.cycles_since_input = self.cycles_since_input.saturating_add(1);
+            self.sync_input();
+            self.cycles += 1;
+
+            if self.stall_cycles > 0 {
+                // Still busy with a slow instruction, time passes but nothing runs
+                self.stall_cycles -= 1;
+            } else {
+                let instr = self.fetch();
+
+                // A DXYN waiting for the next frame hasn't run yet, so it's only logged once it draws
+                if stepping || ! self.waits_for_vblank(instr) {
+                    self.run_instr(instr, counters);
+                }
+            }
 
-            // Decode and execute 
-            self.execute_instr(instr);
+            if let Some(cycle_timer) = self.cycle_timer.as_mut() {
+                cycle_timer.update();
+            }
         }
     }
     
+    /// Fetches the opcode at the PC
+    fn fetch(&self) -> u16 {
+        // Shift the first part of the instr to the left and merge the second part on it
+        (self.memory[self.pc] as u16) << 8 | (self.memory[(self.pc + 1) & ADDRESS_MASK] as u16) // the second byte wraps at the end of memory
+    }
+
+    /// Logs, records and runs the fetched instruction. The counters are the ones from before
+    /// the cycle, for undoing it
+    fn run_instr(&mut self, instr : u16, counters : RunCounters) {
+        self.stopped_at = None;
+        self.record_snapshot(counters);
+
+        // Stop before running anything that another emulator didn't
+        if ! self.follows_reference_trace(instr) {
+            self.wants_to_quit = true;
+            return;
+        }
+
+        self.turbo_cycles = self.turbo_cycles.saturating_sub(1);
+
+        // Log it
+        self.instr_log.push_front(instr);
+        if self.instr_log.len() > INSTR_LOG_LENGTH { // Keep a reasonable log size
+            self.instr_log.pop_back();
+        }
+
+        // Decode and execute 
+        self.execute_instr(instr);
+    }
+
+    /// With the display wait quirk, DXYN waits until the next frame starts before drawing, which
+    /// caps the draws at 60 per second. Returns whether the instruction is still waiting
+    fn waits_for_vblank(&mut self, instr : u16) -> bool {
+        if ! self.quirks.borrow().display_wait || ! matches!(decode(instr), Instruction::Draw(..)) {
+            return false;
+        }
+
+        let frame = self.current_frame();
+        match self.vblank_wait {
+            Some(start) if frame > start => {
+                self.vblank_wait = None;
+                false
+            },
+            Some(_) => true,
+            None => {
+                self.vblank_wait = Some(frame);
+                true
+            }
+        }
+    }
+
     pub fn poll_keypad(&mut self) -> bool {
-        self.keypad_subsystem.poll_keyboard()
+        let wants_to_quit = self.keypad_subsystem.poll_keyboard();
+
+        if self.keypad_subsystem.dump_requested() {
+            self.dump_disassembly();
+        }
+
+        if self.keypad_subsystem.screen_dump_requested() {
+            print!("{}", screen::screen_to_ascii(self.screen(), self.resolution()));
+        }
+
+        if self.keypad_subsystem.step_requested() {
+            self.request_step();
+        }
+
+        if self.keypad_subsystem.step_back_requested() && ! self.step_back() {
+            info!("Nothing to step back to, the rewind buffer is empty (see --rewind-depth)");
+        }
+
+        if self.keypad_subsystem.screenshot_requested() {
+            // Named after the time, so they don't overwrite each other
+            let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis());
+            let path = format!("chip8-screenshot-{}.png", millis);
+
+            match self.save_screenshot(&path) {
+                Ok(()) => println!("Saved a screenshot to {}", path),
+                Err(e) => error!("Could not save a screenshot to {}: {}", path, e)
+            }
+        }
+
+        if self.keypad_subsystem.reset_requested() {
+            match self.reset(! self.config.reset_clears_rpl()) {
+                Ok(()) => self.graphics_subsystem.show_message("Reset"),
+                Err(e) => error!("Could not reset: {}", e)
+            }
+        }
+
+        if self.keypad_subsystem.save_state_requested() {
+            match self.state_path() {
+                Some(path) => match self.save_state(&path) {
+                    Ok(()) => {
+                        info!("Saved the state to {}", path);
+                        self.graphics_subsystem.show_message("State saved");
+                    },
+                    Err(e) => error!("Could not save the state to {}: {}", path, e)
+                },
+                None => warn!("There's no ROM file to save the state next to")
+            }
+        }
+
+        if self.keypad_subsystem.load_state_requested() {
+            match self.state_path() {
+                Some(path) => match self.load_state(&path) {
+                    Ok(()) => {
+                        info!("Loaded the state from {}", path);
+                        self.graphics_subsystem.show_message("State loaded");
+                    },
+                    Err(e) => error!("Could not load the state from {}: {}", path, e)
+                },
+                None => warn!("There's no ROM file to load the state from")
+            }
+        }
+
+        if self.keypad_subsystem.turbo_requested() {
+            self.turbo_cycles = TURBO_CYCLE_BUDGET;
+        }
+
+        if let Some(freq_period) = self.keypad_subsystem.freq_changed() {
+            self.graphics_subsystem.show_message(&keypad::describe_frequency(freq_period));
+        }
+
+        if self.keypad_subsystem.changed() {
+            self.cycles_since_input = 0;
+        }
+
+        wants_to_quit
+    }
+
+    /// Returns true if the mute key was pressed since the last call. The sound isn't
+    /// part of the cpu, so whoever owns it takes care of muting
+    pub fn mute_requested(&mut self) -> bool {
+        self.keypad_subsystem.mute_requested()
+    }
+
+    /// Returns how many steps the volume keys moved the volume since the last call, same as with muting
+    pub fn volume_change(&mut self) -> i32 {
+        self.keypad_subsystem.volume_change()
+    }
+
+    /// Presses a key (0x0-0xF) programmatically, for driving the VM without the keyboard,
+    /// such as replays or scripting. Polling the keyboard resets it
+    pub fn key_down(&mut self, key : usize) {
+        self.keypad_subsystem.set_key(key, true);
+
+        if self.keypad_subsystem.changed() {
+            self.cycles_since_input = 0;
+        }
+    }
+
+    /// Releases a key (0x0-0xF) programmatically
+    pub fn key_up(&mut self, key : usize) {
+        self.keypad_subsystem.set_key(key, false);
+
+        if self.keypad_subsystem.changed() {
+            self.cycles_since_input = 0;
+        }
+    }
+
+    /// Presses the keys of the --replay recording for this cycle, and writes the ones
+    /// the instruction will see to the --record one
+    fn sync_input(&mut self) {
+        let cycle = self.cycles;
+
+        if let Some(keys) = self.input_replay.as_mut().and_then(|replay| replay.keys_at(cycle)) {
+            self.keypad_subsystem.set_state(keys);
+
+            if self.keypad_subsystem.changed() {
+                self.cycles_since_input = 0;
+            }
+        }
+
+        if let Some(recorder) = self.input_recorder.as_mut() {
+            if let Err(e) = recorder.record(cycle, self.keypad_subsystem.state()) {
+                warn!("Could not write to the recording, it stops here: {}", e);
+                self.input_recorder = None;
+            }
+        }
+    }
+
+    /// Writes to the --trace file, if there's one. It's dropped on the first error, so a full
+    /// disk doesn't log a warning for every instruction
+    fn write_trace(&mut self, write : impl FnOnce(&mut TraceLog) -> io::Result<()>) {
+        if let Some(trace) = self.trace_log.as_mut() {
+            if let Err(e) = write(trace) {
+                warn!("Could not write to the trace, it stops here: {}", e);
+                self.trace_log = None;
+            }
+        }
+    }
+
+    /// Compares the instruction about to run against the reference trace, if there's one.
+    /// Returns false and reports what the cpu was doing if it's not the expected one
+    fn follows_reference_trace(&mut self, instr : u16) -> bool {
+        let trace = match self.reference_trace.as_mut() {
+            Some(trace) => trace,
+            None => return true
+        };
+
+        let step = trace.position() + 1;
+        match trace.next_step() {
+            Some((pc, opcode)) if pc == self.pc && opcode == instr => true,
+            Some((pc, opcode)) => {
+                let history : Vec<String> = self.instr_log.iter().map(|instr| format!("{:04X}", instr)).collect();
+                let stack : Vec<String> = self.stack.iter().map(|addr| format!("{:#06x}", addr)).collect();
+
+                error!("Diverged from the reference trace at step {}", step);
+                error!("    expected {:#06x}: {:04X}  {}", pc, opcode, disasm::disassemble(opcode));
+                error!("    got      {:#06x}: {:04X}  {}", self.pc, instr, disasm::disassemble(instr));
+                error!("    previous instructions (latest first): {}", history.join(" "));
+                error!("    I: {:#06x}  V: {:02x?}", self.i, self.v);
+                error!("    stack: [{}]", stack.join(", "));
+                false
+            },
+            None => {
+                info!("Reached the end of the reference trace after {} steps, no differences found", step - 1);
+                self.reference_trace = None;
+                true
+            }
+        }
+    }
+
+    /// Tells the cpu that a new 60hz frame starts, whoever runs it calls this once per frame. With
+    /// the display wait quirk, DXYN waits for it (--deterministic-timers counts the frames in cycles instead)
+    pub fn frame_started(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Logs the disassembly of the next instructions starting at the PC.
+    /// It reads the live memory, so self-modifying code shows up as it currently is
+    pub fn dump_disassembly(&self) {
+        info!("Disassembly at PC {:#06x}:", self.pc);
+
+        for line in disasm::disassemble_range(&self.memory, self.pc, DISASM_DUMP_LENGTH) {
+            info!("{}", line);
+        }
+    }
+
+    /// Pauses before running the instruction at a --break address
+    fn stop_at_breakpoint(&mut self, instr : u16) {
+        info!("Breakpoint at {:#06x}: {:04X}  {}", self.pc, instr, disasm::disassemble(instr));
+        self.stopped_at = Some(self.pc);
+        *self.pause.lock().unwrap() = true;
+        self.graphics_subsystem.show_message(&format!("Breakpoint at {:#06x}", self.pc));
+    }
+
+    /// Makes the next cycle run an instruction even while paused, for going through a ROM one
+    /// instruction at a time. Does nothing if the cpu isn't paused
+    pub fn request_step(&mut self) {
+        self.step_requested = true;
+    }
+
+    /// Undoes the last executed instruction, going back to the state right before it.
+    /// Returns false if there's nothing to undo, which is always the case without --rewind-depth
+    pub fn step_back(&mut self) -> bool {
+        let snapshot = match self.rewind.as_mut().and_then(Rewind::pop) {
+            Some(snapshot) => snapshot,
+            None => return false
+        };
+
+        self.restore(snapshot);
+
+        true
+    }
+
+    /// Saves the game area as it's shown to a PNG file
+    pub fn save_screenshot(&self, path : &str) -> io::Result<()> {
+        fs::write(path, self.graphics_subsystem.screenshot())
+    }
+
+    /// Saves the whole machine state to a file, to be loaded later with load_state
+    pub fn save_state(&self, path : &str) -> io::Result<()> {
+        savestate::write_state(&self.snapshot(), path)
+    }
+
+    /// Replaces the machine state with the one saved in the file. Fails without changing
+    /// anything if the file can't be read or isn't a valid save state
+    pub fn load_state(&mut self, path : &str) -> io::Result<()> {
+        // The run goes on from where it is, only the machine state is replaced
+        let counters = RunCounters { vblank_wait : None, ..self.counters() };
+        let snapshot = Snapshot { counters, ..savestate::read_state(path)? };
+        self.restore(snapshot);
+
+        Ok(())
+    }
+
+    /// Where the save state keys save to and load from, next to the ROM
+    fn state_path(&self) -> Option<String> {
+        self.config.rom_path().map(|path| format!("{}.state", path))
+    }
+
+    /// Everything an instruction can change, as it is right now
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory : self.memory,
+            v : self.v,
+            i : self.i,
+            pc : self.pc,
+            stack : self.stack.clone(),
+            rpl_flags : self.rpl_flags,
+            timers : *self.timers.lock().unwrap(), // both at once, the timer thread could tick in between otherwise
+            screen : *self.graphics_subsystem.screen(),
+            hires : self.graphics_subsystem.resolution() == (screen::HIRES_WIDTH, screen::HIRES_HEIGHT),
+            planes : self.planes,
+            instr_log : self.instr_log.clone(),
+            counters : self.counters(),
+        }
+    }
+
+    fn counters(&self) -> RunCounters {
+        RunCounters {
+            cycles : self.cycles,
+            cycles_since_input : self.cycles_since_input,
+            vblank_wait : self.vblank_wait,
+            trace_position : self.reference_trace.as_ref().map(ReferenceTrace::position),
+        }
+    }
+
+    /// Goes back to the snapshot, showing its screen right away
+    fn restore(&mut self, snapshot : Snapshot) {
+        self.memory = snapshot.memory;
+        self.v = snapshot.v;
+        self.i = snapshot.i;
+        self.pc = snapshot.pc;
+        self.stack = snapshot.stack;
+        self.rpl_flags = snapshot.rpl_flags;
+        self.planes = snapshot.planes;
+        self.instr_log = snapshot.instr_log;
+        self.stall_cycles = 0; // the instruction ran, so it wasn't stalled
+        self.last_draw = DrawStats::default();
+        *self.timers.lock().unwrap() = snapshot.timers;
+
+        let counters = snapshot.counters;
+        self.cycles = counters.cycles;
+        self.cycles_since_input = counters.cycles_since_input;
+        self.vblank_wait = counters.vblank_wait;
+        if let (Some(trace), Some(position)) = (self.reference_trace.as_mut(), counters.trace_position) {
+            trace.rewind_to(position);
+        }
+
+        self.graphics_subsystem.set_hires(snapshot.hires);
+        self.graphics_subsystem.restore_screen(&snapshot.screen);
+        self.graphics_subsystem.refresh(&cpu_state!(self));
+    }
+
+    /// Starts over as if the emulator had just been launched: the memory is set up again, reading
+    /// the ROM from disk (so a rebuilt one gets picked up), and the registers, stack, timers and
+    /// screen are cleared. The RPL flags are kept if `keep_rpl` is set, since they're meant to outlive
+    /// the program, and cleared otherwise (`--reset-clears-rpl`). They're never written to disk on
+    /// their own, only as part of a save state, and loading one restores its flags either way.
+    /// Fails if the files can't be loaded anymore, leaving everything as it was
+    pub fn reset(&mut self, keep_rpl : bool) -> Result<(), String> {
+        self.memory = Cpu::initial_memory(self.config)?;
+        self.v = [0; 16];
+        self.i = 0;
+        self.pc = PROGRAM_START;
+        self.stack.clear();
+        if ! keep_rpl {
+            self.rpl_flags = [0; 8];
+        }
+        self.planes = 1;
+        self.reported_opcodes.clear();
+        self.rng = Cpu::new_rng(self.config); // a seeded run goes the same way again
+        self.instr_log.clear();
+        self.last_draw = DrawStats::default();
+        self.turbo_cycles = 0;
+        self.cycles_since_input = 0;
+        self.stall_cycles = 0;
+        self.vblank_wait = None;
+        // Plain CHIP-8 games loaded after an XO-CHIP one get the beep back
+        self.audio_pattern_cleared |= self.audio_pattern.take().is_some();
+        self.audio_pattern_changed = false;
+        self.reference_trace = self.config.compare_trace().cloned();
+        self.write_trace(|trace| trace.note("Reset"));
+        if let Some(rewind) = self.rewind.as_mut() {
+            *rewind = Rewind::new(self.config.rewind_depth()); // there's no going back to before the reset
+        }
+        *self.timers.lock().unwrap() = (0, 0);
+
+        // Restoring a blank screen shows it right away, rather than on the next draw
+        self.graphics_subsystem.set_hires(false);
+        self.graphics_subsystem.restore_screen(&[[0; screen::HIRES_WIDTH]; screen::HIRES_HEIGHT]);
+        self.graphics_subsystem.refresh(&cpu_state!(self));
+
+        info!("Reset");
+        Ok(())
+    }
+
+    /// Keeps the state before executing an instruction, if rewinding is enabled
+    fn record_snapshot(&mut self, counters : RunCounters) {
+        if self.rewind.is_none() {
+            return;
+        }
+
+        let snapshot = Snapshot { counters, ..self.snapshot() };
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.push(snapshot);
+        }
+    }
+
+    /// Runs --cycles-per-frame cycles for each frame and returns the screen after each of them,
+    /// for checking animations rather than a single frame. The timers tick once per frame, so this
+    /// is meant for a cpu whose timers nothing else ticks, such as one made with new_headless
+    pub fn run_and_capture(&mut self, frames : usize) -> Vec<Framebuffer> {
+        let mut captured = Vec::with_capacity(frames);
+
+        for _ in 0..frames {
+            self.frame_started();
+            for _ in 0..self.config.cycles_per_frame() {
+                self.cycle();
+            }
+
+            // --deterministic-timers already ticks them as the cycles run
+            if self.cycle_timer.is_none() {
+                timer::tick(&mut self.timers.lock().unwrap());
+            }
+
+            self.render();
+            captured.push(*self.screen());
+        }
+
+        captured
+    }
+
+    /// Called once per frame by whoever runs the cpu. Presents the screen if an instruction changed
+    /// it since the last frame, and renders the cpu state either way so the debug panel is current
+    pub fn render(&mut self) {
+        self.measure_ips();
+
+        if std::mem::replace(&mut self.screen_changed, false) {
+            self.graphics_subsystem.draw(&cpu_state!(self));
+        } else {
+            self.graphics_subsystem.refresh(&cpu_state!(self));
+        }
+    }
+
+    /// Updates the IPS readout once every IPS_INTERVAL, so it doesn't jump around from frame to frame
+    fn measure_ips(&mut self) {
+        let (since, cycles) = self.ips_since;
+        let elapsed = since.elapsed();
+
+        if elapsed >= IPS_INTERVAL {
+            self.ips = ((self.cycles - cycles) as f64 / elapsed.as_secs_f64()).round() as u32;
+            self.ips_since = (Instant::now(), self.cycles);
+        }
+    }
+
+    /// Renders the last presented frame and the cpu state again, without running anything
+    pub fn refresh(&mut self) {
+        self.graphics_subsystem.refresh(&cpu_state!(self));
+    }
+
+    /// Shows a short message over the game, for things outside the cpu such as the sound
+    pub fn show_message(&mut self, text : &str) {
+        self.graphics_subsystem.show_message(text);
+    }
+
+    /// Prints the registers, the timers and the stack to stdout. This is the output the user asked
+    /// for with --max-cycles or --max-seconds, at the end of the unattended run, so it's not logged
+    pub fn dump_state(&self) {
+        let (delay_timer, sound_timer) = *self.timers.lock().unwrap();
+
+        println!("PC: {:#06x}  I: {:#06x}  DT: {}  ST: {}", self.pc, self.i, delay_timer, sound_timer);
+        for (row, registers) in self.v.chunks(8).enumerate() {
+            let line : Vec<String> = registers.iter().enumerate()
+                .map(|(i, value)| format!("V{:X}: {:#04x}", row * 8 + i, value))
+                .collect();
+            println!("{}", line.join("  "));
+        }
+
+        let stack : Vec<String> = self.stack.iter().map(|addr| format!("{:#06x}", addr)).collect();
+        println!("Stack: [{}]", stack.join(", "));
+    }
+
+    /// Sets a closure that will be called with the screen matrix after every presented frame
+    pub fn set_frame_hook(&mut self, hook : FrameHook) {
+        self.graphics_subsystem.set_frame_hook(hook);
+    }
+
+    /// Replaces the program in memory with the given ROM contents, starting from 0x200
+    pub fn load_rom_from_bytes(&mut self, rom : &[u8]) {
+        let program_memory = &mut self.memory[PROGRAM_START..];
+        let len = rom.len().min(program_memory.len());
+
+        program_memory.fill(0);
+        program_memory[..len].copy_from_slice(&rom[..len]);
+    }
+
+    /// Writes bytes into memory starting at the given address, wrapping at the end of it.
+    /// For setting up data such as sprites when testing, or poking at a running game
+    pub fn write_memory(&mut self, addr : usize, bytes : &[u8]) {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.memory[(addr + offset) & ADDRESS_MASK] = byte;
+        }
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn i(&self) -> usize {
+        self.i
+    }
+
+    pub fn stack(&self) -> &[usize] {
+        &self.stack
+    }
+
+    pub fn memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
+    pub fn screen(&self) -> &Framebuffer {
+        self.graphics_subsystem.screen()
+    }
+
+    /// The current (width, height) of the screen, 128x64 in the SUPER-CHIP high resolution mode
+    pub fn resolution(&self) -> (usize, usize) {
+        self.graphics_subsystem.resolution()
+    }
+
+    /// The last executed opcodes (up to 12), the most recent one first
+    pub fn instruction_log(&self) -> &VecDeque<u16> {
+        &self.instr_log
+    }
+
+    /// Returns what happened during the last executed DXYN
+    pub fn last_draw(&self) -> DrawStats {
+        self.last_draw
+    }
+
+    /// How much longer than the frequency period to wait between cycles, doubled every time
+    /// the slow motion key is pressed
+    pub fn slow_motion_factor(&self) -> u32 {
+        1 << *self.slow_motion.borrow()
+    }
+
+    /// True while the sound timer is running, which is when the system should beep.
+    /// Lets tests and other frontends follow the sound without going through SDL
+    pub fn is_beeping(&self) -> bool {
+        self.timers.lock().unwrap().1 != 0
+    }
+
+    /// Stops the beep right away by clearing the sound timer, the delay timer is left as is
+    pub fn clear_beep(&mut self) {
+        self.timers.lock().unwrap().1 = 0;
+    }
+
+    /// Returns the XO-CHIP audio pattern if F002 or FX3A changed it since the last call
+    pub fn audio_pattern_changed(&mut self) -> Option<AudioPattern> {
+        if std::mem::replace(&mut self.audio_pattern_changed, false) {
+            self.audio_pattern
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if a reset dropped the XO-CHIP audio pattern since the last call, so the beep
+    /// should be played again instead
+    pub fn audio_pattern_cleared(&mut self) -> bool {
+        std::mem::replace(&mut self.audio_pattern_cleared, false)
+    }
+
+    /// How many cycles have run, not counting the ones while paused
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The cycles per second over the last second, as shown in the debug panel. It's only
+    /// measured when rendering
+    pub fn ips(&self) -> u32 {
+        self.ips
+    }
+
+    /// How many cycles have run since a key was last pressed or released
+    pub fn cycles_since_input(&self) -> u64 {
+        self.cycles_since_input
+    }
+
+    /// True while the cpu should run at full speed, skipping to the next draw (e.g. through an intro)
+    pub fn turbo(&self) -> bool {
+        self.turbo_cycles > 0
     }
 
     pub fn finished(&self) -> bool {
@@ -98,89 +965,176 @@ impl Cpu<'_> {
     }
 
 
+    /// The bytes the sprite preview shows, wrapping around the end of memory
+    fn sprite_at_i(&self) -> [u8; SPRITE_PREVIEW_HEIGHT] {
+        let mut sprite = [0; SPRITE_PREVIEW_HEIGHT];
+        for (row, byte) in sprite.iter_mut().enumerate() {
+            *byte = self.memory[(self.i + row) & ADDRESS_MASK];
+        }
+
+        sprite
+    }
+
     fn execute_instr(&mut self, instr : u16) {
-        // Divide the 16-bit instr into 4 groups of 4 bits (represented as an u8)
-        let instr_nibbles = (
-            //                  AAAA BBBB CCCC DDDD
-            // BITWISE_AND      1111 0000 0000 0000
-            //              =   AAAA 0000 0000 0000
-            // >> 12        =   0000 0000 0000 AAAA
-            // as u8        =   0000 AAAA
-            (instr & 0xF000) >> 12 as u8,
-            // Same with the rest
-            (instr & 0x0F00) >> 8 as u8,
-            (instr & 0x00F0) >> 4 as u8,
-            (instr & 0x000F) as u8,
-        );
-
-        // Address part of the instr
-        let nnn = (instr & 0x0FFF) as usize;
-        // 8-bit constant
-        let nn = (instr & 0x00FF) as u8;
-        // 4-bit constant
-        let n = instr_nibbles.3 as usize;
-        // 4-bit v
-        let x = instr_nibbles.1 as usize;
-        let y = instr_nibbles.2 as usize;
-
-        let pc_change = match instr_nibbles { 
-            // ONNN
-            (0x00, 0x00, 0x0e, 0x00) => self.op_00e0(),
-            (0x00, 0x00, 0x0e, 0x0e) => self.op_00ee(),
-            (0x01, _, _, _) => self.op_1nnn(nnn),
-            (0x02, _, _, _) => self.op_2nnn(nnn),
-            (0x03, _, _, _) => self.op_3xkk(x, nn),
-            (0x04, _, _, _) => self.op_4xkk(x, nn),
-            (0x05, _, _, 0x00) => self.op_5xy0(x, y),
-            (0x06, _, _, _) => self.op_6xnn(x, nn),
-            (0x07, _, _, _) => self.op_7xnn(x, nn),
-            (0x08, _, _, 0x00) => self.op_8xy0(x, y),
-            (0x08, _, _, 0x01) => self.op_8xy1(x, y),
-            (0x08, _, _, 0x02) => self.op_8xy2(x, y),
-            (0x08, _, _, 0x03) => self.op_8xy3(x, y),
-            (0x08, _, _, 0x04) => self.op_8xy4(x, y),
-            (0x08, _, _, 0x05) => self.op_8xy5(x, y),
-            (0x08, _, _, 0x06) => self.op_8x06(x),
-            (0x08, _, _, 0x07) => self.op_8xy7(x, y),
-            (0x08, _, _, 0x0e) => self.op_8xye(x),
-            (0x09, _, _, 0x00) => self.op_9xy0(x, y),
-            (0x0a, _, _, _) => self.op_annn(nnn),
-            (0x0b, _, _, _) => self.op_bnnn(nnn),
-            (0x0c, _, _, _) => self.op_cxnn(x, nn),
-            (0x0d, _, _, _) => self.op_dxyn(x, y, n),
-            (0x0e, _, 0x09, 0x0e) => self.op_ex9e(x),
-            (0x0e, _, 0x0a, 0x01) => self.op_exa1(x),
-            (0x0f, _, 0x00, 0x07) => self.op_fx07(x),
-            (0x0f, _, 0x00, 0x0a) => self.op_fx0a(x),
-            (0x0f, _, 0x01, 0x05) => self.op_fx15(x),
-            (0x0f, _, 0x01, 0x08) => self.op_fx18(x),
-            (0x0f, _, 0x01, 0x0e) => self.op_fx1e(x),
-            (0x0f, _, 0x02, 0x09) => self.op_fx29(x),
-            (0x0f, _, 0x03, 0x03) => self.op_fx33(x),
-            (0x0f, _, 0x05, 0x05) => self.op_fx55(x),
-            (0x0f, _, 0x06, 0x05) => self.op_fx65(x),
-            _ => NextPCValue::Next,
+        let pc = self.pc;
+        self.write_trace(|trace| trace.log(pc, instr));
+
+        let quirks = *self.quirks.borrow();
+
+        let pc_change = match decode(instr) {
+            Instruction::ScrollDown(n) => self.op_00cn(n),
+            Instruction::Cls => self.op_00e0(),
+            Instruction::Ret => self.op_00ee(),
+            Instruction::ScrollRight => self.op_00fb(),
+            Instruction::ScrollLeft => self.op_00fc(),
+            Instruction::Lores => self.op_00fe(),
+            Instruction::Hires => self.op_00ff(),
+            Instruction::Sys(nnn) => self.op_0nnn(nnn),
+            Instruction::Jump(nnn) => self.op_1nnn(nnn),
+            Instruction::Call(nnn) => self.op_2nnn(nnn),
+            Instruction::SkipIfEqualByte(x, nn) => self.op_3xkk(x, nn),
+            Instruction::SkipIfNotEqualByte(x, nn) => self.op_4xkk(x, nn),
+            Instruction::SkipIfEqual(x, y) => self.op_5xy0(x, y),
+            Instruction::LoadByte(x, nn) => self.op_6xnn(x, nn),
+            Instruction::AddByte(x, nn) => self.op_7xnn(x, nn),
+            Instruction::Load(x, y) => self.op_8xy0(x, y),
+            Instruction::Or(x, y) => self.op_8xy1(x, y),
+            Instruction::And(x, y) => self.op_8xy2(x, y),
+            Instruction::Xor(x, y) => self.op_8xy3(x, y),
+            Instruction::Add(x, y) => self.op_8xy4(x, y),
+            Instruction::Sub(x, y) => self.op_8xy5(x, y),
+            Instruction::ShiftRight(x, y) => self.op_8xy6(x, y),
+            Instruction::SubN(x, y) => self.op_8xy7(x, y),
+            Instruction::ShiftLeft(x, y) => self.op_8xye(x, y),
+            Instruction::SkipIfNotEqual(x, y) => self.op_9xy0(x, y),
+            Instruction::LoadI(nnn) => self.op_annn(nnn),
+            Instruction::JumpV0(nnn) => self.op_bnnn(nnn),
+            Instruction::Random(x, nn) => self.op_cxnn(x, nn),
+            Instruction::Draw(x, y, n) => self.op_dxyn(x, y, n),
+            Instruction::SkipIfKey(x) => self.op_ex9e(x),
+            Instruction::SkipIfNotKey(x) => self.op_exa1(x),
+            Instruction::Audio if quirks.xo_chip_audio => self.op_f002(),
+            Instruction::Plane(n) if quirks.xo_chip_planes => self.op_fn01(n),
+            Instruction::LoadDelay(x) => self.op_fx07(x),
+            Instruction::WaitKey(x) => self.op_fx0a(x),
+            Instruction::SetDelay(x) => self.op_fx15(x),
+            Instruction::SetSound(x) => self.op_fx18(x),
+            Instruction::AddI(x) => self.op_fx1e(x),
+            Instruction::LoadFont(x) => self.op_fx29(x),
+            Instruction::LoadBigFont(x) => self.op_fx30(x),
+            Instruction::Bcd(x) => self.op_fx33(x),
+            Instruction::Pitch(x) if quirks.xo_chip_audio => self.op_fx3a(x),
+            Instruction::Store(x) => self.op_fx55(x),
+            Instruction::Restore(x) => self.op_fx65(x),
+            Instruction::StoreFlags(x) => self.op_fx75(x),
+            Instruction::RestoreFlags(x) => self.op_fx85(x),
+            // Unknown opcodes, and the XO-CHIP ones when the platform doesn't have them
+            _ => self.unknown_opcode(instr),
         };
             
         
         
+        // The PC wraps around the end of memory like any other 12-bit address, so a bad
+        // jump or running off the end of the ROM can't index past the memory array
         match pc_change {
-            NextPCValue::Next => self.pc += 2, // PC addresses 16 bits, so we need to advance 2 bytes
-            NextPCValue::Skip => self.pc += 4, // Same, skipping the next instruction
-            NextPCValue::Jump(new) => self.pc = new,
+            NextPCValue::Next => self.pc = (self.pc + 2) & ADDRESS_MASK, // PC addresses 16 bits, so we need to advance 2 bytes
+            NextPCValue::Skip => self.pc = (self.pc + 4) & ADDRESS_MASK, // Same, skipping the next instruction
+            NextPCValue::Jump(new) => self.pc = new & ADDRESS_MASK,
         }
     }
 
-    /// Clears the screen. 
+    /// Scrolls the screen down N pixels (SUPER-CHIP). Like the other scrolls, it moves pixels of
+    /// the current resolution, so it moves twice as far on screen in the 64x32 mode
+    fn op_00cn(&mut self, n: usize) -> NextPCValue {
+        self.graphics_subsystem.scroll(0, n as isize, self.planes);
+        self.screen_changed = true;
+
+        NextPCValue::Next
+    }
+
+    /// Clears the screen, only the selected planes on XO-CHIP
     fn op_00e0(&mut self) -> NextPCValue {
-        self.graphics_subsystem.clear_screen();
+        self.graphics_subsystem.clear_screen(self.planes);
+        self.screen_changed = true;
 
         NextPCValue::Next
     }
 
-    /// Returns from a subroutine. 
+    /// Returns from a subroutine. Returning without a call means the ROM is broken, so the cpu stops there
     fn op_00ee(&mut self) -> NextPCValue {
-        NextPCValue::Jump(self.stack.pop().unwrap()) // We need to panic if we try to jump back to a non-existent routine
+        match self.stack.pop() {
+            Some(addr) => NextPCValue::Jump(addr),
+            None => self.stack_error("Return without a subroutine to return from")
+        }
+    }
+
+    /// Scrolls the screen right 4 pixels (SUPER-CHIP)
+    fn op_00fb(&mut self) -> NextPCValue {
+        self.graphics_subsystem.scroll(SCROLL_DISTANCE, 0, self.planes);
+        self.screen_changed = true;
+
+        NextPCValue::Next
+    }
+
+    /// Scrolls the screen left 4 pixels (SUPER-CHIP)
+    fn op_00fc(&mut self) -> NextPCValue {
+        self.graphics_subsystem.scroll(-SCROLL_DISTANCE, 0, self.planes);
+        self.screen_changed = true;
+
+        NextPCValue::Next
+    }
+
+    /// Switches to the 64x32 resolution (SUPER-CHIP), clearing the screen
+    fn op_00fe(&mut self) -> NextPCValue {
+        self.graphics_subsystem.set_hires(false);
+        self.screen_changed = true;
+
+        NextPCValue::Next
+    }
+
+    /// Switches to the 128x64 high resolution (SUPER-CHIP), clearing the screen
+    fn op_00ff(&mut self) -> NextPCValue {
+        self.graphics_subsystem.set_hires(true);
+        self.screen_changed = true;
+
+        NextPCValue::Next
+    }
+
+    /// Stops the cpu on a call or return that the stack can't take, instead of crashing
+    fn stack_error(&mut self, what : &str) -> NextPCValue {
+        error!("{} at PC {:#06x}, stopping", what, self.pc);
+        self.wants_to_quit = true;
+
+        NextPCValue::Jump(self.pc) // left pointing at it for the final state
+    }
+
+    /// Anything that doesn't decode to an instruction is skipped, but it's reported since it usually
+    /// means the ROM jumped into data or needs another platform. In strict mode the cpu stops there
+    fn unknown_opcode(&mut self, instr : u16) -> NextPCValue {
+        if self.config.strict() {
+            error!("Unknown opcode {:#06x} at PC {:#06x}, stopping (see --strict)", instr, self.pc);
+            self.wants_to_quit = true;
+
+            return NextPCValue::Jump(self.pc); // left pointing at it for the final state
+        }
+
+        // Only once per address, a ROM stuck running data would flood the log otherwise
+        if self.reported_opcodes.insert(self.pc) {
+            warn!("Unknown opcode {:#06x} at PC {:#06x}, skipping it", instr, self.pc);
+        }
+
+        NextPCValue::Next
+    }
+
+    /// Calls the machine code routine at address NNN. Only the original interpreters running on
+    /// the real hardware could do this, so it is ignored like modern interpreters do. 
+    /// In strict mode the call is logged, since it usually means a stray opcode in an old ROM
+    fn op_0nnn(&mut self, nnn: usize) -> NextPCValue {
+        if self.config.strict() {
+            warn!("Ignoring SYS call to {:#06x} at PC {:#06x}", nnn, self.pc);
+        }
+
+        NextPCValue::Next
     }
 
     /// Jumps to address NNN.
@@ -190,6 +1144,10 @@ impl Cpu<'_> {
 
     /// Calls subroutine at NNN. 
     fn op_2nnn(&mut self, nnn: usize) -> NextPCValue {
+        if self.stack.len() >= STACK_DEPTH {
+            return self.stack_error(&format!("Stack overflow ({} nested calls)", STACK_DEPTH));
+        }
+
         self.stack.push(self.pc+2); // Store the next PC value
 
         NextPCValue::Jump(nnn)
@@ -251,6 +1209,7 @@ impl Cpu<'_> {
     /// Sets VX to (VX or VY). (Bitwise OR operation); 
     fn op_8xy1(&mut self, x: usize, y: usize) -> NextPCValue {
         self.v[x] |= self.v[y];
+        self.logic_vf_reset();
         
         NextPCValue::Next
     }
@@ -258,6 +1217,7 @@ impl Cpu<'_> {
     /// Sets VX to VX and VY. (Bitwise AND operation); 
     fn op_8xy2(&mut self, x: usize, y: usize) -> NextPCValue {
         self.v[x] &= self.v[y];
+        self.logic_vf_reset();
 
         NextPCValue::Next
     }
@@ -265,10 +1225,18 @@ impl Cpu<'_> {
     /// Sets VX to VX xor VY. 
     fn op_8xy3(&mut self, x: usize, y: usize) -> NextPCValue {
         self.v[x] ^= self.v[y];
+        self.logic_vf_reset();
         
         NextPCValue::Next
     }
 
+    /// With the VF reset quirk, the logic instructions leave VF at 0
+    fn logic_vf_reset(&mut self) {
+        if self.quirks.borrow().vf_reset {
+            self.v[0x0f] = 0;
+        }
+    }
+
     /// Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there is not. 
     fn op_8xy4(&mut self, x: usize, y: usize) -> NextPCValue {
         // https://doc.rust-lang.org/std/primitive.u8.html#method.overflowing_add
@@ -294,9 +1262,17 @@ impl Cpu<'_> {
     }
 
     /// Stores the least significant bit of VX in VF and then shifts VX to the right by 1
-    fn op_8x06(&mut self, x: usize) -> NextPCValue {
-        self.v[0x0f] = self.v[x] & 0b00000001;
+    /// The flag is written last, so it persists even when X is F (same as 8XY4).
+    /// With the shift quirk VY is copied into VX first, which is what the COSMAC VIP did
+    fn op_8xy6(&mut self, x: usize, y: usize) -> NextPCValue {
+        if self.quirks.borrow().shift_uses_vy {
+            self.v[x] = self.v[y];
+        }
+
+        let flag = self.v[x] & 0b00000001;
+        
         self.v[x] >>= 1;
+        self.v[0x0f] = flag;
         
         NextPCValue::Next
     }
@@ -314,9 +1290,17 @@ impl Cpu<'_> {
     }
 
     // Stores the most significant bit of VX in VF and then shifts VX to the left by 1
-    fn op_8xye(&mut self, x: usize) -> NextPCValue {
-        self.v[0x0f] = (self.v[x] & 0b10000000) >> 7;
+    // The flag is written last, so it persists even when X is F (same as 8XY4).
+    // Same as 8XY6 with the shift quirk
+    fn op_8xye(&mut self, x: usize, y: usize) -> NextPCValue {
+        if self.quirks.borrow().shift_uses_vy {
+            self.v[x] = self.v[y];
+        }
+
+        let flag = (self.v[x] & 0b10000000) >> 7;
+        
         self.v[x] <<= 1;
+        self.v[0x0f] = flag;
         
         NextPCValue::Next
     }
@@ -338,14 +1322,20 @@ impl Cpu<'_> {
     }
 
     /// Jumps to the address NNN plus V0. 
+    /// With the jump quirk it's read as BXNN instead, jumping to XNN plus VX like on SUPER-CHIP
     fn op_bnnn(&mut self, nnn: usize) -> NextPCValue {
-        NextPCValue::Jump((self.v[0] as usize) + nnn)
+        let offset = if self.quirks.borrow().jump_uses_vx {
+            self.v[(nnn >> 8) & 0xF]
+        } else {
+            self.v[0]
+        };
+
+        NextPCValue::Jump((offset as usize) + nnn)
     }
 
     /// Sets VX to the result of a bitwise and operation on a random number (Typically: 0 to 255) and NN. 
     fn op_cxnn(&mut self, x: usize, nn: u8) -> NextPCValue {
-        let mut rng = rand::thread_rng();
-        self.v[x] = rng.gen_range(0..255 as u8) & nn;
+        self.v[x] = self.rng.gen::<u8>() & nn;
         
         NextPCValue::Next
     }
@@ -357,42 +1347,93 @@ impl Cpu<'_> {
     /// when the sprite is drawn, and to 0 if that does not happen 
     fn op_dxyn(&mut self, x: usize, y: usize, n: usize) -> NextPCValue {
         // https://tobiasvl.github.io/blog/write-a-chip-8-emulator/#dxyn-display
-        // The starting coordinates and the drawing itself are wrapped depending on the config option
+        // The starting coordinates and the drawing itself are wrapped depending on the clip mode
+        // With the display wait quirk, the cycle only gets here once the next frame has started
+        let quirks = *self.quirks.borrow();
+
+        let wrapping = quirks.clip_mode == ClipMode::Wrap;
+        let (screen_width, screen_height) = self.graphics_subsystem.resolution();
+
+        // The origin is always on screen when it wraps, only the rest of the sprite gets clipped.
+        // Read before clearing VF, which might be one of the coordinates
+        let (origin_x, origin_y) = if quirks.wrap_start_coord {
+            (self.v[x] as usize % screen_width, self.v[y] as usize % screen_height)
+        } else {
+            (self.v[x] as usize, self.v[y] as usize)
+        };
+
         self.v[0x0f] = 0;
-    
-        for height in 0..n {
-            let y_coord;
+        let mut stats = DrawStats::default();
+
+        // On XO-CHIP, a sprite is drawn on each selected plane. With both of them, the sprite for
+        // the second one comes right after the one for the first in memory
+        let selected = self.planes;
+        let planes = [1, 2].iter().copied().filter(|&plane| selected & plane != 0);
+        for (index, plane) in planes.enumerate() {
+            let sprite = self.i + index * n;
+
+            for height in 0..n {
+                let y_coord = if ! wrapping {
+                    origin_y + height
+                } else {
+                    (origin_y + height) % screen_height
+                };
+
+                if y_coord >= screen_height {
+                    if index == 0 { // the same rows are clipped on every plane
+                        stats.clipped_rows += 1;
+                    }
+                    continue;
+                }
 
-            if ! self.config.wrapping_enabled() {
-                y_coord = self.v[y] as usize + height; 
-            } else {
-                y_coord = (self.v[y] as usize + height) % 32;
-            }
+                for width in 0..8 {
+                    let x_coord = if ! wrapping {
+                        origin_x + width
+                    } else {
+                        (origin_x + width) % screen_width
+                    };
 
-            for width in 0..8 {
-                let x_coord; 
+                    // gets the corresponding column value of the row by shifting, starting from the MSB
+                    // the sprite wraps around the end of memory, as it would with a 12-bit address bus
+                    let color = (self.memory[(sprite + height) & ADDRESS_MASK] >> (7 - width)) & 0b00000001;
 
-                if ! self.config.wrapping_enabled() {
-                    x_coord = self.v[x] as usize + width;
-                } else {
-                    x_coord = (self.v[x] as usize + width) % 64;
+                    let collided = self.graphics_subsystem.set_pos(x_coord, y_coord, color * plane);
+                    self.v[0x0f] |= collided;
+
+                    if color == 1 && x_coord < screen_width {
+                        stats.drawn += 1;
+                    }
+                    stats.collided += collided as usize;
                 }
+            }
+        }
 
-                // gets the corresponding column value of the row by shifting, starting from the MSB
-                let color = (self.memory[self.i + height] >> (7 - width)) & 0b00000001;
+        self.last_draw = stats;
+        self.turbo_cycles = 0; // We got to the next draw, back to normal speed
 
-                self.v[0x0f] |= self.graphics_subsystem.set_pos(x_coord, y_coord, color);
-            }
+        // Drawing took a while on slower hardware, games written for it may rely on that
+        if quirks.draw_cost {
+            self.stall_cycles = n as u32;
         }
-        
-        self.graphics_subsystem.draw(&self.v, &self.stack, &self.instr_log);
+        self.screen_changed = true; // shown on the next render, once per frame
 
         NextPCValue::Next
     }
 
+    /// The 60hz frame the cpu is in, counted in cycles with --deterministic-timers
+    /// and the frames actually run (frame_started) otherwise
+    fn current_frame(&self) -> u64 {
+        match &self.cycle_timer {
+            Some(cycle_timer) => cycle_timer.frames(),
+            None => self.frames
+        }
+    }
+
     /// Skips the next instruction if the key stored in VX is pressed. 
     /// (Usually the next instruction is a jump to skip a code block); 
     fn op_ex9e(&mut self, x: usize) -> NextPCValue {
+        self.check_key_value(x);
+
         if self.keypad_subsystem.is_pressed(self.v[x] as usize) {
             return NextPCValue::Skip;
         }
@@ -403,6 +1444,8 @@ impl Cpu<'_> {
     /// Skips the next instruction if the key stored in VX is not pressed. 
     /// (Usually the next instruction is a jump to skip a code block); 
     fn op_exa1(&mut self, x: usize) -> NextPCValue {
+        self.check_key_value(x);
+
         if ! self.keypad_subsystem.is_pressed(self.v[x] as usize) {
             return NextPCValue::Skip;
         }
@@ -410,6 +1453,14 @@ impl Cpu<'_> {
         NextPCValue::Next  
     }
 
+    /// Keys above 0xF are never pressed, which is safe but usually means a ROM bug or a quirk
+    /// mismatch, so in strict mode it's logged
+    fn check_key_value(&self, x: usize) {
+        if self.config.strict() && self.v[x] > 0xF {
+            warn!("V{:X} holds {:#04x}, which is not a key, at PC {:#06x}", x, self.v[x], self.pc);
+        }
+    }
+
     
     /// Sets VX to the value of the delay timer. 
     fn op_fx07(&mut self, x: usize) -> NextPCValue {
@@ -457,9 +1508,9 @@ impl Cpu<'_> {
         NextPCValue::Next
     }
 
-    /// Adds VX to I. VF is not affected
+    /// Adds VX to I. VF is not affected. I wraps around at the end of memory
     fn op_fx1e(&mut self, x: usize) -> NextPCValue {
-        self.i += self.v[x] as usize;
+        self.i = (self.i + self.v[x] as usize) & ADDRESS_MASK;
         
         NextPCValue::Next
     }
@@ -468,7 +1519,15 @@ impl Cpu<'_> {
     /// Characters 0-F (in hexadecimal) are represented by a 4x5 font. 
     fn op_fx29(&mut self, x: usize) -> NextPCValue {
         // Fonts are pre-allocated starting from 0x0, and each one is 5 bytes long        
-        self.i = (self.v[x] as usize) * 5;
+        self.i = (self.v[x] as usize) * SMALL_GLYPH_SIZE;
+
+        NextPCValue::Next
+    }
+
+    /// Sets I to the location of the big 8x10 sprite for the digit in VX (SUPER-CHIP).
+    /// There are only digits 0-9, anything above points past the end of the big font
+    fn op_fx30(&mut self, x: usize) -> NextPCValue {
+        self.i = BIG_FONT_START + (self.v[x] as usize) * BIG_GLYPH_SIZE;
 
         NextPCValue::Next
     }
@@ -478,32 +1537,91 @@ impl Cpu<'_> {
     ///
     /// (In other words, take the decimal representation of VX, place the hundreds digit in memory at location in I, 
     /// the tens digit at location I+1, and the ones digit at location I+2.); 
+    /// Addresses past the end of memory wrap around to 0x000, like in FX55
     fn op_fx33(&mut self, x: usize) -> NextPCValue {
         self.memory[self.i] = self.v[x] / 100; // hundreds digit
 
-        self.memory[self.i + 1] = (self.v[x] % 100) / 10; // tens digit
+        self.memory[(self.i + 1) & ADDRESS_MASK] = (self.v[x] % 100) / 10; // tens digit
 
-        self.memory[self.i + 2] = self.v[x] % 10; // ones digit
+        self.memory[(self.i + 2) & ADDRESS_MASK] = self.v[x] % 10; // ones digit
         
         NextPCValue::Next
     }
 
     /// Stores V0 to VX (including VX) in memory starting at address I
-    /// The offset from I is increased by 1 for each value written, but I itself is left unmodified
+    /// The offset from I is increased by 1 for each value written. I itself is left unmodified,
+    /// like SUPER-CHIP and modern interpreters do, unless the load/store quirk is enabled, in which
+    /// case it ends up at I + X + 1 like on the original CHIP-8 interpreter.
+    /// Addresses past the end of memory wrap around to 0x000, as they would with a 12-bit address bus
     fn op_fx55(&mut self, x: usize) -> NextPCValue {
         for i in 0..=x {
-            self.memory[self.i + i] = self.v[i];
+            self.memory[(self.i + i) & ADDRESS_MASK] = self.v[i];
         }
+        self.load_store_increment(x);
 
         NextPCValue::Next
     }
 
     /// Fills V0 to VX (including VX) with values from memory starting at address I. 
-    /// The offset from I is increased by 1 for each value written, but I itself is left unmodified.
+    /// The offset from I is increased by 1 for each value written. As with FX55, I is left unmodified
+    /// unless the load/store quirk is enabled, which leaves it at I + X + 1 (original CHIP-8).
+    /// Addresses past the end of memory wrap around to 0x000, as they would with a 12-bit address bus
     fn op_fx65(&mut self, x: usize) -> NextPCValue {
         for i in 0..=x {
-            self.v[i] = self.memory[self.i + i];
+            self.v[i] = self.memory[(self.i + i) & ADDRESS_MASK];
+        }
+        self.load_store_increment(x);
+
+        NextPCValue::Next
+    }
+
+    /// Stores V0 to VX (including VX) in the RPL user flags (SUPER-CHIP). There are only 8 of them,
+    /// so X is clamped to 7. They survive resets on the HP48, games use them for things like high scores
+    fn op_fx75(&mut self, x: usize) -> NextPCValue {
+        let x = x.min(self.rpl_flags.len() - 1);
+        self.rpl_flags[..=x].copy_from_slice(&self.v[..=x]);
+
+        NextPCValue::Next
+    }
+
+    /// Fills V0 to VX (including VX) from the RPL user flags (SUPER-CHIP), with X clamped to 7 as in FX75
+    fn op_fx85(&mut self, x: usize) -> NextPCValue {
+        let x = x.min(self.rpl_flags.len() - 1);
+        self.v[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+
+        NextPCValue::Next
+    }
+
+    /// With the load/store quirk, FX55 and FX65 leave I after the last register they went through
+    fn load_store_increment(&mut self, x: usize) {
+        if self.quirks.borrow().increment_i_on_store {
+            self.i = (self.i + x + 1) & ADDRESS_MASK;
+        }
+    }
+
+    /// XO-CHIP: loads the 16 bytes starting at I into the audio pattern buffer
+    fn op_f002(&mut self) -> NextPCValue {
+        let audio_pattern = self.audio_pattern.get_or_insert_with(AudioPattern::default);
+        for (offset, byte) in audio_pattern.pattern.iter_mut().enumerate() {
+            *byte = self.memory[(self.i + offset) & ADDRESS_MASK];
         }
+        self.audio_pattern_changed = true;
+
+        NextPCValue::Next
+    }
+
+    /// XO-CHIP: selects the bit-planes that drawing, clearing and scrolling work on, from the
+    /// mask N (bit 0 for the first plane and bit 1 for the second). 0 selects none, so nothing is drawn
+    fn op_fn01(&mut self, n: usize) -> NextPCValue {
+        self.planes = n as u8 & screen::ALL_PLANES;
+
+        NextPCValue::Next
+    }
+
+    /// XO-CHIP: sets the playback pitch of the audio pattern to VX
+    fn op_fx3a(&mut self, x: usize) -> NextPCValue {
+        self.audio_pattern.get_or_insert_with(AudioPattern::default).pitch = self.v[x];
+        self.audio_pattern_changed = true;
 
         NextPCValue::Next
     }
@@ -590,12 +1708,45 @@ impl Cpu<'_> {
         memory[i] = 0xF0; i+=1;
         memory[i] = 0x80; i+=1;
         memory[i] = 0x80;
+
+        memory[BIG_FONT_START..BIG_FONT_START + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
     }
 
-    fn load_rom(path : &str, memory : &mut [u8; 4096]) {
-        let mut file = File::open(path).unwrap();
-        
-        // Insert the ROM contents, starting from 0x200
-        file.read(&mut memory[0x200..]).unwrap();
+    /// Copies a whole file into memory at the given address, returns how many bytes were loaded.
+    /// Whatever doesn't fit before the end of memory is left out
+    fn load_blob(path : &str, addr : usize, memory : &mut [u8; 4096]) -> io::Result<usize> {
+        let blob = fs::read(path)?;
+        let size = blob.len().min(memory.len() - addr);
+
+        if size < blob.len() {
+            warn!("{} doesn't fit at {:#06x}, only its first {} of {} bytes were loaded", path, addr, size, blob.len());
+        }
+
+        memory[addr..addr + size].copy_from_slice(&blob[..size]);
+        info!("Loaded {} at {:#06x}-{:#06x}", path, addr, addr + size - 1);
+
+        Ok(size)
+    }
+
+    /// Copies the ROM into memory starting from 0x200, returns its size.
+    /// ROMs that don't fit in memory are rejected instead of loading only part of them
+    fn load_rom(path : &str, memory : &mut [u8; 4096]) -> io::Result<usize> {
+        let rom = fs::read(path)?;
+        let size = rom.len();
+
+        if size > MAX_ROM_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("the ROM is {} bytes long, but only {} fit in memory", size, MAX_ROM_SIZE)));
+        }
+
+        memory[PROGRAM_START..PROGRAM_START + size].copy_from_slice(&rom);
+
+        // Opcodes are 2 bytes long, the last byte is left as is, and the following one is
+        // already 0 so it forms an opcode anyway
+        if size % 2 == 1 {
+            warn!("The ROM has an odd size ({} bytes), its last byte doesn't form a full opcode", size);
+        }
+
+        Ok(size)
     }
 }
@@ -0,0 +1,195 @@
+// Turns raw opcodes into human-readable mnemonics, mostly following the naming
+// in http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#3.1
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::instruction::{decode, Instruction};
+
+/// Returns the mnemonic for the given instruction, or `DB <hex>` if it is not a
+/// known opcode (it's probably data, such as sprites)
+pub fn disassemble(instr : u16) -> String {
+    match decode(instr) {
+        Instruction::ScrollDown(n) => format!("SCD {}", n),
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::ScrollRight => "SCR".to_string(),
+        Instruction::ScrollLeft => "SCL".to_string(),
+        Instruction::Lores => "LOW".to_string(),
+        Instruction::Hires => "HIGH".to_string(),
+        Instruction::Sys(nnn) => format!("SYS {:#05x}", nnn),
+        Instruction::Jump(nnn) => format!("JP {:#05x}", nnn),
+        Instruction::Call(nnn) => format!("CALL {:#05x}", nnn),
+        Instruction::SkipIfEqualByte(x, nn) => format!("SE V{}, {:#04x}", x, nn),
+        Instruction::SkipIfNotEqualByte(x, nn) => format!("SNE V{}, {:#04x}", x, nn),
+        Instruction::SkipIfEqual(x, y) => format!("SE V{}, V{}", x, y),
+        Instruction::LoadByte(x, nn) => format!("LD V{}, {:#04x}", x, nn),
+        Instruction::AddByte(x, nn) => format!("ADD V{}, {:#04x}", x, nn),
+        Instruction::Load(x, y) => format!("LD V{}, V{}", x, y),
+        Instruction::Or(x, y) => format!("OR V{}, V{}", x, y),
+        Instruction::And(x, y) => format!("AND V{}, V{}", x, y),
+        Instruction::Xor(x, y) => format!("XOR V{}, V{}", x, y),
+        Instruction::Add(x, y) => format!("ADD V{}, V{}", x, y),
+        Instruction::Sub(x, y) => format!("SUB V{}, V{}", x, y),
+        Instruction::ShiftRight(x, _) => format!("SHR V{}", x),
+        Instruction::SubN(x, y) => format!("SUBN V{}, V{}", x, y),
+        Instruction::ShiftLeft(x, _) => format!("SHL V{}", x),
+        Instruction::SkipIfNotEqual(x, y) => format!("SNE V{}, V{}", x, y),
+        Instruction::LoadI(nnn) => format!("LD I, {:#05x}", nnn),
+        Instruction::JumpV0(nnn) => format!("JP V0, {:#05x}", nnn),
+        Instruction::Random(x, nn) => format!("RND V{}, {:#04x}", x, nn),
+        Instruction::Draw(x, y, n) => format!("DRW V{}, V{}, {}", x, y, n),
+        Instruction::SkipIfKey(x) => format!("SKP V{}", x),
+        Instruction::SkipIfNotKey(x) => format!("SKNP V{}", x),
+        Instruction::Audio => "AUDIO".to_string(),
+        Instruction::Plane(n) => format!("PLANE {}", n),
+        Instruction::LoadDelay(x) => format!("LD V{}, DT", x),
+        Instruction::WaitKey(x) => format!("LD V{}, K", x),
+        Instruction::SetDelay(x) => format!("LD DT, V{}", x),
+        Instruction::SetSound(x) => format!("LD ST, V{}", x),
+        Instruction::AddI(x) => format!("ADD I, V{}", x),
+        Instruction::LoadFont(x) => format!("LD F, V{}", x),
+        Instruction::LoadBigFont(x) => format!("LD HF, V{}", x),
+        Instruction::Bcd(x) => format!("LD B, V{}", x),
+        Instruction::Pitch(x) => format!("PITCH V{}", x),
+        Instruction::Store(x) => format!("LD [I], V{}", x),
+        Instruction::Restore(x) => format!("LD V{}, [I]", x),
+        Instruction::StoreFlags(x) => format!("LD R, V{}", x),
+        Instruction::RestoreFlags(x) => format!("LD V{}, R", x),
+        Instruction::Unknown(instr) => format!("DB {:#06x}", instr),
+    }
+}
+
+/// A disassembled instruction, or a data word that doesn't decode to one
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisasmLine {
+    pub addr : usize,
+    pub bytes : Vec<u8>, // usually 2, only 1 for the last byte of an odd sized ROM
+    pub mnemonic : String,
+    pub operands : Vec<String>,
+    pub is_data : bool, // doesn't decode to a known opcode, such as sprites
+}
+
+/// How a listing is written out, with --disasm-format
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisasmFormat {
+    Plain,
+    Json,
+}
+
+impl DisasmLine {
+    pub fn new(addr : usize, bytes : &[u8]) -> DisasmLine {
+        let text = match bytes {
+            [high, low] => disassemble((*high as u16) << 8 | (*low as u16)),
+            _ => format!("DB {:#04x}", bytes[0]), // a lone byte is always data
+        };
+
+        // The mnemonic is the first word, the operands are separated by commas
+        let (mnemonic, operands) = match text.split_once(' ') {
+            Some((mnemonic, operands)) => (mnemonic.to_string(), operands.split(", ").map(String::from).collect()),
+            None => (text, Vec::new())
+        };
+
+        DisasmLine {
+            addr,
+            bytes : bytes.to_vec(),
+            is_data : mnemonic == "DB",
+            mnemonic,
+            operands,
+        }
+    }
+
+    /// A single JSON object, all the strings are mnemonics so nothing needs escaping
+    pub fn to_json(&self) -> String {
+        let bytes : Vec<String> = self.bytes.iter().map(|byte| byte.to_string()).collect();
+        let operands : Vec<String> = self.operands.iter().map(|operand| format!("\"{}\"", operand)).collect();
+
+        format!("{{\"address\": {}, \"bytes\": [{}], \"mnemonic\": \"{}\", \"operands\": [{}], \"is_data\": {}}}",
+            self.addr, bytes.join(", "), self.mnemonic, operands.join(", "), self.is_data)
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let bytes : String = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+        write!(f, "{:#06x}: {:4}  {}", self.addr, bytes, self.mnemonic)?;
+        if ! self.operands.is_empty() {
+            write!(f, " {}", self.operands.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for DisasmFormat {
+    type Err = String;
+
+    fn from_str(src : &str) -> Result<DisasmFormat, String> {
+        match src {
+            "plain" => Ok(DisasmFormat::Plain),
+            "json" => Ok(DisasmFormat::Json),
+            _ => Err(format!("'{}' is not a disassembly format, expected plain or json", src))
+        }
+    }
+}
+
+/// Disassembles `count` instructions from `memory`, starting at `start`.
+/// Each line contains the address, the raw opcode and its mnemonic
+pub fn disassemble_range(memory : &[u8], start : usize, count : usize) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for addr in (start..memory.len() - 1).step_by(2).take(count) {
+        lines.push(DisasmLine::new(addr, &memory[addr..addr + 2]).to_string());
+    }
+
+    lines
+}
+
+/// Disassembles a whole ROM as it would be loaded at 0x200
+pub fn disassemble_rom(rom : &[u8]) -> Vec<DisasmLine> {
+    rom.chunks(2)
+        .enumerate()
+        .map(|(i, bytes)| DisasmLine::new(0x200 + i * 2, bytes))
+        .collect()
+}
+
+/// Writes out a listing in the given format, the JSON one is an array with an object per line
+pub fn format_listing(lines : &[DisasmLine], format : DisasmFormat) -> String {
+    match format {
+        DisasmFormat::Plain => lines.iter().map(|line| format!("{}\n", line)).collect(),
+        DisasmFormat::Json => {
+            let objects : Vec<String> = lines.iter().map(|line| format!("  {}", line.to_json())).collect();
+            format!("[\n{}\n]\n", objects.join(",\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_mnemonics_and_operands() {
+        let line = DisasmLine::new(0x200, &[0xd0, 0x15]);
+
+        assert_eq!(line.mnemonic, "DRW");
+        assert_eq!(line.operands, vec!["V0", "V1", "5"]);
+        assert!(! line.is_data);
+        assert_eq!(line.to_string(), "0x0200: D015  DRW V0, V1, 5");
+    }
+
+    #[test]
+    fn json_listing_keeps_its_schema() {
+        // CLS, then a sprite row and a lone trailing byte, both data
+        let lines = disassemble_rom(&[0x00, 0xe0, 0xf0, 0x90, 0x90]);
+
+        assert_eq!(format_listing(&lines, DisasmFormat::Json), concat!(
+            "[\n",
+            "  {\"address\": 512, \"bytes\": [0, 224], \"mnemonic\": \"CLS\", \"operands\": [], \"is_data\": false},\n",
+            "  {\"address\": 514, \"bytes\": [240, 144], \"mnemonic\": \"DB\", \"operands\": [\"0xf090\"], \"is_data\": true},\n",
+            "  {\"address\": 516, \"bytes\": [144], \"mnemonic\": \"DB\", \"operands\": [\"0x90\"], \"is_data\": true}\n",
+            "]\n",
+        ));
+    }
+}
@@ -0,0 +1,317 @@
+use std::{thread, time};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use crate::chip8::cpu::Cpu;
+use crate::chip8::sound::Sound;
+use crate::chip8::terminal::TerminalInput;
+use crate::chip8::timer::{self, InlineTimer, Timer, TIMER_PERIOD};
+use crate::config;
+
+use log::info;
+
+// https://jackson-s.me/2019/07/13/Chip-8-Instruction-Scheduling-and-Frequency.html
+// we run the main loop at 550hz (~1.82ms), and the timers at 60Hz
+const DEFAULT_FREQ_PERIOD : u64 = 1820000;
+
+// The frequency period can be brought down to 0, so every cycle counts as at least this long
+// when splitting the run in frames
+const MIN_CYCLE_TIME : time::Duration = time::Duration::from_nanos(1000);
+
+/// Owns the cpu along with the timers and the sound, and runs the main loop.
+/// Both the binary and anything embedding the VM drive it through here
+pub struct Emulator<'a> {
+    cpu : Cpu<'a>,
+    sound_subsystem : Sound,
+    pause : Arc<Mutex<bool>>, // shared pause flag, toggled by the keypad subsystem
+    freq_period : Rc<RefCell<u64>>, // nanoseconds between cycles, changed by the keypad subsystem
+    must_beep : Arc<Mutex<bool>>, // set by the timer thread
+    inline_timer : Option<InlineTimer>, // used instead of the timer thread with --inline-timers
+    // Neither of these is used with --deterministic-timers, the cpu ticks the timers itself
+    timer_thread : Option<(Sender<()>, JoinHandle<()>)>,
+    terminal_input : Option<TerminalInput>, // where the keys come from with --terminal
+    // With --frame-timing the cycles of a frame run in a burst, and the loop ticks the timers
+    // itself, unless the cpu already does with --deterministic-timers
+    frame_timing : bool,
+    frame_timers : Option<Arc<Mutex<(u8, u8)>>>,
+    frame_budget : time::Duration, // time of the frames so far that no cycle has used up yet
+    wants_to_quit : bool,
+    // Limits for unattended runs, reaching either of them stops the emulator
+    max_cycles : Option<u64>,
+    max_duration : Option<time::Duration>,
+    started : time::Instant,
+}
+
+impl<'a> Emulator<'a> {
+    /// Fails if the ROM can't be loaded, with a message for the user
+    pub fn new(sdl_context : &'a sdl2::Sdl, config : &'a config::Config, ttf_context : sdl2::ttf::Sdl2TtfContext) -> Result<Emulator<'a>, String> {
+        let timers = Arc::new(Mutex::new((0, 0)));
+        let pause = Arc::new(Mutex::new(config.step())); // --step starts paused, N steps and Space resumes
+        let freq_period = Rc::new(RefCell::new(initial_freq_period(config)));
+
+        let cpu = Cpu::new(sdl_context, config, Arc::clone(&timers), Arc::clone(&pause), Rc::clone(&freq_period), ttf_context)?;
+
+        let sound_subsystem = if config.no_audio() {
+            Sound::disabled()
+        } else {
+            Sound::new(sdl_context, ! config.mute(), config)
+        };
+
+        Ok(Emulator::with_cpu(cpu, config, sound_subsystem, timers, pause, freq_period, config.inline_timers()))
+    }
+
+    /// An emulator without any window, keyboard or sound. The timers are always updated
+    /// from the loop, so no thread is left running when it's used for a quick check
+    pub fn new_headless(config : &'a config::Config) -> Result<Emulator<'a>, String> {
+        let timers = Arc::new(Mutex::new((0, 0)));
+        let pause = Arc::new(Mutex::new(false)); // there's no keyboard to resume with when headless
+        let freq_period = Rc::new(RefCell::new(initial_freq_period(config)));
+
+        let cpu = Cpu::new_headless(config, Arc::clone(&timers), Arc::clone(&pause), Rc::clone(&freq_period))?;
+
+        Ok(Emulator::with_cpu(cpu, config, Sound::disabled(), timers, pause, freq_period, true))
+    }
+
+    /// An emulator that runs in the terminal, printing the screen and reading the keys from stdin.
+    /// There's no sound, and none of the special keys but quitting with Escape
+    pub fn new_terminal(config : &'a config::Config) -> Result<Emulator<'a>, String> {
+        let timers = Arc::new(Mutex::new((0, 0)));
+        let pause = Arc::new(Mutex::new(false));
+        let freq_period = Rc::new(RefCell::new(initial_freq_period(config)));
+
+        let cpu = Cpu::new_terminal(config, Arc::clone(&timers), Arc::clone(&pause), Rc::clone(&freq_period))?;
+
+        let mut emulator = Emulator::with_cpu(cpu, config, Sound::disabled(), timers, pause, freq_period, config.inline_timers());
+        emulator.terminal_input = Some(TerminalInput::new(config.keymap()));
+
+        Ok(emulator)
+    }
+
+    fn with_cpu(cpu : Cpu<'a>, config : &config::Config, sound_subsystem : Sound, timers : Arc<Mutex<(u8, u8)>>, pause : Arc<Mutex<bool>>, freq_period : Rc<RefCell<u64>>, inline_timers : bool) -> Emulator<'a> {
+        // Timer loop and beep flag, either in a separate thread or updated from the main loop
+        let must_beep = Arc::new(Mutex::new(false));
+        let mut inline_timer = None;
+        let mut timer_thread = None;
+
+        if config.deterministic_timers() || config.frame_timing() {
+            // Nothing to do, the cpu or the frame loop tick the timers as they run
+        } else if inline_timers {
+            inline_timer = Some(InlineTimer::new(Arc::clone(&timers), Arc::clone(&pause)));
+        } else {
+            let (tx, rx) = mpsc::channel();
+
+            let timers_inner = Arc::clone(&timers);
+            let must_beep_inner = Arc::clone(&must_beep);
+            let pause_inner = Arc::clone(&pause);
+            let handler = thread::spawn(move || {
+                let mut timer_subsystem = Timer::new(timers_inner, rx, must_beep_inner, pause_inner);
+                timer_subsystem.run();
+            });
+
+            timer_thread = Some((tx, handler));
+        }
+
+        Emulator {
+            cpu,
+            sound_subsystem,
+            pause,
+            freq_period,
+            must_beep,
+            inline_timer,
+            timer_thread,
+            terminal_input : None,
+            frame_timing : config.frame_timing(),
+            frame_timers : if config.deterministic_timers() { None } else { Some(timers) },
+            frame_budget : time::Duration::from_secs(0),
+            wants_to_quit : false,
+            max_cycles : config.max_cycles(),
+            max_duration : config.max_seconds().map(time::Duration::from_secs),
+            started : time::Instant::now(),
+        }
+    }
+
+    /// Runs until the user quits
+    pub fn run(&mut self) {
+        while self.run_one_frame() {}
+    }
+
+    /// Runs as many cycles as fit in a 60hz frame at the current speed (or until the user quits).
+    /// Returns false once the user wants to quit
+    pub fn run_one_frame(&mut self) -> bool {
+        self.cpu.frame_started();
+
+        if self.frame_timing {
+            return self.run_batched_frame();
+        }
+
+        let mut elapsed = time::Duration::from_secs(0);
+
+        while elapsed < TIMER_PERIOD {
+            if self.finished() {
+                return false;
+            }
+
+            elapsed += self.step().max(MIN_CYCLE_TIME);
+        }
+
+        self.cpu.render();
+        ! self.finished()
+    }
+
+    pub fn cpu(&self) -> &Cpu<'a> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu<'a> {
+        &mut self.cpu
+    }
+
+    pub fn finished(&self) -> bool {
+        self.cpu.finished() || self.wants_to_quit
+    }
+
+    /// True once --max-cycles or --max-seconds have been reached
+    pub fn limit_reached(&self) -> bool {
+        let too_many_cycles = self.max_cycles.is_some_and(|max| self.cpu.cycles() >= max);
+        let too_long = self.max_duration.is_some_and(|max| self.started.elapsed() >= max);
+
+        too_many_cycles || too_long
+    }
+
+    /// How many cycles have been executed, not counting the ones spent paused
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles()
+    }
+
+    /// Polls the keyboard, runs a cycle, updates the beep and waits for the next one.
+    /// Returns the time the cycle takes at the current speed
+    fn step(&mut self) -> time::Duration {
+        self.poll_input();
+
+        let paused = * self.pause.lock().unwrap(); // only the keypad changes it, which was just polled
+        self.cpu.cycle();
+        self.wants_to_quit |= self.limit_reached();
+
+        self.update_sound(paused);
+
+        // Nothing runs while paused, but the keys are still polled at 60hz, and the
+        // window is rendered after every frame so it can be inspected
+        if paused {
+            thread::sleep(TIMER_PERIOD);
+
+            return TIMER_PERIOD;
+        }
+
+        let period = self.cycle_period();
+        if ! self.cpu.turbo() {
+            thread::sleep(period);
+        }
+
+        period
+    }
+
+    /// With --frame-timing: polls the keyboard once, runs the cycles that fit in a 60hz frame
+    /// in a burst, ticks the timers and sleeps until the frame is over
+    fn run_batched_frame(&mut self) -> bool {
+        let frame_start = time::Instant::now();
+        self.poll_input();
+
+        let paused = * self.pause.lock().unwrap();
+        if paused {
+            self.cpu.cycle(); // still steps and redraws the pause overlay
+            self.update_sound(true);
+            self.cpu.render();
+            thread::sleep(TIMER_PERIOD);
+
+            return ! self.finished();
+        }
+
+        // The cycles that don't fit in this frame are left for the next ones, so slow speeds still work
+        let period = self.cycle_period().max(MIN_CYCLE_TIME);
+        self.frame_budget += TIMER_PERIOD;
+        while self.frame_budget >= period && ! self.finished() {
+            self.frame_budget -= period;
+
+            self.cpu.cycle();
+            self.wants_to_quit |= self.limit_reached();
+        }
+
+        if let Some(timers) = self.frame_timers.as_ref() {
+            timer::tick(&mut timers.lock().unwrap());
+        }
+        self.update_sound(false);
+        self.cpu.render();
+
+        if ! self.cpu.turbo() {
+            thread::sleep(TIMER_PERIOD.saturating_sub(frame_start.elapsed()));
+        }
+
+        ! self.finished()
+    }
+
+    /// Polls the keyboard, and the terminal with --terminal
+    fn poll_input(&mut self) {
+        self.wants_to_quit = self.cpu.poll_keypad();
+        if let Some(input) = self.terminal_input.as_mut() {
+            self.wants_to_quit |= input.poll(&mut self.cpu);
+        }
+    }
+
+    /// How long a cycle takes at the current frequency and speed
+    fn cycle_period(&self) -> time::Duration {
+        time::Duration::from_nanos(*self.freq_period.borrow()) * self.cpu.slow_motion_factor()
+    }
+
+    /// Applies what the keys asked of the sound, and beeps while the sound timer runs
+    fn update_sound(&mut self, paused : bool) {
+        if self.cpu.mute_requested() {
+            let enabled = ! self.sound_subsystem.enabled();
+            self.sound_subsystem.set_enabled(enabled);
+            info!("Sound {}", if enabled { "on" } else { "muted" });
+        }
+
+        let volume_change = self.cpu.volume_change();
+        if volume_change != 0 {
+            let volume = self.sound_subsystem.change_volume(volume_change);
+            self.cpu.show_message(&format!("Volume: {:.0}%", volume * 100.0));
+        }
+
+        if self.cpu.audio_pattern_cleared() {
+            self.sound_subsystem.clear_pattern();
+        }
+
+        if let Some(pattern) = self.cpu.audio_pattern_changed() {
+            self.sound_subsystem.set_pattern(pattern);
+        }
+
+        let beeping = match (self.inline_timer.as_mut(), self.timer_thread.is_some()) {
+            (Some(timer), _) => timer.update(),
+            (None, true) => * self.must_beep.lock().unwrap(),
+            (None, false) => self.cpu.is_beeping() // deterministic timers or --frame-timing
+        };
+
+        // Don't keep beeping while paused, even if the sound timer hasn't reached 0
+        if beeping && ! paused {
+            self.sound_subsystem.beep();
+        } else {
+            self.sound_subsystem.stop_beep();
+        }
+    }
+}
+
+/// Nanoseconds between cycles to begin with, from --freq or the default ~550hz
+fn initial_freq_period(config : &config::Config) -> u64 {
+    config.freq().map_or(DEFAULT_FREQ_PERIOD, |freq| 1_000_000_000 / freq)
+}
+
+impl Drop for Emulator<'_> {
+    fn drop(&mut self) {
+        if let Some((tx, handler)) = self.timer_thread.take() {
+            let _ = tx.send(()); // Tell the timer subsystem to stop
+            handler.join().unwrap();
+        }
+    }
+}
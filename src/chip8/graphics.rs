@@ -1,17 +1,58 @@
 use sdl2;
-use sdl2::pixels;
 use sdl2::rect::Rect;
+use sdl2::rwops::RWops;
 use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::render::TextureQuery;
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use sdl2::render::{Texture, TextureCreator, TextureQuery};
+use sdl2::render::BlendMode;
 use sdl2::pixels::Color;
 
+use std::time::{Duration, Instant};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+
 use crate::config;
+use crate::theme::Theme;
+use crate::chip8::keypad::speed_multiplier;
+use crate::chip8::quirks::Quirks;
+use crate::chip8::renderer::{CpuState, FrameHook, Renderer};
+use crate::chip8::view::ViewOptions;
+use crate::chip8::screen::{Framebuffer, Screen, LORES_HEIGHT, LORES_WIDTH};
+use crate::chip8::screenshot;
 // Pretty much based on https://github.com/starrhorne/chip8-rust/blob/master/src/drivers/display_driver.rs,
 // modified to bring the screen matrix here, and also draw information about the CPU state
 
-// Since the chip8 screen is 64x32, we scale it
-const SCALE_FACTOR: u32 = 15;
+const WINDOW_TITLE: &str = "CHIP-8 VM";
+
+// The panel font when --font_path isn't given (or can't be loaded), built into the binary so
+// there's no file to ship along with it. It's Terminus, see OFL_terminus_font.txt for its license
+const BUILTIN_FONT : &[u8] = include_bytes!("../../font.ttf");
+
+// The debug panel text is wrapped at this width when rendered at the default 128pt,
+// other font sizes scale it accordingly so the panels keep the same layout
+const PANEL_WRAP_WIDTH: u32 = 1200;
+const DEFAULT_PANEL_FONT_SIZE: u32 = 128;
+
+// Height of the line with the values that change every frame (timers, speed, IPS and cycles since input), in lores pixels
+const COUNTERS_LINE_HEIGHT: u32 = 2;
+
+// Size of each pixel of the sprite preview, and its distance to the corner of the window
+const SPRITE_PREVIEW_SCALE: u32 = 5;
+const SPRITE_PREVIEW_MARGIN: u32 = 15;
+
+// How long messages stay over the game area while fading out, their height and distance to the corner
+const MESSAGE_DURATION: Duration = Duration::from_secs(1);
+const MESSAGE_HEIGHT: u32 = 40;
+const MESSAGE_MARGIN: u32 = 10;
+
+// Height of each line of the quirk list shown while paused, in lores pixels
+const QUIRKS_LINE_HEIGHT: u32 = 2;
+
+// How long it takes the rainbow mode to go through all hues
+const RAINBOW_PERIOD_SECS: f32 = 10.0;
 
 // handle the annoying Rect i32
 // https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/ttf-demo.rs
@@ -21,176 +62,336 @@ macro_rules! rect(
     )
 );
 
+/// A rendered debug panel, kept until its text changes
+struct Panel<T> {
+    text : String,
+    texture : T,
+}
+
+type PanelTexture = Panel<Texture<'static>>;
+
 pub struct Graphics<'a> {
-    screen : [[u8; 64]; 32], // graphics matrix
+    screen : Screen, // graphics matrix, double-buffered
     canvas: Canvas<Window>,
-    ttf_context : sdl2::ttf::Sdl2TtfContext,
+    scale : u32, // size of a lores pixel on the window
+    theme : Theme, // with the color overrides already applied
+    font : sdl2::ttf::Font<'static, 'static>, // loaded once, the panels are rendered with it on every draw
     config : &'a config::Config,
-    texture_creator : sdl2::render::TextureCreator<sdl2::video::WindowContext>,
+    texture_creator : &'static TextureCreator<WindowContext>,
+    // The debug panels as last rendered, along with the text they show
+    registers_panel : Option<PanelTexture>,
+    counters_panel : Option<PanelTexture>,
+    stack_panel : Option<PanelTexture>,
+    instr_panel : Option<PanelTexture>,
+    quirks_overlay : Option<PanelTexture>,
+    frame_count : u32, // presents since fps_timer was last reset
+    fps_timer : Instant,
+    view : Rc<RefCell<ViewOptions>>, // shared display options, toggled by the keypad subsystem
+    panel_shown : bool, // whether the window is currently wide enough for the debug panel
+    fullscreen : bool, // whether the window is currently fullscreen
+    quirks : Rc<RefCell<Quirks>>,
+    pause : Arc<Mutex<bool>>, // the game area is dimmed while paused
+    slow_motion : Rc<RefCell<u32>>, // shown in the panel as a speed multiplier
+    start_time : Instant, // time source for the rainbow mode
+    frame_hook : Option<FrameHook>,
+    message : Option<(PanelTexture, Instant)>, // shown over the game until MESSAGE_DURATION has passed
 }
 
 impl Graphics<'_> {
-    pub fn new<'a>(sdl_context : &'a sdl2::Sdl, config : &'a config::Config, ttf_context : sdl2::ttf::Sdl2TtfContext) -> Graphics<'a> {
+    pub fn new<'a>(sdl_context : &'a sdl2::Sdl, config : &'a config::Config, ttf_context : sdl2::ttf::Sdl2TtfContext, view : Rc<RefCell<ViewOptions>>, quirks : Rc<RefCell<Quirks>>, pause : Arc<Mutex<bool>>, slow_motion : Rc<RefCell<u32>>) -> Graphics<'a> {
         // Initialization
         let video_subsys = sdl_context.video().unwrap();
-        let window = video_subsys
-            // only widths up to 63 * SCALE_FACTOR are used by the game itself, the rest are for the VM to draw information on
-            .window("CHIP-8 VM", 128 * SCALE_FACTOR, 32 * SCALE_FACTOR) 
-            .position_centered()
+        // Since the chip8 screen is 64x32, we scale it by --scale. The SUPER-CHIP 128x64 mode takes the same space
+        // only widths up to 63 * scale are used by the game itself, the rest are for the VM to draw information on
+        let scale = config.scale();
+        let panel_shown = view.borrow().debug_panel;
+        let window_width = window_width(panel_shown, scale);
+        let mut window_builder = video_subsys.window(WINDOW_TITLE, window_width, 32 * scale);
+
+        match config.window_pos() {
+            Some((x, y)) => window_builder.position(x, y),
+            None => window_builder.position_centered()
+        };
+
+        let window = window_builder
             .opengl()
             .build()
             .unwrap();
 
+        let theme = config.theme();
+
         let mut canvas = window.into_canvas().build().unwrap();
-        canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
+        // Everything is drawn at the window size, and SDL scales it to fit in fullscreen
+        // keeping the proportions and centering it
+        canvas.set_logical_size(window_width, 32 * scale).unwrap();
+        canvas.set_draw_color(theme.panel_background());
         canvas.clear();
         canvas.present();
 
-        let texture_creator = canvas.texture_creator();
+        // The font borrows the ttf context and textures borrow their creator, and all of them would
+        // have to live in this struct. There's only ever one window, so the context and the
+        // creator are simply leaked to keep them around for good
+        let texture_creator : &'static TextureCreator<WindowContext> = Box::leak(Box::new(canvas.texture_creator()));
+        let ttf_context : &'static sdl2::ttf::Sdl2TtfContext = Box::leak(Box::new(ttf_context));
+        let mut font = load_font(ttf_context, config.font_path(), config.panel_font_size());
+        font.set_style(sdl2::ttf::FontStyle::BOLD);
+
+        let mut graphics = Graphics {
+            screen : Screen::new(),
+            canvas,
+            scale,
+            theme,
+            font,
+            config,
+            texture_creator,
+            registers_panel : None,
+            counters_panel : None,
+            stack_panel : None,
+            instr_panel : None,
+            quirks_overlay : None,
+            frame_count : 0,
+            fps_timer : Instant::now(),
+            view,
+            panel_shown,
+            fullscreen : false,
+            quirks,
+            pause,
+            slow_motion,
+            start_time : Instant::now(),
+            frame_hook : None,
+            message : None,
+        };
 
-        Graphics {
-            screen : [[0; 64]; 32],
-            canvas: canvas,
-            ttf_context : ttf_context,
-            config : config,
-            texture_creator : texture_creator,
+        if config.fullscreen() {
+            graphics.toggle_fullscreen();
         }
+
+        graphics
     }
 
-    pub fn clear_screen(&mut self) {
-        for row in self.screen.iter_mut() {
-            for col in row.iter_mut() {
-                *col = 0;
-            }
+    /// Switches between a window and (desktop) fullscreen. If SDL can't, the window stays as it was
+    /// and the shared view options are put back to match it
+    pub fn toggle_fullscreen(&mut self) {
+        let fullscreen = ! self.fullscreen;
+        let mode = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+
+        match self.canvas.window_mut().set_fullscreen(mode) {
+            Ok(()) => self.fullscreen = fullscreen,
+            Err(e) => warn!("Couldn't {} fullscreen: {}", if fullscreen { "enter" } else { "leave" }, e),
         }
+
+        self.view.borrow_mut().fullscreen = self.fullscreen;
     }
 
-    /// If the coordinates are correct, XORs the value at (x,y).
-    /// Returns 1 if the screen pixel has changed from set to unset, otherwise 0
-    pub fn set_pos(&mut self, x : usize, y : usize, val : u8) -> u8 {
-        let mut changed = 0;
-        
-        if ! self.config.wrapping_enabled() {
-            if (0..64).contains(&x) && (0..32).contains(&y) {
-                changed = self.screen[y][x]; // y is indexed first, it's a 2d array!
-                // The value is XOR'd into the screen
-                self.screen[y][x] ^= val; 
-
-                // And the changed flag is activated if the pixel is    
-                // unset, which only happens if both values were 1 due to
-                // the XOR operation
-                changed &= val;
-            }
-        } else { // We mod the coordinates to the maximum values and thus wrap them
-            changed = self.screen[y % 32][x % 64]; // y is indexed first, it's a 2d array!
-            // The value is XOR'd into the screen
-            self.screen[y % 32][x % 64] ^= val; 
-
-            // And the changed flag is activated if the pixel is    
-            // unset, which only happens if both values were 1 due to
-            // the XOR operation
-            changed &= val;
+    /// Draws the debug information and the screen matrix, and presents them
+    fn render(&mut self, state : &CpuState) {
+        let font_size = self.config.panel_font_size();
+        let wrap_width = PANEL_WRAP_WIDTH * font_size as u32 / DEFAULT_PANEL_FONT_SIZE;
+
+        // The window is resized when the panel is toggled, it only fits the game without it
+        let show_panel = self.view.borrow().debug_panel;
+        if show_panel != self.panel_shown {
+            let (width, height) = (window_width(show_panel, self.scale), 32 * self.scale);
+            let _ = self.canvas.window_mut().set_size(width, height);
+            let _ = self.canvas.set_logical_size(width, height);
+            self.panel_shown = show_panel;
         }
 
-        
+        if self.view.borrow().fullscreen != self.fullscreen {
+            self.toggle_fullscreen();
+        }
 
-        changed
-    }
+        self.canvas.set_draw_color(self.theme.panel_background());
+        self.canvas.clear();
 
-    pub fn draw(&mut self, v : &[u8; 16], stack : &Vec<usize>, instr_log : &Vec<u16>) {
-        // Load the font
-        let mut font = self.ttf_context.load_font(self.config.font_path(), 128).unwrap();
-        font.set_style(sdl2::ttf::FontStyle::BOLD);
+        // The panel is left out entirely when disabled
+        if show_panel {
+            // CPU registers, and the counters that change every frame on a line of their own
+            let registers = registers_text(state);
+            let counters = counters_text(state, speed_multiplier(*self.slow_motion.borrow()));
 
-        self.canvas.clear();
+            // Stack
+            let mut stack_arr : [usize; 12] = [0; 12]; // The default/original stack size was 12
+            for (i, elem) in state.stack.iter().rev().enumerate() {
+                stack_arr[i] = *elem;
+            }
 
-        // CPU registers
-        let surface = font
-            .render(&format!("Register contents:    \
-                                v0:   {:#06x}   v1:   {:#06x}   \
-                                v2:   {:#06x}   v3:   {:#06x}   \
-                                v4:   {:#06x}   v5:   {:#06x}   \
-                                v6:   {:#06x}   v7:   {:#06x}   \
-                                v8:   {:#06x}   v9:   {:#06x}   \
-                                v10:   {:#06x}   v11:   {:#06x}   \
-                                v12:   {:#06x}   v13:   {:#06x}   \
-                                v14:   {:#06x}   v15:   {:#06x}   ", 
-                                v[0], v[1], v[2], v[3], v[4], v[5], 
-                                v[6], v[7], v[8], v[9], v[10], v[11], 
-                                v[12], v[13], v[14], v[15]))
-            .blended_wrapped(Color::RGBA(194, 57, 56, 0), 1200)
-            .map_err(|e| e.to_string()).unwrap();
-        
-        let texture_cpu = self.texture_creator.create_texture_from_surface(&surface).unwrap();
-        let rect_cpu = self.get_rect_cpu_registers(&texture_cpu);
-
-        // Stack
-        let mut stack_arr : [usize; 12] = [0; 12]; // The default/original stack size was 12
-        let mut i = 0;
-        for elem in stack.iter().rev() {
-            stack_arr[i] = *elem;
-            i += 1;
+            let stack = format!("Stack:    {:#06x}    {:#06x}    {:#06x}    \
+                                    {:#06x}    {:#06x}    {:#06x}    {:#06x}    \
+                                    {:#06x}    {:#06x}    {:#06x}    {:#06x}    {:#06x}", 
+                                    stack_arr[0], stack_arr[1], stack_arr[2], stack_arr[3], 
+                                    stack_arr[4], stack_arr[5], stack_arr[6], stack_arr[7], 
+                                    stack_arr[8], stack_arr[9], stack_arr[10], stack_arr[11]);
+
+            // Instructions
+            let mut instr_log_arr : [u16; 12] = [0;12];
+            for (i, instr) in state.instr_log.iter().enumerate() {
+                instr_log_arr[i] = *instr;
+            }
+    
+            let instructions = format!("Instruction history:    {:#06x}    {:#06x}    {:#06x}    \
+                                    {:#06x}    {:#06x}    {:#06x}    {:#06x}    {:#06x}    \
+                                    {:#06x}    {:#06x}    {:#06x}    {:#06x}", 
+                                    instr_log_arr[0], instr_log_arr[1], instr_log_arr[2], 
+                                    instr_log_arr[3], instr_log_arr[4], instr_log_arr[5], 
+                                    instr_log_arr[6], instr_log_arr[7], instr_log_arr[8], 
+                                    instr_log_arr[9], instr_log_arr[10], instr_log_arr[11]);
+
+            // Rendering text is slow, so the textures are only regenerated when their text changes
+            let (font, texture_creator, color) = (&self.font, self.texture_creator, Color::from(self.theme.panel_text()));
+            let render = |text : &str| render_text(text, font, texture_creator, color, wrap_width);
+            update_panel(&mut self.registers_panel, registers, render);
+            update_panel(&mut self.counters_panel, counters, render);
+            update_panel(&mut self.stack_panel, stack, render);
+            update_panel(&mut self.instr_panel, instructions, render);
+
+            let texture_cpu = panel_texture(&self.registers_panel);
+            let texture_counters = panel_texture(&self.counters_panel);
+            let texture_stack = panel_texture(&self.stack_panel);
+            let texture_instr = panel_texture(&self.instr_panel);
+
+            let rect_cpu = self.get_rect_cpu_registers(texture_cpu);
+            let rect_counters = self.get_rect_counters(texture_counters);
+            let rect_stack = self.get_rect_stack(texture_stack);
+            let rect_instr = self.get_rect_instr(texture_instr);
+
+            self.canvas.copy(texture_cpu, None, Some(rect_cpu)).unwrap();
+            self.canvas.copy(texture_counters, None, Some(rect_counters)).unwrap();
+            self.canvas.copy(texture_stack, None, Some(rect_stack)).unwrap();
+            self.canvas.copy(texture_instr, None, Some(rect_instr)).unwrap();
         }
 
-        let surface = font
-            .render(&format!("Stack:    {:#06x}    {:#06x}    {:#06x}    \
-                                {:#06x}    {:#06x}    {:#06x}    {:#06x}    \
-                                {:#06x}    {:#06x}    {:#06x}    {:#06x}    {:#06x}", 
-                                stack_arr[0], stack_arr[1], stack_arr[2], stack_arr[3], 
-                                stack_arr[4], stack_arr[5], stack_arr[6], stack_arr[7], 
-                                stack_arr[8], stack_arr[9], stack_arr[10], stack_arr[11]))
-            .blended_wrapped(Color::RGBA(194, 57, 56, 0), 1200)
-            .map_err(|e| e.to_string()).unwrap();
-        
-        let texture_stack = self.texture_creator.create_texture_from_surface(&surface).unwrap();
-        let rect_stack = self.get_rect_stack(&texture_stack);
-
-        // Instructions
-        let mut instr_log_arr : [u16; 12] = [0;12];
-        let mut i = 0;
-        for instr in instr_log.iter() {
-            instr_log_arr[i] = *instr;
-            i += 1;
-        }
-    
-        let surface = font
-            .render(&format!("Instruction history:    {:#06x}    {:#06x}    {:#06x}    \
-                                {:#06x}    {:#06x}    {:#06x}    {:#06x}    {:#06x}    \
-                                {:#06x}    {:#06x}    {:#06x}    {:#06x}", 
-                                instr_log_arr[0], instr_log_arr[1], instr_log_arr[2], 
-                                instr_log_arr[3], instr_log_arr[4], instr_log_arr[5], 
-                                instr_log_arr[6], instr_log_arr[7], instr_log_arr[8], 
-                                instr_log_arr[9], instr_log_arr[10], instr_log_arr[11]))
-            .blended_wrapped(Color::RGBA(194, 57, 56, 0), 1200)
-            .map_err(|e| e.to_string()).unwrap();
-        
-        let texture_instr = self.texture_creator.create_texture_from_surface(&surface).unwrap();
-        let rect_instr = self.get_rect_instr(&texture_instr);
-
-        self.canvas.copy(&texture_cpu, None, Some(rect_cpu)).unwrap();
-        self.canvas.copy(&texture_stack, None, Some(rect_stack)).unwrap();
-        self.canvas.copy(&texture_instr, None, Some(rect_instr)).unwrap();
-
-        for (y, row) in self.screen.iter().enumerate() { // Iterate through each row
-            for (x, &col_value) in row.iter().enumerate() { // Iterator through each column
-                // Scale the coords
-                let x = (x as u32) * SCALE_FACTOR;
-                let y = (y as u32) * SCALE_FACTOR;
+        let view = *self.view.borrow();
+        let foreground_color = self.foreground_color();
+        let background_color = Color::from(self.theme.background());
+        let palette = self.palette();
+
+        let (width, height) = self.screen.resolution();
+
+        for (y, row) in self.screen.displayed().iter().take(height).enumerate() { // Iterate through each row
+            for (x, &col_value) in row[..width].iter().enumerate() { // Iterator through each column
+                // Scale the coords, the game area is the same size in both resolutions
+                let (x, w) = scale_pixel(x, width, LORES_WIDTH, self.scale);
+                let (y, h) = scale_pixel(y, height, LORES_HEIGHT, self.scale);
                 
-                // if it has a non-zero value, the pixel is active
-                if col_value == 0 {
-                    self.canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
-                } else {    
-                    self.canvas.set_draw_color(pixels::Color::RGB(198, 43, 248)); // I like purple
-                }
+                // The value has a bit per plane, 0 is the background and 1 the foreground
+                self.canvas.set_draw_color(palette[col_value as usize & 3]);
                 
                 // Draws the pixel as a rectangle
-                self.canvas.fill_rect(Rect::new(x as i32, y as i32, SCALE_FACTOR, SCALE_FACTOR)).unwrap();
+                self.canvas.fill_rect(rect!(x, y, w, h)).unwrap();
             }
         }
+
+        if view.sprite_preview {
+            let window_width = self.canvas.logical_size().0;
+            render_sprite_preview(&mut self.canvas, window_width, self.scale, &state.sprite, foreground_color, background_color);
+        }
+
+        // Darken the game area while paused, so it's obvious that the VM is stopped
+        let pause_dim = self.config.pause_dim();
+        if pause_dim > 0 && *self.pause.lock().unwrap() {
+            self.canvas.set_blend_mode(BlendMode::Blend);
+            self.canvas.set_draw_color(Color::RGBA(0, 0, 0, pause_dim));
+            self.canvas.fill_rect(rect!(0, 0, 64 * self.scale, 32 * self.scale)).unwrap();
+            self.canvas.set_blend_mode(BlendMode::None);
+        }
+
+        // The quirks are listed over the game while paused, so it's clear what toggling one changed
+        if *self.pause.lock().unwrap() {
+            self.render_quirks_overlay(wrap_width);
+        }
+
+        // The message fades out, and is dropped once it's fully transparent
+        if let Some((message, shown_at)) = self.message.as_mut() {
+            let elapsed = shown_at.elapsed();
+
+            if elapsed < MESSAGE_DURATION {
+                let alpha = 255.0 * (1.0 - elapsed.as_secs_f32() / MESSAGE_DURATION.as_secs_f32());
+                message.texture.set_alpha_mod(alpha as u8);
+
+                // The font is rendered at the panel size, scale it to a fixed height
+                let TextureQuery { width, height, .. } = message.texture.query();
+                let width = width * MESSAGE_HEIGHT / height.max(1);
+                self.canvas.copy(&message.texture, None, Some(rect!(MESSAGE_MARGIN, MESSAGE_MARGIN, width, MESSAGE_HEIGHT))).unwrap();
+            } else {
+                self.message = None;
+            }
+        }
+
         self.canvas.present();
     }
 
+    /// Draws the state of every quirk on the game area, below where the messages go
+    fn render_quirks_overlay(&mut self, wrap_width : u32) {
+        let states = self.quirks.borrow().states();
+        let lines : Vec<String> = states.iter().map(|(name, state)| format!("{}: {}", name, state)).collect();
+        let text = format!("Quirks\n{}", lines.join("\n"));
+        let (font, texture_creator, color) = (&self.font, self.texture_creator, Color::from(self.theme.panel_text()));
+        update_panel(&mut self.quirks_overlay, text, |text| render_text(text, font, texture_creator, color, wrap_width));
+
+        let texture = panel_texture(&self.quirks_overlay);
+        let TextureQuery { width, height, .. } = texture.query();
+
+        // Scaled to a fixed line height, or smaller if it doesn't fit in the game area
+        let top = 2 * MESSAGE_MARGIN + MESSAGE_HEIGHT;
+        let (max_width, max_height) = (64 * self.scale - MESSAGE_MARGIN, (32 * self.scale).saturating_sub(top));
+        let mut target_height = (QUIRKS_LINE_HEIGHT * self.scale * (states.len() as u32 + 1)).min(max_height);
+        let mut target_width = width * target_height / height.max(1);
+        if target_width > max_width {
+            target_height = target_height * max_width / target_width;
+            target_width = max_width;
+        }
+
+        self.canvas.copy(texture, None, Some(rect!(MESSAGE_MARGIN, top, target_width, target_height))).unwrap();
+    }
+
+    /// Returns the color active pixels are drawn with, which rotates through all hues in rainbow mode
+    fn foreground_color(&self) -> Color {
+        if ! self.config.rainbow() {
+            return Color::from(self.theme.foreground());
+        }
+
+        let elapsed = self.start_time.elapsed().as_secs_f32();
+        let hue = (elapsed % RAINBOW_PERIOD_SECS) / RAINBOW_PERIOD_SECS * 360.0;
+
+        hsv_to_rgb(hue, 0.8, 1.0)
+    }
+
+    /// The color of each pixel value: the background and foreground (which might be cycling through
+    /// the rainbow) for the first bit-plane, and the rest of the theme palette for XO-CHIP games
+    /// that use both. Inverting swaps the background with the foreground, and the other two
+    fn palette(&self) -> [Color; 4] {
+        let mut palette = self.theme.palette().map(Color::from);
+        palette[0] = Color::from(self.theme.background());
+        palette[1] = self.foreground_color();
+
+        if self.view.borrow().invert {
+            palette.swap(0, 1);
+            palette.swap(2, 3);
+        }
+
+        palette
+    }
+
+    /// Counts a presented frame, and once per second shows the measured rate in the window title.
+    /// Frames are only presented when the game draws something, so this is what the game
+    /// actually renders rather than a fixed refresh rate
+    fn update_fps(&mut self) {
+        self.frame_count += 1;
+
+        let elapsed = self.fps_timer.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let fps = self.frame_count as f64 / elapsed.as_secs_f64();
+            let title = format!("{} - {:.1} FPS", WINDOW_TITLE, fps);
+            
+            // Failing to set the title isn't worth crashing over
+            let _ = self.canvas.window_mut().set_title(&title);
+
+            self.frame_count = 0;
+            self.fps_timer = Instant::now();
+        }
+    }
+
     // All functions below are based on the SDL2 ttf demo at https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/ttf-demo.rs
 
     fn get_rect_cpu_registers(&self, texture : &sdl2::render::Texture) -> Rect {
@@ -200,11 +401,25 @@ impl Graphics<'_> {
         self.get_rect_aligned_left(
             width,
             height,
-            (128 - padding) * SCALE_FACTOR,
-            (32 - padding) * SCALE_FACTOR,
+            (128 - padding) * self.scale,
+            (32 - padding - COUNTERS_LINE_HEIGHT) * self.scale,
         )
     }
 
+    /// The counters go on the bottom left of the panel, below the registers
+    fn get_rect_counters(&self, texture : &sdl2::render::Texture) -> Rect {
+        let TextureQuery { width, height, .. } = texture.query();
+        let max_width = 63 * self.scale;
+        let mut h = COUNTERS_LINE_HEIGHT * self.scale;
+        let mut w = width * h / height.max(1);
+        if w > max_width {
+            h = h * max_width / w;
+            w = max_width;
+        }
+
+        rect!(65*self.scale, 32 * self.scale - h, w, h)
+    }
+
     fn get_rect_stack(&self, texture : &sdl2::render::Texture) -> Rect {
         let TextureQuery { width, height, .. } = texture.query();
         // If the example text is too big for the screen, downscale it (and position it irregardless)
@@ -212,8 +427,8 @@ impl Graphics<'_> {
         self.get_rect_aligned_right(
             width,
             height,
-            (128 - padding) * SCALE_FACTOR,
-            (32 - padding) * SCALE_FACTOR,
+            (128 - padding) * self.scale,
+            (32 - padding) * self.scale,
         )
     }
 
@@ -224,8 +439,8 @@ impl Graphics<'_> {
         self.get_rect_aligned_center(
             width,
             height,
-            (128 - padding) * SCALE_FACTOR,
-            (32 - padding) * SCALE_FACTOR,
+            (128 - padding) * self.scale,
+            (32 - padding) * self.scale,
         )
     }
 
@@ -246,7 +461,7 @@ impl Graphics<'_> {
             (rect_width as i32, rect_height as i32)
         };
 
-        rect!(65*SCALE_FACTOR, 0, w, h)
+        rect!(65*self.scale, 0, w, h)
     }
 
     // Scale fonts to a reasonable size when they're too big (though they might look less smooth)
@@ -266,7 +481,7 @@ impl Graphics<'_> {
             (rect_width as i32, rect_height as i32)
         };
 
-        let cx = (128*SCALE_FACTOR as i32 - w) / 2 + 64 * SCALE_FACTOR as i32;
+        let cx = (128*self.scale as i32 - w) / 2 + 64 * self.scale as i32;
         rect!(cx, 0, w, h)
     }
 
@@ -287,9 +502,242 @@ impl Graphics<'_> {
             (rect_width as i32, rect_height as i32)
         };
 
-        let cx = (128*SCALE_FACTOR as i32 - w) / 2 + 32 * SCALE_FACTOR as i32;
+        let cx = (128*self.scale as i32 - w) / 2 + 32 * self.scale as i32;
 
         rect!(cx, 0, w, h)
     }
     
 }
+
+impl Renderer for Graphics<'_> {
+    fn screen(&self) -> &Framebuffer {
+        self.screen.back()
+    }
+
+    fn resolution(&self) -> (usize, usize) {
+        self.screen.resolution()
+    }
+
+    fn set_hires(&mut self, hires : bool) {
+        self.screen.set_hires(hires);
+    }
+
+    fn clear_screen(&mut self, planes : u8) {
+        self.screen.clear(planes);
+    }
+
+    fn scroll(&mut self, dx : isize, dy : isize, planes : u8) {
+        self.screen.scroll(dx, dy, planes);
+    }
+
+    fn set_pos(&mut self, x : usize, y : usize, val : u8) -> u8 {
+        let clip_mode = self.quirks.borrow().clip_mode;
+        self.screen.set_pos(x, y, val, clip_mode)
+    }
+
+    fn draw(&mut self, state : &CpuState) {
+        // Swap in the finished frame before rendering, so only complete frames are shown
+        self.screen.present();
+        self.render(state);
+
+        if let Some(hook) = self.frame_hook.as_mut() {
+            hook(self.screen.displayed());
+        }
+
+        if self.config.show_fps() {
+            self.update_fps();
+        }
+    }
+
+    fn refresh(&mut self, state : &CpuState) {
+        self.render(state);
+    }
+
+    fn restore_screen(&mut self, screen : &Framebuffer) {
+        self.screen.restore(screen);
+    }
+
+    fn show_message(&mut self, text : &str) {
+        // Rendered once, fading it out only changes its alpha. SDL_ttf can't render an empty string
+        self.message = None;
+        if text.is_empty() {
+            return;
+        }
+
+        let texture = self.font
+            .render(text)
+            .blended(self.theme.panel_text())
+            .map_err(|e| e.to_string())
+            .and_then(|surface| self.texture_creator.create_texture_from_surface(&surface).map_err(|e| e.to_string()));
+
+        match texture {
+            Ok(mut texture) => {
+                texture.set_blend_mode(BlendMode::Blend);
+                self.message = Some((Panel { text : text.to_string(), texture }, Instant::now()));
+            },
+            Err(e) => warn!("Could not render the message '{}': {}", text, e)
+        }
+    }
+
+    fn screenshot(&self) -> Vec<u8> {
+        screenshot::screenshot_png(self.screen.displayed(), self.screen.resolution(), self.scale, self.palette().map(|color| color.rgb()))
+    }
+
+    fn set_frame_hook(&mut self, hook : FrameHook) {
+        self.frame_hook = Some(hook);
+    }
+}
+
+/// Loads the font at the path if there's one, falling back to the built-in font if it can't be loaded
+fn load_font(ttf_context : &'static sdl2::ttf::Sdl2TtfContext, path : Option<&str>, size : u16) -> sdl2::ttf::Font<'static, 'static> {
+    if let Some(path) = path {
+        match ttf_context.load_font(path, size) {
+            Ok(font) => return font,
+            Err(e) => warn!("Could not load the font {}, using the built-in one: {}", path, e)
+        }
+    }
+
+    let rwops = RWops::from_bytes(BUILTIN_FONT).unwrap();
+    ttf_context.load_font_from_rwops(rwops, size).unwrap()
+}
+
+/// The registers panel text, which only changes when the program does something
+fn registers_text(state : &CpuState) -> String {
+    let v = state.v;
+
+    format!("Register contents:    \
+            v0:   {:#06x}   v1:   {:#06x}   \
+            v2:   {:#06x}   v3:   {:#06x}   \
+            v4:   {:#06x}   v5:   {:#06x}   \
+            v6:   {:#06x}   v7:   {:#06x}   \
+            v8:   {:#06x}   v9:   {:#06x}   \
+            v10:   {:#06x}   v11:   {:#06x}   \
+            v12:   {:#06x}   v13:   {:#06x}   \
+            v14:   {:#06x}   v15:   {:#06x}   \
+            I:   {:#06x}   PC:   {:#06x}", 
+            v[0], v[1], v[2], v[3], v[4], v[5], 
+            v[6], v[7], v[8], v[9], v[10], v[11], 
+            v[12], v[13], v[14], v[15], state.i, state.pc)
+}
+
+/// The timers, the speed and the counters, which change on every frame while the game runs.
+/// They're kept apart so they don't make the registers panel render again each time
+fn counters_text(state : &CpuState, speed : f64) -> String {
+    format!("Delay timer:   {}   Sound timer:   {}   Speed:   {}x   IPS:   {}   Cycles since input:   {}",
+        state.timers.0, state.timers.1, speed, state.ips, state.cycles_since_input)
+}
+
+/// Renders the text with the panel font
+fn render_text(text : &str, font : &sdl2::ttf::Font, texture_creator : &'static TextureCreator<WindowContext>, color : Color, wrap_width : u32) -> Texture<'static> {
+    let surface = font
+        .render(text)
+        .blended_wrapped(color, wrap_width)
+        .map_err(|e| e.to_string()).unwrap();
+    texture_creator.create_texture_from_surface(&surface).unwrap()
+}
+
+/// Renders the text into the panel texture, unless it already shows that same text.
+/// Returns whether it was rendered
+fn update_panel<T>(panel : &mut Option<Panel<T>>, text : String, render : impl FnOnce(&str) -> T) -> bool {
+    if panel.as_ref().is_some_and(|panel| panel.text == text) {
+        return false;
+    }
+
+    let texture = render(&text);
+    *panel = Some(Panel { text, texture });
+    true
+}
+
+/// The texture of a panel that update_panel has already rendered
+fn panel_texture(panel : &Option<PanelTexture>) -> &Texture<'static> {
+    &panel.as_ref().expect("the panel is rendered before being drawn").texture
+}
+
+/// The window always fits the 64x32 game area (scaled), and just as much again for the debug panel
+fn window_width(debug_panel : bool, scale : u32) -> u32 {
+    let width = if debug_panel { 128 } else { 64 };
+    width * scale
+}
+
+/// Returns where a pixel starts and how big it is along one axis, in window coordinates.
+/// An odd scale doesn't split evenly in high resolution, so pixels are a point bigger or smaller
+/// there to make the game area exactly as big as in the lores mode
+fn scale_pixel(pos : usize, pixels : usize, lores_pixels : usize, scale : u32) -> (u32, u32) {
+    let area = lores_pixels as u32 * scale;
+    let start = pos as u32 * area / pixels as u32;
+    let end = (pos as u32 + 1) * area / pixels as u32;
+
+    (start, end - start)
+}
+
+/// Draws the bytes starting at I as an 8 pixel wide sprite on the bottom right corner of the window,
+/// to check that sprite and font addresses point where they should.
+/// On small scales it doesn't fit there, and starts from the top left corner instead
+fn render_sprite_preview(canvas : &mut Canvas<Window>, window_width : u32, scale : u32, sprite : &[u8], foreground_color : Color, background_color : Color) {
+    let x = window_width.saturating_sub(8 * SPRITE_PREVIEW_SCALE + SPRITE_PREVIEW_MARGIN);
+    let y = (32 * scale).saturating_sub(sprite.len() as u32 * SPRITE_PREVIEW_SCALE + SPRITE_PREVIEW_MARGIN);
+
+    for (row, byte) in sprite.iter().enumerate() {
+        for col in 0..8 {
+            if (byte >> (7 - col)) & 0b00000001 == 1 {
+                canvas.set_draw_color(foreground_color);
+            } else {
+                canvas.set_draw_color(background_color);
+            }
+
+            canvas.fill_rect(rect!(
+                x + col * SPRITE_PREVIEW_SCALE,
+                y + row as u32 * SPRITE_PREVIEW_SCALE,
+                SPRITE_PREVIEW_SCALE,
+                SPRITE_PREVIEW_SCALE
+            )).unwrap();
+        }
+    }
+}
+
+/// Converts a color from HSV (hue in degrees, saturation and value between 0 and 1) to RGB
+/// https://en.wikipedia.org/wiki/HSL_and_HSV#HSV_to_RGB
+fn hsv_to_rgb(hue : f32, saturation : f32, value : f32) -> Color {
+    let chroma = value * saturation;
+    let h = hue / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    let m = value - chroma;
+    Color::RGB(((r + m) * 255.0) as u8, ((g + m) * 255.0) as u8, ((b + m) * 255.0) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::chip8::renderer::SPRITE_PREVIEW_HEIGHT;
+
+    #[test]
+    fn running_without_changes_only_renders_the_counters_again() {
+        let (v, stack, instr_log) = ([0; 16], [], VecDeque::new());
+        let state = |timer, cycles_since_input, ips| CpuState {
+            v : &v, i : 0x300, pc : 0x200, timers : (timer, timer), stack : &stack, instr_log : &instr_log,
+            cycles_since_input, ips, sprite : [0; SPRITE_PREVIEW_HEIGHT],
+        };
+
+        let (mut registers_panel, mut counters_panel) = (None, None);
+        let (mut registers_renders, mut counters_renders) = (0, 0);
+        for frame in 0..10 {
+            let state = state(60 - frame as u8, frame * 10, 600);
+            update_panel(&mut registers_panel, registers_text(&state), |_| registers_renders += 1);
+            update_panel(&mut counters_panel, counters_text(&state, 1.0), |_| counters_renders += 1);
+        }
+
+        assert_eq!(registers_renders, 1);
+        assert_eq!(counters_renders, 10);
+    }
+}
@@ -0,0 +1,151 @@
+// Decoding of the raw 16-bit opcodes, shared by the interpreter and the disassembler so both
+// always agree on what an opcode is
+
+/// A decoded instruction, with its operands already pulled out of the opcode.
+/// X and Y are register numbers, NNN an address, NN a byte and N a nibble
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    ScrollDown(usize), // 00CN (SUPER-CHIP)
+    Cls, // 00E0
+    Ret, // 00EE
+    ScrollRight, // 00FB (SUPER-CHIP)
+    ScrollLeft, // 00FC (SUPER-CHIP)
+    Lores, // 00FE (SUPER-CHIP)
+    Hires, // 00FF (SUPER-CHIP)
+    Sys(usize), // 0NNN
+    Jump(usize), // 1NNN
+    Call(usize), // 2NNN
+    SkipIfEqualByte(usize, u8), // 3XNN
+    SkipIfNotEqualByte(usize, u8), // 4XNN
+    SkipIfEqual(usize, usize), // 5XY0
+    LoadByte(usize, u8), // 6XNN
+    AddByte(usize, u8), // 7XNN
+    Load(usize, usize), // 8XY0
+    Or(usize, usize), // 8XY1
+    And(usize, usize), // 8XY2
+    Xor(usize, usize), // 8XY3
+    Add(usize, usize), // 8XY4
+    Sub(usize, usize), // 8XY5
+    ShiftRight(usize, usize), // 8XY6
+    SubN(usize, usize), // 8XY7
+    ShiftLeft(usize, usize), // 8XYE
+    SkipIfNotEqual(usize, usize), // 9XY0
+    LoadI(usize), // ANNN
+    JumpV0(usize), // BNNN
+    Random(usize, u8), // CXNN
+    Draw(usize, usize, usize), // DXYN
+    SkipIfKey(usize), // EX9E
+    SkipIfNotKey(usize), // EXA1
+    Audio, // F002 (XO-CHIP)
+    Plane(usize), // FN01 (XO-CHIP), N is the bit-plane mask rather than a register
+    LoadDelay(usize), // FX07
+    WaitKey(usize), // FX0A
+    SetDelay(usize), // FX15
+    SetSound(usize), // FX18
+    AddI(usize), // FX1E
+    LoadFont(usize), // FX29
+    LoadBigFont(usize), // FX30 (SUPER-CHIP)
+    Bcd(usize), // FX33
+    Pitch(usize), // FX3A (XO-CHIP)
+    Store(usize), // FX55
+    Restore(usize), // FX65
+    StoreFlags(usize), // FX75 (SUPER-CHIP)
+    RestoreFlags(usize), // FX85 (SUPER-CHIP)
+    Unknown(u16), // probably data, such as sprites
+}
+
+/// Figures out which instruction the opcode is. Opcodes that aren't known are Unknown,
+/// whether they're actually run is up to the interpreter
+pub fn decode(instr : u16) -> Instruction {
+    // Divide the 16-bit instr into 4 groups of 4 bits (represented as an u8)
+    let instr_nibbles = (
+        //                  AAAA BBBB CCCC DDDD
+        // BITWISE_AND      1111 0000 0000 0000
+        //              =   AAAA 0000 0000 0000
+        // >> 12        =   0000 0000 0000 AAAA
+        // as u8        =   0000 AAAA
+        ((instr & 0xF000) >> 12) as u8,
+        // Same with the rest
+        ((instr & 0x0F00) >> 8) as u8,
+        ((instr & 0x00F0) >> 4) as u8,
+        (instr & 0x000F) as u8,
+    );
+
+    // Address part of the instr
+    let nnn = (instr & 0x0FFF) as usize;
+    // 8-bit constant
+    let nn = (instr & 0x00FF) as u8;
+    // 4-bit constant
+    let n = instr_nibbles.3 as usize;
+    // 4-bit v
+    let x = instr_nibbles.1 as usize;
+    let y = instr_nibbles.2 as usize;
+
+    match instr_nibbles {
+        (0x00, 0x00, 0x0c, _) => Instruction::ScrollDown(n),
+        (0x00, 0x00, 0x0e, 0x00) => Instruction::Cls,
+        (0x00, 0x00, 0x0e, 0x0e) => Instruction::Ret,
+        (0x00, 0x00, 0x0f, 0x0b) => Instruction::ScrollRight,
+        (0x00, 0x00, 0x0f, 0x0c) => Instruction::ScrollLeft,
+        (0x00, 0x00, 0x0f, 0x0e) => Instruction::Lores,
+        (0x00, 0x00, 0x0f, 0x0f) => Instruction::Hires,
+        (0x00, _, _, _) => Instruction::Sys(nnn),
+        (0x01, _, _, _) => Instruction::Jump(nnn),
+        (0x02, _, _, _) => Instruction::Call(nnn),
+        (0x03, _, _, _) => Instruction::SkipIfEqualByte(x, nn),
+        (0x04, _, _, _) => Instruction::SkipIfNotEqualByte(x, nn),
+        (0x05, _, _, 0x00) => Instruction::SkipIfEqual(x, y),
+        (0x06, _, _, _) => Instruction::LoadByte(x, nn),
+        (0x07, _, _, _) => Instruction::AddByte(x, nn),
+        (0x08, _, _, 0x00) => Instruction::Load(x, y),
+        (0x08, _, _, 0x01) => Instruction::Or(x, y),
+        (0x08, _, _, 0x02) => Instruction::And(x, y),
+        (0x08, _, _, 0x03) => Instruction::Xor(x, y),
+        (0x08, _, _, 0x04) => Instruction::Add(x, y),
+        (0x08, _, _, 0x05) => Instruction::Sub(x, y),
+        (0x08, _, _, 0x06) => Instruction::ShiftRight(x, y),
+        (0x08, _, _, 0x07) => Instruction::SubN(x, y),
+        (0x08, _, _, 0x0e) => Instruction::ShiftLeft(x, y),
+        (0x09, _, _, 0x00) => Instruction::SkipIfNotEqual(x, y),
+        (0x0a, _, _, _) => Instruction::LoadI(nnn),
+        (0x0b, _, _, _) => Instruction::JumpV0(nnn),
+        (0x0c, _, _, _) => Instruction::Random(x, nn),
+        (0x0d, _, _, _) => Instruction::Draw(x, y, n),
+        (0x0e, _, 0x09, 0x0e) => Instruction::SkipIfKey(x),
+        (0x0e, _, 0x0a, 0x01) => Instruction::SkipIfNotKey(x),
+        (0x0f, 0x00, 0x00, 0x02) => Instruction::Audio,
+        (0x0f, _, 0x00, 0x01) => Instruction::Plane(x),
+        (0x0f, _, 0x00, 0x07) => Instruction::LoadDelay(x),
+        (0x0f, _, 0x00, 0x0a) => Instruction::WaitKey(x),
+        (0x0f, _, 0x01, 0x05) => Instruction::SetDelay(x),
+        (0x0f, _, 0x01, 0x08) => Instruction::SetSound(x),
+        (0x0f, _, 0x01, 0x0e) => Instruction::AddI(x),
+        (0x0f, _, 0x02, 0x09) => Instruction::LoadFont(x),
+        (0x0f, _, 0x03, 0x00) => Instruction::LoadBigFont(x),
+        (0x0f, _, 0x03, 0x03) => Instruction::Bcd(x),
+        (0x0f, _, 0x03, 0x0a) => Instruction::Pitch(x),
+        (0x0f, _, 0x05, 0x05) => Instruction::Store(x),
+        (0x0f, _, 0x06, 0x05) => Instruction::Restore(x),
+        (0x0f, _, 0x07, 0x05) => Instruction::StoreFlags(x),
+        (0x0f, _, 0x08, 0x05) => Instruction::RestoreFlags(x),
+        _ => Instruction::Unknown(instr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulls_out_the_operands() {
+        assert_eq!(decode(0x6a02), Instruction::LoadByte(0xa, 0x02));
+        assert_eq!(decode(0xd12f), Instruction::Draw(1, 2, 0xf));
+        assert_eq!(decode(0xb345), Instruction::JumpV0(0x345));
+        assert_eq!(decode(0x00c3), Instruction::ScrollDown(3));
+        assert_eq!(decode(0x00e1), Instruction::Sys(0x0e1));
+
+        // 5XY0 has to end with 0, and FX.. needs one of the known low bytes
+        assert_eq!(decode(0x5121), Instruction::Unknown(0x5121));
+        assert_eq!(decode(0xf1ff), Instruction::Unknown(0xf1ff));
+    }
+}
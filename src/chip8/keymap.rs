@@ -0,0 +1,145 @@
+use sdl2::keyboard::Keycode;
+
+use std::fs;
+
+use crate::chip8::keypad::special_key_action;
+
+// Keymap files bind CHIP-8 keys to physical keys with `<key> = <SDL key name>` lines, where the
+// CHIP-8 key is a hex digit and the names are the ones in --list-keys. An AZERTY keymap could be:
+//
+//      # Comments and empty lines are ignored
+//      4 = A
+//      5 = Z
+//      A = W
+//
+// Keys that aren't listed keep their default binding
+
+// The CHIP-8 keys in the order they're laid out on the COSMAC VIP keypad, row by row
+// https://tobiasvl.github.io/assets/images/cosmac-vip-keypad.png
+pub const KEYPAD_LAYOUT : [usize; 16] = [
+    0x1, 0x2, 0x3, 0xc,
+    0x4, 0x5, 0x6, 0xd,
+    0x7, 0x8, 0x9, 0xe,
+    0xa, 0x0, 0xb, 0xf,
+];
+
+/// Which physical key is bound to each CHIP-8 key
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    keys : [Keycode; 16], // indexed by the CHIP-8 key
+}
+
+impl Keymap {
+    pub fn from_path(path : &str) -> Result<Keymap, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read keymap file {}: {}", path, e))?;
+
+        Keymap::parse(&contents).map_err(|e| format!("Invalid keymap file {}: {}", path, e))
+    }
+
+    /// Parses the contents of a keymap file
+    pub fn parse(contents : &str) -> Result<Keymap, String> {
+        let mut keymap = Keymap::default();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, name) = match line.split_once('=') {
+                Some((key, name)) => (key.trim(), name.trim()),
+                None => return Err(format!("line {}: expected `<key> = <key name>`", number + 1))
+            };
+
+            let key = usize::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key <= 0xF)
+                .ok_or_else(|| format!("line {}: '{}' is not a CHIP-8 key (0-F)", number + 1, key))?;
+            let keycode = Keycode::from_name(name)
+                .ok_or_else(|| format!("line {}: unknown key name '{}'", number + 1, name))?;
+
+            if let Some(action) = special_key_action(keycode) {
+                return Err(format!("line {}: {} is a special key ({})", number + 1, name, action));
+            }
+
+            keymap.keys[key] = keycode;
+        }
+
+        // Checked at the end, so keys can be swapped around
+        for (key, keycode) in keymap.keys.iter().enumerate() {
+            if let Some(other) = keymap.key_for(*keycode).filter(|&other| other != key) {
+                return Err(format!("{} is bound to both {:X} and {:X}", keycode.name(), other, key));
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    /// The CHIP-8 key bound to that physical key, if any
+    pub fn key_for(&self, keycode : Keycode) -> Option<usize> {
+        self.keys.iter().position(|&bound| bound == keycode)
+    }
+
+    /// The physical key bound to a CHIP-8 key (0x0-0xF)
+    pub fn keycode(&self, key : usize) -> Keycode {
+        self.keys[key]
+    }
+}
+
+impl Default for Keymap {
+    /// The left side of a QWERTY keyboard, 1234/QWER/ASDF/ZXCV, mirrors the keypad layout
+    fn default() -> Self {
+        Keymap {
+            keys : [
+                Keycode::X,    // 0
+                Keycode::Num1, // 1
+                Keycode::Num2, // 2
+                Keycode::Num3, // 3
+                Keycode::Q,    // 4
+                Keycode::W,    // 5
+                Keycode::E,    // 6
+                Keycode::A,    // 7
+                Keycode::S,    // 8
+                Keycode::D,    // 9
+                Keycode::Z,    // A
+                Keycode::C,    // B
+                Keycode::Num4, // C
+                Keycode::R,    // D
+                Keycode::F,    // E
+                Keycode::V,    // F
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_keymap_round_trips() {
+        let keymap = Keymap::default();
+
+        for key in 0..16 {
+            assert_eq!(keymap.key_for(keymap.keycode(key)), Some(key));
+        }
+        assert_eq!(keymap.key_for(Keycode::Num1), Some(0x1));
+        assert_eq!(keymap.key_for(Keycode::X), Some(0x0));
+        assert_eq!(keymap.key_for(Keycode::Escape), None);
+    }
+
+    #[test]
+    fn keymap_files_rebind_keys() {
+        let keymap = Keymap::parse("# AZERTY\n4 = A\n5 = Z\n7 = Q\na = W\n").unwrap();
+
+        assert_eq!(keymap.key_for(Keycode::A), Some(0x4));
+        assert_eq!(keymap.key_for(Keycode::W), Some(0xA));
+        assert_eq!(keymap.key_for(Keycode::Num1), Some(0x1)); // untouched
+
+        assert!(Keymap::parse("4 = Nope").unwrap_err().starts_with("line 1"));
+        assert!(Keymap::parse("G = A").is_err());
+        assert!(Keymap::parse("4 = Escape").is_err());
+        assert!(Keymap::parse("4 = X").is_err()); // X is still bound to 0
+    }
+}
@@ -1,38 +1,96 @@
-use sdl2;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "sdl")]
+use crate::chip8::keymap::Keymap;
+use crate::chip8::quirks::Quirks;
+use crate::chip8::view::ViewOptions;
+use crate::config;
+
+// Reading the keyboard through SDL, with the special keys and the keypad mapping
+#[cfg(feature = "sdl")]
+mod keyboard;
+
+#[cfg(feature = "sdl")]
+pub use keyboard::describe_keys;
+#[cfg(feature = "sdl")]
+pub(crate) use keyboard::special_key_action;
 
 pub const EXIT_KEY_VALUE : usize = 0xffa;
-const EXIT_KEYCODE : Keycode = Keycode::Escape;
 pub const PAUSE_KEY_VALUE : usize = 0xffb;
-const PAUSE_KEYCODE : Keycode = Keycode::Space;
 pub const FREQ_DOWN_KEY_VALUE : usize = 0xffc;
-const FREQ_DOWN_KEYCODE : Keycode = Keycode::Down;
 pub const FREQ_UP_KEY_VALUE : usize = 0xffd;
-const FREQ_UP_KEYCODE : Keycode = Keycode::Up;
 
+// Without SDL nothing reads the keyboard, so the shared state the special keys change goes unused
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
 pub struct Keypad {
     keypad : [bool; 16],
-    event_pump : sdl2::EventPump,
-    pause : Rc<RefCell<bool>>, // shared pause flag, read by the cpu
-    freq_period : Rc<RefCell<u64>>
+    enabled : [bool; 16], // keys which are masked out are never seen as pressed
+    replaying : bool, // the keys come from a --replay recording, the keyboard only has the special keys
+    #[cfg(feature = "sdl")]
+    keymap : Keymap,
+    #[cfg(feature = "sdl")]
+    event_pump : Option<sdl2::EventPump>, // missing when running headless
+    pause : Arc<Mutex<bool>>, // shared pause flag, read by the cpu and the timer thread
+    freq_period : Rc<RefCell<u64>>,
+    view : Rc<RefCell<ViewOptions>>, // shared display options, read by the graphics subsystem
+    quirks : Rc<RefCell<Quirks>>, // shared quirks, read by the cpu and the graphics subsystem
+    slow_motion : Rc<RefCell<u32>>, // how many times the speed has been halved, read by the cpu and the graphics subsystem
+    dump_requested : bool, // set when the disassembly dump key is pressed, consumed by the cpu
+    turbo_requested : bool, // same, for the turbo until draw key
+    screen_dump_requested : bool, // same, for the screen dump key
+    screenshot_requested : bool, // same, for the screenshot key
+    step_requested : bool, // same, for the step key
+    step_back_requested : bool, // same, for the step back key
+    mute_requested : bool, // same, for the mute key, but consumed by the emulator which owns the sound
+    volume_change : i32, // presses of the volume up key minus the volume down ones, also consumed by the emulator
+    reset_requested : bool, // same, for the reset key
+    save_state_requested : bool, // same, for the save state key
+    load_state_requested : bool, // same, for the load state key
+    freq_changed : bool, // set when the frequency keys change the frequency period, consumed by the cpu
+    changed : bool // set when the keypad state changes, consumed by the cpu
 }
 
 impl Keypad {
-    pub fn new(sdl_context : &sdl2::Sdl, pause : Rc<RefCell<bool>>, freq_period : Rc<RefCell<u64>>) -> Keypad {
+    /// A keypad that never reads the keyboard, only driven through set_key
+    pub fn headless(pause : Arc<Mutex<bool>>, freq_period : Rc<RefCell<u64>>, view : Rc<RefCell<ViewOptions>>, quirks : Rc<RefCell<Quirks>>, slow_motion : Rc<RefCell<u32>>, config : &config::Config) -> Keypad {
+        let mut enabled = [true; 16];
+        for &key in config.disabled_keys() {
+            enabled[key] = false;
+        }
+
         Keypad {
             keypad : [false; 16],
-            event_pump : sdl_context.event_pump().unwrap(), // get and handle the event pump from the context
-            pause : pause,
-            freq_period : freq_period
+            enabled,
+            replaying : config.replay().is_some(),
+            #[cfg(feature = "sdl")]
+            keymap : config.keymap(),
+            #[cfg(feature = "sdl")]
+            event_pump : None,
+            pause,
+            freq_period,
+            view,
+            quirks,
+            slow_motion,
+            dump_requested : false,
+            turbo_requested : false,
+            screen_dump_requested : false,
+            screenshot_requested : false,
+            step_requested : false,
+            step_back_requested : false,
+            mute_requested : false,
+            volume_change : 0,
+            reset_requested : false,
+            save_state_requested : false,
+            load_state_requested : false,
+            freq_changed : false,
+            changed : false
         }
     }
 
     // Return an iterator over the keypad
-    pub fn iter(&self) -> std::slice::Iter<bool> {
+    pub fn iter(&self) -> std::slice::Iter<'_, bool> {
         self.keypad.iter()
     }
 
@@ -46,79 +104,116 @@ impl Keypad {
         false
     }
 
-    /// Consumes all SDL events and updates the keypad. Returns true if the user
-    /// wants to quit, false otherwise.
-    pub fn poll_keyboard(&mut self) -> bool {
-        let mut wants_to_quit = false;
-
-        // Consumes all pending events and checks if one of them is quitting (pressing (x) in the window...)
-        for event in self.event_pump.poll_iter() { 
-            if let Event::Quit { .. } = event {
-                wants_to_quit = true;
-            };
-        } 
-
-        let keys: Vec<Keycode> = self.event_pump
-            .keyboard_state() // Get a snapshot of the current keyboard state
-            .pressed_scancodes() // With the pressed scancodes
-            .filter_map(Keycode::from_scancode) // Turning them into keycodes
-            .collect(); // And into a Vec
-
-        self.clear_keypad();
-
-        for key in keys {
-            // https://tobiasvl.github.io/assets/images/cosmac-vip-keypad.png
-            let index = match key {
-                Keycode::Num1 => Some(0x1),
-                Keycode::Num2 => Some(0x2),
-                Keycode::Num3 => Some(0x3),
-                Keycode::Num4 => Some(0xc),
-                Keycode::Q => Some(0x4),
-                Keycode::W => Some(0x5),
-                Keycode::E => Some(0x6),
-                Keycode::R => Some(0xd),
-                Keycode::A => Some(0x7),
-                Keycode::S => Some(0x8),
-                Keycode::D => Some(0x9),
-                Keycode::F => Some(0xe),
-                Keycode::Z => Some(0xa),
-                Keycode::X => Some(0x0),
-                Keycode::C => Some(0xb),
-                Keycode::V => Some(0xf),
-                EXIT_KEYCODE => Some(EXIT_KEY_VALUE), // Exit key
-                PAUSE_KEYCODE => Some(PAUSE_KEY_VALUE),
-                FREQ_DOWN_KEYCODE => Some(FREQ_DOWN_KEY_VALUE), // Exit key
-                FREQ_UP_KEYCODE => Some(FREQ_UP_KEY_VALUE),
-                _ => None,
-            };
-
-            if let Some(i) = index {
-                match i {
-                    EXIT_KEY_VALUE => wants_to_quit = true, 
-                    PAUSE_KEY_VALUE => {    
-                        let pause = *self.pause.borrow();
-                        *self.pause.borrow_mut() = ! pause;
-                    },
-                    FREQ_DOWN_KEY_VALUE => {
-                            let freq = *self.freq_period.borrow();
-                            *self.freq_period.borrow_mut() = freq.saturating_add(1000);
-                        },
-                    FREQ_UP_KEY_VALUE => {
-                            let freq = *self.freq_period.borrow();
-                            *self.freq_period.borrow_mut() = freq.saturating_sub(1000);
-                        }
-                    i => self.keypad[i] = true
-                }
-            }
+    /// Presses or releases a key directly, without going through SDL. Keys out of the 0x0-0xF
+    /// range are ignored. Keep in mind that poll_keyboard overwrites the whole keypad state
+    pub fn set_key(&mut self, key : usize, pressed : bool) {
+        if (0..=0xF).contains(&key) {
+            let pressed = pressed && self.enabled[key];
+            self.changed |= self.keypad[key] != pressed;
+            self.keypad[key] = pressed;
         }
+    }
 
-        wants_to_quit
+    /// The pressed keys as 16 bits, bit N being key N
+    pub fn state(&self) -> u16 {
+        self.keypad.iter().enumerate()
+            .filter(|(_, &pressed)| pressed)
+            .fold(0, |state, (key, _)| state | 1 << key)
     }
 
-    /// Self-explanatory
-    fn clear_keypad(&mut self) {
-        for key in self.keypad.iter_mut() {
-            *key = false;
+    /// Presses exactly the keys set in the 16 bits, as returned by state()
+    pub fn set_state(&mut self, state : u16) {
+        for key in 0..16 {
+            self.set_key(key, state >> key & 1 == 1);
         }
     }
+
+    /// Without SDL there's no keyboard to read, the keys are only pressed through set_key
+    #[cfg(not(feature = "sdl"))]
+    pub fn poll_keyboard(&mut self) -> bool {
+        false
+    }
+
+    /// Returns true if the disassembly dump key was pressed since the last call
+    pub fn dump_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.dump_requested, false)
+    }
+
+    /// Returns true if the turbo until draw key was pressed since the last call
+    pub fn turbo_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.turbo_requested, false)
+    }
+
+    /// Returns true if the screen dump key was pressed since the last call
+    pub fn screen_dump_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.screen_dump_requested, false)
+    }
+
+    /// Returns true if the screenshot key was pressed since the last call
+    pub fn screenshot_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.screenshot_requested, false)
+    }
+
+    /// Returns true if the step key was pressed since the last call
+    pub fn step_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.step_requested, false)
+    }
+
+    /// Returns true if the step back key was pressed since the last call
+    pub fn step_back_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.step_back_requested, false)
+    }
+
+    /// Returns true if the mute key was pressed since the last call
+    pub fn mute_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.mute_requested, false)
+    }
+
+    /// Returns how many steps the volume keys moved the volume since the last call, negative to turn it down
+    pub fn volume_change(&mut self) -> i32 {
+        std::mem::replace(&mut self.volume_change, 0)
+    }
+
+    /// Returns true if the reset key was pressed since the last call
+    pub fn reset_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.reset_requested, false)
+    }
+
+    /// Returns true if the save state key was pressed since the last call
+    pub fn save_state_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.save_state_requested, false)
+    }
+
+    /// Returns true if the load state key was pressed since the last call
+    pub fn load_state_requested(&mut self) -> bool {
+        std::mem::replace(&mut self.load_state_requested, false)
+    }
+
+    /// Returns the new frequency period if the frequency keys changed it since the last call
+    pub fn freq_changed(&mut self) -> Option<u64> {
+        if std::mem::replace(&mut self.freq_changed, false) {
+            Some(*self.freq_period.borrow())
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if any key was pressed or released since the last call
+    pub fn changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+}
+
+/// The fraction of the normal speed the VM runs at after halving it `slow_motion` times
+pub fn speed_multiplier(slow_motion : u32) -> f64 {
+    1.0 / (1u32 << slow_motion) as f64
+}
+
+/// Describes the frequency the cycles run at for the given period in nanoseconds
+pub fn describe_frequency(freq_period : u64) -> String {
+    if freq_period == 0 {
+        "Frequency: unlimited".to_string()
+    } else {
+        format!("Frequency: {:.0} Hz", 1e9 / freq_period as f64)
+    }
 }
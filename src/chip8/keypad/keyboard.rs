@@ -0,0 +1,253 @@
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+use log::info;
+
+use super::{speed_multiplier, Keypad, EXIT_KEY_VALUE, FREQ_DOWN_KEY_VALUE, FREQ_UP_KEY_VALUE, PAUSE_KEY_VALUE};
+use crate::chip8::keymap::{Keymap, KEYPAD_LAYOUT};
+use crate::chip8::quirks::Quirks;
+use crate::chip8::view::ViewOptions;
+use crate::config;
+
+const EXIT_KEYCODE : Keycode = Keycode::Escape;
+const PAUSE_KEYCODE : Keycode = Keycode::Space;
+const FREQ_DOWN_KEYCODE : Keycode = Keycode::Down;
+const FREQ_UP_KEYCODE : Keycode = Keycode::Up;
+const DISASM_DUMP_KEYCODE : Keycode = Keycode::F2;
+const SCREEN_DUMP_KEYCODE : Keycode = Keycode::F3;
+const DEBUG_PANEL_KEYCODE : Keycode = Keycode::F4;
+const FULLSCREEN_KEYCODE : Keycode = Keycode::F11;
+const STEP_BACK_KEYCODE : Keycode = Keycode::Backspace;
+const INVERT_KEYCODE : Keycode = Keycode::I;
+// The quirks are toggled with Ctrl, so a mistyped key next to the keypad doesn't change them mid-game
+const QUIRK_MODIFIER : Mod = Mod::from_bits_truncate(Mod::LCTRLMOD.bits() | Mod::RCTRLMOD.bits());
+const QUIRK_CLIP_MODE_KEYCODE : Keycode = Keycode::Num5;
+const QUIRK_SHIFT_KEYCODE : Keycode = Keycode::Num6;
+const QUIRK_LOAD_STORE_KEYCODE : Keycode = Keycode::Num7;
+const QUIRK_JUMP_KEYCODE : Keycode = Keycode::Num8;
+const QUIRK_VF_RESET_KEYCODE : Keycode = Keycode::Num9;
+const QUIRK_VBLANK_KEYCODE : Keycode = Keycode::Num0;
+const TURBO_UNTIL_DRAW_KEYCODE : Keycode = Keycode::Tab;
+const SPRITE_PREVIEW_KEYCODE : Keycode = Keycode::P;
+const SLOW_MOTION_KEYCODE : Keycode = Keycode::Left;
+const NORMAL_SPEED_KEYCODE : Keycode = Keycode::Right;
+const MUTE_KEYCODE : Keycode = Keycode::M;
+const VOLUME_UP_KEYCODE : Keycode = Keycode::Equals;
+const VOLUME_DOWN_KEYCODE : Keycode = Keycode::Minus;
+const RESET_KEYCODE : Keycode = Keycode::F1;
+const STEP_KEYCODE : Keycode = Keycode::N;
+const SCREENSHOT_KEYCODE : Keycode = Keycode::F12;
+const SAVE_STATE_KEYCODE : Keycode = Keycode::F5;
+const LOAD_STATE_KEYCODE : Keycode = Keycode::F9;
+
+// What every special key does, for --list-keys
+const SPECIAL_KEYS : &[(Keycode, &str)] = &[
+    (EXIT_KEYCODE, "exit"),
+    (PAUSE_KEYCODE, "pause/resume"),
+    (FREQ_UP_KEYCODE, "increase the frequency"),
+    (FREQ_DOWN_KEYCODE, "decrease the frequency"),
+    (SLOW_MOTION_KEYCODE, "halve the speed (slow motion)"),
+    (NORMAL_SPEED_KEYCODE, "back to normal speed"),
+    (TURBO_UNTIL_DRAW_KEYCODE, "full speed until the next draw"),
+    (MUTE_KEYCODE, "mute/unmute the sound"),
+    (VOLUME_UP_KEYCODE, "turn the beep up"),
+    (VOLUME_DOWN_KEYCODE, "turn the beep down"),
+    (INVERT_KEYCODE, "invert the colors"),
+    (SPRITE_PREVIEW_KEYCODE, "show the sprite at I"),
+    (DEBUG_PANEL_KEYCODE, "show/hide the debug panel"),
+    (FULLSCREEN_KEYCODE, "enter/leave fullscreen"),
+    (RESET_KEYCODE, "reset, reloading the ROM from disk"),
+    (SAVE_STATE_KEYCODE, "save the state next to the ROM (<rom>.state)"),
+    (LOAD_STATE_KEYCODE, "load the state saved with F5"),
+    (DISASM_DUMP_KEYCODE, "dump the disassembly at the PC"),
+    (SCREEN_DUMP_KEYCODE, "print the screen as ASCII art"),
+    (SCREENSHOT_KEYCODE, "save a screenshot of the game as a PNG"),
+    (STEP_KEYCODE, "run the next instruction, while paused"),
+    (STEP_BACK_KEYCODE, "undo the last instruction (with --rewind-depth)"),
+];
+
+// What every quirk key does when pressed along with Ctrl, for --list-keys
+const QUIRK_KEYS : &[(Keycode, &str)] = &[
+    (QUIRK_CLIP_MODE_KEYCODE, "toggle the clip mode quirk"),
+    (QUIRK_SHIFT_KEYCODE, "toggle the shift quirk (8XY6 and 8XYE use VY)"),
+    (QUIRK_LOAD_STORE_KEYCODE, "toggle the load/store quirk (FX55 and FX65 increment I)"),
+    (QUIRK_JUMP_KEYCODE, "toggle the jump quirk (BXNN jumps to XNN + VX)"),
+    (QUIRK_VF_RESET_KEYCODE, "toggle the logic quirk (8XY1, 8XY2 and 8XY3 reset VF)"),
+    (QUIRK_VBLANK_KEYCODE, "toggle the vblank quirk (DXYN waits for the next frame)"),
+];
+
+// Slow motion stops halving the speed at 1/256x
+const MAX_SLOW_MOTION : u32 = 8;
+
+impl Keypad {
+    pub fn new(sdl_context : &sdl2::Sdl, pause : Arc<Mutex<bool>>, freq_period : Rc<RefCell<u64>>, view : Rc<RefCell<ViewOptions>>, quirks : Rc<RefCell<Quirks>>, slow_motion : Rc<RefCell<u32>>, config : &config::Config) -> Keypad {
+        let event_pump = sdl_context.event_pump().unwrap(); // get and handle the event pump from the context
+
+        Keypad {
+            event_pump : Some(event_pump),
+            ..Keypad::headless(pause, freq_period, view, quirks, slow_motion, config)
+        }
+    }
+
+    /// Consumes all SDL events and updates the keypad. Returns true if the user
+    /// wants to quit, false otherwise. Headless keypads are left untouched
+    pub fn poll_keyboard(&mut self) -> bool {
+        let mut wants_to_quit = false;
+
+        let event_pump = match self.event_pump.as_mut() {
+            Some(event_pump) => event_pump,
+            None => return false
+        };
+
+        // Consumes all pending events and checks if one of them is quitting (pressing (x) in the window...)
+        // or a one-shot key, which we don't want to trigger on every poll while it's held down
+        for event in event_pump.poll_iter() { 
+            match event {
+                Event::Quit { .. } => wants_to_quit = true,
+                Event::KeyDown { keycode: Some(DISASM_DUMP_KEYCODE), repeat: false, .. } => self.dump_requested = true,
+                Event::KeyDown { keycode: Some(TURBO_UNTIL_DRAW_KEYCODE), repeat: false, .. } => self.turbo_requested = true,
+                Event::KeyDown { keycode: Some(SCREEN_DUMP_KEYCODE), repeat: false, .. } => self.screen_dump_requested = true,
+                Event::KeyDown { keycode: Some(SCREENSHOT_KEYCODE), repeat: false, .. } => self.screenshot_requested = true,
+                Event::KeyDown { keycode: Some(STEP_KEYCODE), .. } => self.step_requested = true, // holding it keeps stepping
+                Event::KeyDown { keycode: Some(STEP_BACK_KEYCODE), .. } => self.step_back_requested = true, // holding it keeps going back
+                Event::KeyDown { keycode: Some(MUTE_KEYCODE), repeat: false, .. } => self.mute_requested = true,
+                Event::KeyDown { keycode: Some(VOLUME_UP_KEYCODE), .. } => self.volume_change += 1, // holding them keeps going
+                Event::KeyDown { keycode: Some(VOLUME_DOWN_KEYCODE), .. } => self.volume_change -= 1,
+                Event::KeyDown { keycode: Some(RESET_KEYCODE), repeat: false, .. } => self.reset_requested = true,
+                Event::KeyDown { keycode: Some(SAVE_STATE_KEYCODE), repeat: false, .. } => self.save_state_requested = true,
+                Event::KeyDown { keycode: Some(LOAD_STATE_KEYCODE), repeat: false, .. } => self.load_state_requested = true,
+                Event::KeyDown { keycode: Some(INVERT_KEYCODE), repeat: false, .. } => {
+                    let mut view = self.view.borrow_mut();
+                    view.invert = ! view.invert;
+                },
+                Event::KeyDown { keycode: Some(SPRITE_PREVIEW_KEYCODE), repeat: false, .. } => {
+                    let mut view = self.view.borrow_mut();
+                    view.sprite_preview = ! view.sprite_preview;
+                },
+                Event::KeyDown { keycode: Some(DEBUG_PANEL_KEYCODE), repeat: false, .. } => {
+                    let mut view = self.view.borrow_mut();
+                    view.debug_panel = ! view.debug_panel;
+                },
+                Event::KeyDown { keycode: Some(FULLSCREEN_KEYCODE), repeat: false, .. } => {
+                    let mut view = self.view.borrow_mut();
+                    view.fullscreen = ! view.fullscreen;
+                },
+                Event::KeyDown { keycode: Some(SLOW_MOTION_KEYCODE), repeat: false, .. } => {
+                    let mut slow_motion = self.slow_motion.borrow_mut();
+                    *slow_motion = (*slow_motion + 1).min(MAX_SLOW_MOTION);
+                    info!("Speed: {}x", speed_multiplier(*slow_motion));
+                },
+                Event::KeyDown { keycode: Some(NORMAL_SPEED_KEYCODE), repeat: false, .. } => {
+                    *self.slow_motion.borrow_mut() = 0;
+                    info!("Speed: 1x");
+                },
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } if keymod.intersects(QUIRK_MODIFIER) => {
+                    match keycode {
+                        QUIRK_CLIP_MODE_KEYCODE => toggle_quirk(&self.quirks, |quirks| quirks.clip_mode = quirks.clip_mode.toggled()),
+                        QUIRK_SHIFT_KEYCODE => toggle_quirk(&self.quirks, |quirks| quirks.shift_uses_vy = ! quirks.shift_uses_vy),
+                        QUIRK_LOAD_STORE_KEYCODE => toggle_quirk(&self.quirks, |quirks| quirks.increment_i_on_store = ! quirks.increment_i_on_store),
+                        QUIRK_JUMP_KEYCODE => toggle_quirk(&self.quirks, |quirks| quirks.jump_uses_vx = ! quirks.jump_uses_vx),
+                        QUIRK_VF_RESET_KEYCODE => toggle_quirk(&self.quirks, |quirks| quirks.vf_reset = ! quirks.vf_reset),
+                        QUIRK_VBLANK_KEYCODE => toggle_quirk(&self.quirks, |quirks| quirks.display_wait = ! quirks.display_wait),
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
+        } 
+
+        let keys: Vec<Keycode> = event_pump
+            .keyboard_state() // Get a snapshot of the current keyboard state
+            .pressed_scancodes() // With the pressed scancodes
+            .filter_map(Keycode::from_scancode) // Turning them into keycodes
+            .collect(); // And into a Vec
+
+        let previous = self.keypad;
+        if ! self.replaying {
+            self.clear_keypad();
+        }
+
+        for key in keys {
+            let index = match key {
+                EXIT_KEYCODE => Some(EXIT_KEY_VALUE), // Exit key
+                PAUSE_KEYCODE => Some(PAUSE_KEY_VALUE),
+                FREQ_DOWN_KEYCODE => Some(FREQ_DOWN_KEY_VALUE),
+                FREQ_UP_KEYCODE => Some(FREQ_UP_KEY_VALUE),
+                key => self.keymap.key_for(key),
+            };
+
+            if let Some(i) = index {
+                match i {
+                    EXIT_KEY_VALUE => wants_to_quit = true, 
+                    PAUSE_KEY_VALUE => {    
+                        let mut pause = self.pause.lock().unwrap();
+                        *pause = ! *pause;
+                    },
+                    FREQ_DOWN_KEY_VALUE => {
+                            let freq = *self.freq_period.borrow();
+                            *self.freq_period.borrow_mut() = freq.saturating_add(1000);
+                            self.freq_changed = true;
+                        },
+                    FREQ_UP_KEY_VALUE => {
+                            let freq = *self.freq_period.borrow();
+                            *self.freq_period.borrow_mut() = freq.saturating_sub(1000);
+                            self.freq_changed |= freq != 0;
+                        }
+                    _ if self.replaying => {},
+                    i => self.keypad[i] = self.enabled[i]
+                }
+            }
+        }
+
+        self.changed |= self.keypad != previous;
+
+        wants_to_quit
+    }
+
+    /// Self-explanatory
+    fn clear_keypad(&mut self) {
+        for key in self.keypad.iter_mut() {
+            *key = false;
+        }
+    }
+}
+
+/// Changes one of the shared quirks and logs the new state of all of them
+fn toggle_quirk(quirks : &Rc<RefCell<Quirks>>, toggle : impl FnOnce(&mut Quirks)) {
+    let mut quirks = quirks.borrow_mut();
+    toggle(&mut quirks);
+    info!("{}", quirks);
+}
+
+/// What the special key does, if it's one of them
+pub(crate) fn special_key_action(keycode : Keycode) -> Option<&'static str> {
+    SPECIAL_KEYS.iter()
+        .find(|(special, _)| *special == keycode)
+        .map(|(_, action)| *action)
+}
+
+/// Describes the keypad mapping and the special keys, as printed by --list-keys
+pub fn describe_keys(keymap : &Keymap) -> String {
+    let mut description = String::from("CHIP-8 keypad -> keyboard\n");
+
+    for row in KEYPAD_LAYOUT.chunks(4) {
+        let hex : Vec<String> = row.iter().map(|key| format!("{:X}", key)).collect();
+        let keyboard : Vec<String> = row.iter().map(|&key| format!("{:<6}", keymap.keycode(key).name())).collect();
+
+        description += &format!("    {}    ->    {}\n", hex.join(" "), keyboard.join(" ").trim_end());
+    }
+
+    description += "\nSpecial keys\n";
+    for (keycode, action) in SPECIAL_KEYS {
+        description += &format!("    {:<8} {}\n", keycode.name(), action);
+    }
+    for (keycode, action) in QUIRK_KEYS {
+        description += &format!("    {:<8} {}\n", format!("Ctrl+{}", keycode.name()), action);
+    }
+
+    description
+}
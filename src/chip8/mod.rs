@@ -1,16 +1,59 @@
+// The SDL-free API for embedding the VM, used as chip8::core::Core
+pub mod core;
+
 // This is private
+mod conformance;
 mod cpu; // Promise chip8 is defined either in `./cpu.rs` or `./cpu/mod.rs`,
+mod disasm;
+#[cfg(feature = "sdl")]
+mod emulator;
+#[cfg(feature = "sdl")]
 mod graphics; // etc.
+mod instruction;
+#[cfg(feature = "sdl")]
+mod keymap;
 mod keypad;
+mod quirks;
+mod renderer;
+mod replay;
+mod rewind;
+mod savestate;
+mod screen;
+mod screenshot;
+mod selftest;
 mod sound;
+#[cfg(feature = "sdl")]
+mod terminal;
 mod timer;
+mod trace;
+mod view;
 
 // Re-export cpu's functions and structs
+pub use conformance::*;
 pub use cpu::*; // Bring all symbols in scope, which we promise the `cpu` module exports.
+pub use disasm::*;
+#[cfg(feature = "sdl")]
+pub use emulator::*;
+#[cfg(feature = "sdl")]
 pub use graphics::*; // etc.
+pub use instruction::*;
+#[cfg(feature = "sdl")]
+pub use keymap::*;
 pub use keypad::*;
+pub use quirks::*;
+pub use renderer::*;
+pub use replay::*;
+pub use rewind::*;
+pub use savestate::*;
+pub use screen::*;
+pub use screenshot::*;
+pub use selftest::*;
 pub use sound::*;
+#[cfg(feature = "sdl")]
+pub use terminal::*;
 pub use timer::*;
+pub use trace::*;
+pub use view::*;
 
 // https://fasterthanli.me/articles/rust-modules-vs-files
 
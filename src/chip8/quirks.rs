@@ -0,0 +1,180 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::config;
+
+/// Behaviors that differ between CHIP-8 implementations. They're shared between the subsystems
+/// behind an Rc<RefCell<>> instead of being read from the config, so they can be toggled
+/// while the game runs to find out which combination a ROM expects
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    pub clip_mode : ClipMode, // what happens to sprites crossing the borders of the screen
+    pub draw_cost : bool, // DXYN stalls the cpu for a cycle per sprite row, like on slower hardware
+    pub display_wait : bool, // DXYN waits for the next 60hz frame (the vertical blank) before drawing, like on the VIP
+    pub wrap_start_coord : bool, // the sprite origin wraps around the screen even when the rest of it is clipped
+    pub shift_uses_vy : bool, // 8XY6 and 8XYE shift VY into VX, instead of shifting VX in place
+    pub jump_uses_vx : bool, // BNNN is read as BXNN and jumps to XNN + VX, like SUPER-CHIP, instead of NNN + V0
+    pub increment_i_on_store : bool, // FX55 and FX65 leave I pointing past the last register, instead of unchanged
+    pub vf_reset : bool, // 8XY1, 8XY2 and 8XY3 clear VF, as a side effect of how the VIP ran them
+    pub xo_chip_audio : bool, // F002 and FX3A load a pattern and a pitch to play instead of the beep
+    pub xo_chip_planes : bool, // FN01 selects the bit-planes that DXYN, 00E0 and the scrolls work on, for 4 colors
+}
+
+/// Sets of quirks matching the documented behavior of each platform.
+/// Flags given explicitly still override what the profile sets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    CosmacVip,
+    Chip48,
+    Schip,
+    XoChip,
+}
+
+/// Whether sprites that cross the borders of the screen wrap around to the other side or are
+/// cut off. Kept apart from the old wrapping flag, since SCHIP and XO-CHIP don't agree on it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipMode {
+    Wrap,
+    Clip,
+}
+
+impl Quirks {
+    pub fn from_config(config : &config::Config) -> Quirks {
+        let mut quirks = match config.profile() {
+            Some(profile) => Quirks::for_profile(profile),
+            None => Quirks::default()
+        };
+
+        if let Some(clip_mode) = config.clip_mode() {
+            quirks.clip_mode = clip_mode;
+        }
+
+        if config.shift_quirk() {
+            quirks.shift_uses_vy = true;
+        }
+
+        if config.jump_quirk() {
+            quirks.jump_uses_vx = true;
+        }
+
+        if config.load_store_quirk() {
+            quirks.increment_i_on_store = true;
+        }
+
+        if config.vf_reset() {
+            quirks.vf_reset = true;
+        }
+
+        if config.vblank_quirk() {
+            quirks.display_wait = true;
+        }
+
+        if config.no_wrap_start() {
+            quirks.wrap_start_coord = false;
+        }
+
+        quirks
+    }
+
+    pub fn for_profile(profile : Profile) -> Quirks {
+        match profile {
+            // Drawing waited for the display on the VIP, so it took a noticeable amount of time
+            Profile::CosmacVip => Quirks { draw_cost : true, shift_uses_vy : true, increment_i_on_store : true, vf_reset : true, ..Quirks::default() },
+            Profile::Chip48 => Quirks { jump_uses_vx : true, ..Quirks::default() },
+            Profile::Schip => Quirks { jump_uses_vx : true, ..Quirks::default() },
+            Profile::XoChip => Quirks { clip_mode : ClipMode::Wrap, shift_uses_vy : true, increment_i_on_store : true, xo_chip_audio : true, xo_chip_planes : true, ..Quirks::default() },
+        }
+    }
+}
+
+impl Default for Quirks {
+    /// What the VM did before quirks could be changed, except for the origin of sprites
+    /// wrapping, which all the platforms do
+    fn default() -> Self {
+        Quirks {
+            clip_mode : ClipMode::Clip,
+            draw_cost : false,
+            display_wait : false,
+            wrap_start_coord : true,
+            shift_uses_vy : false,
+            jump_uses_vx : false,
+            increment_i_on_store : false,
+            vf_reset : false,
+            xo_chip_audio : false,
+            xo_chip_planes : false,
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(src : &str) -> Result<Profile, String> {
+        match src {
+            "cosmac-vip" => Ok(Profile::CosmacVip),
+            "chip48" => Ok(Profile::Chip48),
+            "schip" => Ok(Profile::Schip),
+            "xo-chip" => Ok(Profile::XoChip),
+            _ => Err(format!("'{}' is not a profile, expected cosmac-vip, chip48, schip or xo-chip", src))
+        }
+    }
+}
+
+impl ClipMode {
+    /// The other mode, for toggling it while the game runs
+    pub fn toggled(self) -> ClipMode {
+        match self {
+            ClipMode::Wrap => ClipMode::Clip,
+            ClipMode::Clip => ClipMode::Wrap,
+        }
+    }
+}
+
+impl FromStr for ClipMode {
+    type Err = String;
+
+    fn from_str(src : &str) -> Result<ClipMode, String> {
+        match src {
+            "wrap" => Ok(ClipMode::Wrap),
+            "clip" => Ok(ClipMode::Clip),
+            _ => Err(format!("'{}' is not a clip mode, expected wrap or clip", src))
+        }
+    }
+}
+
+impl fmt::Display for ClipMode {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClipMode::Wrap => write!(f, "wrap"),
+            ClipMode::Clip => write!(f, "clip"),
+        }
+    }
+}
+
+impl Quirks {
+    /// The name and state of every quirk, as logged when one is toggled and shown while paused
+    pub fn states(&self) -> Vec<(&'static str, String)> {
+        let on_off = |enabled : bool| String::from(if enabled { "on" } else { "off" });
+
+        vec![
+            ("clip mode", self.clip_mode.to_string()),
+            ("wrap start", on_off(self.wrap_start_coord)),
+            ("draw cost", on_off(self.draw_cost)),
+            ("display wait", on_off(self.display_wait)),
+            ("shift uses VY", on_off(self.shift_uses_vy)),
+            ("jump uses VX", on_off(self.jump_uses_vx)),
+            ("increment I on load/store", on_off(self.increment_i_on_store)),
+            ("VF reset", on_off(self.vf_reset)),
+            ("XO-CHIP audio", on_off(self.xo_chip_audio)),
+            ("XO-CHIP planes", on_off(self.xo_chip_planes)),
+        ]
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let states : Vec<String> = self.states().iter().map(|(name, state)| format!("{} {}", name, state)).collect();
+
+        write!(f, "Quirks: {}", states.join(", "))
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use crate::chip8::quirks::Quirks;
+use crate::chip8::screen::{Framebuffer, Screen};
+use crate::chip8::screenshot;
+
+/// Called with the presented screen matrix every time a frame is presented
+pub type FrameHook = Box<dyn FnMut(&Framebuffer)>;
+
+// How many bytes the sprite preview shows, the tallest sprite DXYN can draw
+pub const SPRITE_PREVIEW_HEIGHT : usize = 15;
+
+/// What the backends that show the cpu state get on every draw
+pub struct CpuState<'s> {
+    pub v : &'s [u8; 16],
+    pub i : usize,
+    pub pc : usize,
+    pub timers : (u8, u8), // (delay, sound), a copy so the timer thread isn't kept waiting on the lock
+    pub stack : &'s [usize],
+    pub instr_log : &'s VecDeque<u16>, // the most recent one first
+    pub cycles_since_input : u64, // helps finding out whether a game stuck polling the keypad gets any input
+    pub ips : u32, // cycles actually run per second, which can fall short of the frequency
+    pub sprite : [u8; SPRITE_PREVIEW_HEIGHT], // memory starting at I, for the sprite preview
+}
+
+/// The display side of the VM, what the cpu draws on. Graphics is the SDL window,
+/// while NullGraphics keeps the screen in memory only
+pub trait Renderer {
+    /// The logical screen state, which might not have been presented yet
+    fn screen(&self) -> &Framebuffer;
+
+    /// The current (width, height), 64x32 or 128x64 in the SUPER-CHIP high resolution mode
+    fn resolution(&self) -> (usize, usize);
+
+    /// Switches to the 128x64 mode or back to 64x32, clearing the screen
+    fn set_hires(&mut self, hires : bool);
+
+    /// Clears the given XO-CHIP bit-planes, ALL_PLANES for the whole screen
+    fn clear_screen(&mut self, planes : u8);
+
+    /// Moves the contents of the given bit-planes by (dx, dy) pixels, clearing the ones that move in
+    fn scroll(&mut self, dx : isize, dy : isize, planes : u8);
+
+    /// If the coordinates are correct, XORs the value at (x,y), the bits of the planes to flip.
+    /// Returns 1 if the screen pixel has changed from set to unset, otherwise 0
+    fn set_pos(&mut self, x : usize, y : usize, val : u8) -> u8;
+
+    /// Presents the screen, along with the cpu state for the backends that show it
+    fn draw(&mut self, state : &CpuState);
+
+    /// Renders the last presented frame again, for when something other than the screen
+    /// changes while the game isn't drawing (such as pausing)
+    fn refresh(&mut self, state : &CpuState);
+
+    /// Replaces the screen, already presented, such as when undoing instructions
+    fn restore_screen(&mut self, screen : &Framebuffer);
+
+    /// Briefly shows a message over the game, such as the new frequency after changing it
+    fn show_message(&mut self, text : &str);
+
+    /// The presented game area as a PNG, in the colors and size it's shown at
+    fn screenshot(&self) -> Vec<u8>;
+
+    /// Sets a closure that will be called with the screen matrix after every presented frame,
+    /// useful for recording or analyzing the output without touching the rendering code
+    fn set_frame_hook(&mut self, hook : FrameHook);
+}
+
+/// A display that does no SDL work at all, for benchmarking and testing on machines
+/// without a display server. Collisions and clipping behave exactly like in Graphics
+pub struct NullGraphics {
+    screen : Screen,
+    quirks : Rc<RefCell<Quirks>>,
+    frame_hook : Option<FrameHook>,
+}
+
+impl NullGraphics {
+    pub fn new(quirks : Rc<RefCell<Quirks>>) -> NullGraphics {
+        NullGraphics {
+            screen : Screen::new(),
+            quirks,
+            frame_hook : None,
+        }
+    }
+}
+
+impl Renderer for NullGraphics {
+    fn screen(&self) -> &Framebuffer {
+        self.screen.back()
+    }
+
+    fn resolution(&self) -> (usize, usize) {
+        self.screen.resolution()
+    }
+
+    fn set_hires(&mut self, hires : bool) {
+        self.screen.set_hires(hires);
+    }
+
+    fn clear_screen(&mut self, planes : u8) {
+        self.screen.clear(planes);
+    }
+
+    fn scroll(&mut self, dx : isize, dy : isize, planes : u8) {
+        self.screen.scroll(dx, dy, planes);
+    }
+
+    fn set_pos(&mut self, x : usize, y : usize, val : u8) -> u8 {
+        let clip_mode = self.quirks.borrow().clip_mode;
+        self.screen.set_pos(x, y, val, clip_mode)
+    }
+
+    fn draw(&mut self, _state : &CpuState) {
+        self.screen.present();
+
+        if let Some(hook) = self.frame_hook.as_mut() {
+            hook(self.screen.displayed());
+        }
+    }
+
+    fn refresh(&mut self, _state : &CpuState) {}
+
+    fn restore_screen(&mut self, screen : &Framebuffer) {
+        self.screen.restore(screen);
+    }
+
+    fn show_message(&mut self, _text : &str) {}
+
+    fn screenshot(&self) -> Vec<u8> {
+        // There are no colors or window scale without a window
+        screenshot::screenshot_png(self.screen.displayed(), self.screen.resolution(), 1, screenshot::GRAYSCALE)
+    }
+
+    fn set_frame_hook(&mut self, hook : FrameHook) {
+        self.frame_hook = Some(hook);
+    }
+}
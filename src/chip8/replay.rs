@@ -0,0 +1,135 @@
+// Input recordings, for replaying a run exactly (along with --seed and --deterministic-timers).
+// A recording has a line for every cycle in which the keypad changed, with the number of cycles
+// run before it and the pressed keys as 16 bits in hex, bit N being key N:
+//
+//      # Comments and empty lines are ignored
+//      0 0000
+//      1520 0020
+//      1610 0000
+//
+// The keys keep their state until the next line
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+/// Writes the keypad state to a recording every time it changes
+pub struct InputRecorder {
+    file : BufWriter<File>,
+    last : Option<u16>, // the state in the last line, nothing is written until it changes
+}
+
+impl InputRecorder {
+    pub fn create(path : &str) -> io::Result<InputRecorder> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "# <cycle> <pressed keys, bit N is key N>")?;
+
+        Ok(InputRecorder { file, last : None })
+    }
+
+    /// Called before every cycle with the keys the instruction will see
+    pub fn record(&mut self, cycle : u64, keys : u16) -> io::Result<()> {
+        if self.last == Some(keys) {
+            return Ok(());
+        }
+
+        self.last = Some(keys);
+        writeln!(self.file, "{} {:04x}", cycle, keys)
+    }
+}
+
+/// The keypad states of a recording, handed out as the cycles they were recorded at come up
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputReplay {
+    changes : Vec<(u64, u16)>, // (cycle, keys), in order
+    next : usize, // index of the next change to hand out
+}
+
+impl InputReplay {
+    pub fn from_path(path : &str) -> Result<InputReplay, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read recording {}: {}", path, e))?;
+
+        InputReplay::parse(&contents).map_err(|e| format!("Invalid recording {}: {}", path, e))
+    }
+
+    pub fn parse(contents : &str) -> Result<InputReplay, String> {
+        let mut changes : Vec<(u64, u16)> = Vec::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let (cycle, keys) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(cycle), Some(keys), None) => (cycle, keys),
+                _ => return Err(format!("line {}: expected `<cycle> <keys>`", number + 1))
+            };
+
+            let cycle = cycle.parse::<u64>()
+                .map_err(|_| format!("line {}: '{}' is not a cycle number", number + 1, cycle))?;
+            let keys = u16::from_str_radix(keys, 16)
+                .map_err(|_| format!("line {}: '{}' is not a keypad state, expected 4 hex digits", number + 1, keys))?;
+
+            if changes.last().is_some_and(|&(last, _)| cycle <= last) {
+                return Err(format!("line {}: cycle {} doesn't come after the one on the previous line", number + 1, cycle));
+            }
+
+            changes.push((cycle, keys));
+        }
+
+        Ok(InputReplay { changes, next : 0 })
+    }
+
+    /// Returns the keys to switch to at this cycle, if the keypad changed then
+    pub fn keys_at(&mut self, cycle : u64) -> Option<u16> {
+        let mut keys = None;
+
+        while let Some(&(at, state)) = self.changes.get(self.next) {
+            if at > cycle {
+                break;
+            }
+
+            keys = Some(state);
+            self.next += 1;
+        }
+
+        keys
+    }
+
+    /// True once every change has been handed out
+    pub fn finished(&self) -> bool {
+        self.next >= self.changes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recordings_replay_the_same_keys() {
+        let path = std::env::temp_dir().join("chip-8-vm-recording-test.txt");
+        let path = path.to_str().unwrap();
+
+        let mut recorder = InputRecorder::create(path).unwrap();
+        for (cycle, keys) in [(0, 0x0000), (1, 0x0000), (2, 0x0020), (3, 0x0020), (4, 0x0000)] {
+            recorder.record(cycle, keys).unwrap();
+        }
+        drop(recorder);
+
+        let mut replay = InputReplay::from_path(path).unwrap();
+        let keys : Vec<Option<u16>> = (0..5).map(|cycle| replay.keys_at(cycle)).collect();
+        assert_eq!(keys, [Some(0x0000), None, Some(0x0020), None, Some(0x0000)]);
+        assert!(replay.finished());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn cycles_have_to_go_forward() {
+        assert_eq!(InputReplay::parse("5 0001\n5 0000"), Err(String::from("line 2: cycle 5 doesn't come after the one on the previous line")));
+        assert!(InputReplay::parse("5 zz").unwrap_err().starts_with("line 1: "));
+    }
+}
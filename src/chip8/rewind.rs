@@ -0,0 +1,72 @@
+// Keeps the machine state from before each of the last executed instructions, so they can be
+// undone one at a time. Every snapshot holds a full copy of the memory and the screen, a bit
+// over 12KB, so a depth of 1000 instructions (under 2 seconds at the default speed) takes
+// around 12MB. Copying that much on every cycle also slows the VM down, which is why
+// it's only enabled with --rewind-depth
+
+use std::collections::VecDeque;
+
+use crate::chip8::screen::Framebuffer;
+
+/// Everything an instruction can change
+#[derive(Clone)]
+pub struct Snapshot {
+    pub memory : [u8; 4096],
+    pub v : [u8; 16],
+    pub i : usize,
+    pub pc : usize,
+    pub stack : Vec<usize>,
+    pub rpl_flags : [u8; 8],
+    pub timers : (u8, u8),
+    pub screen : Framebuffer,
+    pub hires : bool,
+    pub planes : u8, // the XO-CHIP bit-planes selected with FN01
+    pub instr_log : VecDeque<u16>,
+    pub counters : RunCounters,
+}
+
+/// How far the run has gone, so going back and running forward again counts the same cycles,
+/// waits as long and follows --compare-trace from the same step. Save states don't keep it
+#[derive(Clone, Copy, Default)]
+pub struct RunCounters {
+    pub cycles : u64,
+    pub cycles_since_input : u64,
+    pub vblank_wait : Option<u64>, // the frame a waiting DXYN started waiting in
+    pub trace_position : Option<usize>, // the next step of the reference trace
+}
+
+pub struct Rewind {
+    snapshots : VecDeque<Snapshot>, // the most recent one last
+    depth : usize,
+}
+
+impl Rewind {
+    pub fn new(depth : usize) -> Rewind {
+        Rewind {
+            snapshots : VecDeque::with_capacity(depth),
+            depth,
+        }
+    }
+
+    /// Keeps the snapshot, dropping the oldest one if there are already `depth` of them
+    pub fn push(&mut self, snapshot : Snapshot) {
+        if self.snapshots.len() == self.depth {
+            self.snapshots.pop_front();
+        }
+
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Takes the most recent snapshot
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        self.snapshots.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
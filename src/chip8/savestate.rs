@@ -0,0 +1,151 @@
+// Save states keep a rewind Snapshot on disk, so a game can be picked up later from the same point.
+// The format is a fixed header followed by the state, with the addresses in big endian:
+//
+//      "CHIP8SAV" + format version (1 byte)
+//      memory (4096 bytes), V0-VF (16), I (2), PC (2)
+//      stack depth (1) + the return addresses (2 each)
+//      RPL flags (8), delay and sound timers (2), high resolution (1), selected planes (1)
+//      screen (128x64 bytes, one per pixel, 0 to 3 with a bit per XO-CHIP plane)
+//
+// Version 1 didn't have the planes, its states are still loaded with the first plane selected.
+// The instruction log isn't saved, it starts empty after loading
+
+use std::fs;
+use std::io;
+
+use crate::chip8::rewind::Snapshot;
+use crate::chip8::screen::{Framebuffer, ALL_PLANES, HIRES_HEIGHT, HIRES_WIDTH};
+
+const MAGIC : &[u8] = b"CHIP8SAV";
+const VERSION : u8 = 2;
+
+// Anything deeper doesn't fit in the byte used for the depth, and no game gets close
+const MAX_STACK_DEPTH : usize = 255;
+
+pub fn write_state(snapshot : &Snapshot, path : &str) -> io::Result<()> {
+    if snapshot.stack.len() > MAX_STACK_DEPTH {
+        return Err(invalid_data(format!("the stack is {} levels deep, only {} fit in a save state", snapshot.stack.len(), MAX_STACK_DEPTH)));
+    }
+
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 4096 + HIRES_WIDTH * HIRES_HEIGHT + 64);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&snapshot.memory);
+    bytes.extend_from_slice(&snapshot.v);
+    bytes.extend_from_slice(&(snapshot.i as u16).to_be_bytes());
+    bytes.extend_from_slice(&(snapshot.pc as u16).to_be_bytes());
+    bytes.push(snapshot.stack.len() as u8);
+    for addr in snapshot.stack.iter() {
+        bytes.extend_from_slice(&(*addr as u16).to_be_bytes());
+    }
+    bytes.extend_from_slice(&snapshot.rpl_flags);
+    bytes.push(snapshot.timers.0);
+    bytes.push(snapshot.timers.1);
+    bytes.push(snapshot.hires as u8);
+    bytes.push(snapshot.planes);
+    for row in snapshot.screen.iter() {
+        bytes.extend_from_slice(row);
+    }
+
+    fs::write(path, bytes)
+}
+
+/// Fails with InvalidData if the file isn't a save state, is from another version or is cut short
+pub fn read_state(path : &str) -> io::Result<Snapshot> {
+    let bytes = fs::read(path)?;
+    let mut reader = Reader { bytes : &bytes, pos : 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(invalid_data(String::from("not a save state")));
+    }
+
+    let version = reader.byte()?;
+    if ! (1..=VERSION).contains(&version) {
+        return Err(invalid_data(format!("save state version {}, but only versions 1 to {} are supported", version, VERSION)));
+    }
+
+    let mut memory = [0; 4096];
+    memory.copy_from_slice(reader.take(4096)?);
+    let mut v = [0; 16];
+    v.copy_from_slice(reader.take(16)?);
+    let i = reader.address()?;
+    let pc = reader.address()?;
+
+    let depth = reader.byte()? as usize;
+    let mut stack = Vec::with_capacity(depth);
+    for _ in 0..depth {
+        stack.push(reader.address()?);
+    }
+
+    let mut rpl_flags = [0; 8];
+    rpl_flags.copy_from_slice(reader.take(8)?);
+    let timers = (reader.byte()?, reader.byte()?);
+    let hires = reader.byte()? != 0;
+    let planes = if version >= 2 { reader.byte()? } else { 1 };
+    if planes > ALL_PLANES {
+        return Err(invalid_data(format!("{} is not a set of planes", planes)));
+    }
+
+    let mut screen : Framebuffer = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+    for row in screen.iter_mut() {
+        row.copy_from_slice(reader.take(HIRES_WIDTH)?);
+    }
+    if screen.iter().flatten().any(|&pixel| pixel > ALL_PLANES) {
+        return Err(invalid_data(String::from("the screen has pixels other than 0 to 3")));
+    }
+
+    if reader.pos != bytes.len() {
+        return Err(invalid_data(format!("{} unexpected bytes at the end", bytes.len() - reader.pos)));
+    }
+
+    Ok(Snapshot {
+        memory,
+        v,
+        i,
+        pc,
+        stack,
+        rpl_flags,
+        timers,
+        screen,
+        hires,
+        planes,
+        instr_log : Default::default(),
+        counters : Default::default(),
+    })
+}
+
+fn invalid_data(message : String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the file from start to end, failing if it's shorter than expected
+struct Reader<'b> {
+    bytes : &'b [u8],
+    pos : usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len : usize) -> io::Result<&'b [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid_data(String::from("the file is cut short")))?;
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Addresses are 12 bits, anything above doesn't come from a save state
+    fn address(&mut self) -> io::Result<usize> {
+        let bytes = self.take(2)?;
+        let addr = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+
+        if addr > 0xFFF {
+            return Err(invalid_data(format!("{:#06x} is not a valid address", addr)));
+        }
+
+        Ok(addr)
+    }
+}
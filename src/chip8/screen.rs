@@ -0,0 +1,264 @@
+// The screen matrix, kept apart from the SDL rendering so it can be used (and tested) on its own.
+// It's double-buffered: instructions only modify the back buffer, which is copied into the
+// displayed one when presenting, so what's on screen is never a half-drawn frame.
+// The buffers are sized for the SUPER-CHIP 128x64 high resolution mode, in the usual
+// 64x32 mode only the top left corner of them is used.
+// Every pixel holds a bit per XO-CHIP bit-plane, bit 0 for the first plane and bit 1 for the
+// second one, so it's 0 to 3. Games that don't select planes only ever use the first one
+
+use crate::chip8::quirks::ClipMode;
+
+pub const LORES_WIDTH : usize = 64;
+pub const LORES_HEIGHT : usize = 32;
+pub const HIRES_WIDTH : usize = 128;
+pub const HIRES_HEIGHT : usize = 64;
+
+// The bits of both bit-planes, the ones FX01 selects from
+pub const ALL_PLANES : u8 = 0b11;
+
+/// A whole screen, indexed by row and then column
+pub type Framebuffer = [[u8; HIRES_WIDTH]; HIRES_HEIGHT];
+
+pub struct Screen {
+    back : Framebuffer, // what the instructions draw on
+    displayed : Framebuffer, // what gets rendered, only updated by present()
+    hires : bool, // 128x64 instead of 64x32, toggled by 00FF and 00FE
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen {
+            back : [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            displayed : [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            hires : false,
+        }
+    }
+
+    /// The logical screen state, including changes that haven't been presented yet
+    pub fn back(&self) -> &Framebuffer {
+        &self.back
+    }
+
+    /// The last presented frame
+    pub fn displayed(&self) -> &Framebuffer {
+        &self.displayed
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switches between the 128x64 and 64x32 modes. Like most SUPER-CHIP interpreters,
+    /// switching clears the screen, as the old contents wouldn't make sense in the new one
+    pub fn set_hires(&mut self, hires : bool) {
+        self.hires = hires;
+        self.clear(ALL_PLANES);
+    }
+
+    /// The current (width, height) in pixels
+    pub fn resolution(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (LORES_WIDTH, LORES_HEIGHT)
+        }
+    }
+
+    /// Copies the back buffer into the displayed one
+    pub fn present(&mut self) {
+        self.displayed = self.back;
+    }
+
+    /// Replaces both buffers with the given screen, e.g. when going back to an older state
+    pub fn restore(&mut self, screen : &Framebuffer) {
+        self.back = *screen;
+        self.displayed = *screen;
+    }
+
+    /// Clears the given bit-planes, leaving the other one as it is
+    pub fn clear(&mut self, planes : u8) {
+        for row in self.back.iter_mut() {
+            for col in row.iter_mut() {
+                *col &= ! planes;
+            }
+        }
+    }
+
+    /// Moves the contents of the given bit-planes by (dx, dy) pixels, positive values going right and down.
+    /// Pixels moved off the screen are lost, and the ones moved in are cleared
+    pub fn scroll(&mut self, dx : isize, dy : isize, planes : u8) {
+        let (width, height) = self.resolution();
+        let old = self.back;
+
+        for y in 0..height {
+            for x in 0..width {
+                let from_x = x as isize - dx;
+                let from_y = y as isize - dy;
+
+                let inside = (0..width as isize).contains(&from_x) && (0..height as isize).contains(&from_y);
+                let moved = if inside { old[from_y as usize][from_x as usize] } else { 0 };
+                self.back[y][x] = (moved & planes) | (old[y][x] & ! planes);
+            }
+        }
+    }
+
+    /// If the coordinates are correct, XORs the value at (x,y), the bits of the planes to flip.
+    /// Returns 1 if any of them has changed from set to unset, otherwise 0
+    pub fn set_pos(&mut self, x : usize, y : usize, val : u8, clip_mode : ClipMode) -> u8 {
+        let mut changed = 0;
+        let (width, height) = self.resolution();
+
+        if clip_mode == ClipMode::Clip {
+            if (0..width).contains(&x) && (0..height).contains(&y) {
+                changed = self.back[y][x]; // y is indexed first, it's a 2d array!
+                // The value is XOR'd into the screen
+                self.back[y][x] ^= val;
+
+                // And the changed flag is activated if the pixel is
+                // unset, which only happens if both values were 1 due to
+                // the XOR operation
+                changed &= val;
+            }
+        } else { // We mod the coordinates to the maximum values and thus wrap them
+            changed = self.back[y % height][x % width]; // y is indexed first, it's a 2d array!
+            // The value is XOR'd into the screen
+            self.back[y % height][x % width] ^= val;
+
+            // And the changed flag is activated if the pixel is
+            // unset, which only happens if both values were 1 due to
+            // the XOR operation
+            changed &= val;
+        }
+
+        (changed != 0) as u8
+    }
+}
+
+/// Turns a screen matrix into ASCII art, `#` for set pixels and spaces for unset ones,
+/// a line per row. Handy for bug reports and comparing screens by eye.
+/// Only the top left width x height pixels are included, the ones used by the current resolution
+pub fn screen_to_ascii(screen : &Framebuffer, (width, height) : (usize, usize)) -> String {
+    let mut ascii = String::with_capacity((width + 1) * height);
+
+    for row in screen.iter().take(height) {
+        ascii.extend(row[..width].iter().map(|&pixel| if pixel == 0 { ' ' } else { '#' }));
+        ascii.push('\n');
+    }
+
+    ascii
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Screen::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displayed_buffer_only_changes_on_present() {
+        let mut screen = Screen::new();
+
+        screen.set_pos(3, 4, 1, ClipMode::Clip);
+        assert_eq!(screen.back()[4][3], 1);
+        assert_eq!(screen.displayed()[4][3], 0);
+
+        screen.present();
+        assert_eq!(screen.displayed()[4][3], 1);
+
+        screen.clear(ALL_PLANES);
+        assert_eq!(screen.back()[4][3], 0);
+        assert_eq!(screen.displayed()[4][3], 1);
+
+        screen.present();
+        assert_eq!(screen.displayed()[4][3], 0);
+    }
+
+    #[test]
+    fn set_pos_reports_collisions() {
+        let mut screen = Screen::new();
+
+        assert_eq!(screen.set_pos(0, 0, 1, ClipMode::Clip), 0);
+        assert_eq!(screen.set_pos(0, 0, 1, ClipMode::Clip), 1);
+        assert_eq!(screen.back()[0][0], 0);
+    }
+
+    #[test]
+    fn ascii_has_a_line_per_row() {
+        let mut screen = Screen::new();
+        screen.set_pos(0, 0, 1, ClipMode::Clip);
+        screen.set_pos(63, 31, 1, ClipMode::Clip);
+
+        let ascii = screen_to_ascii(screen.back(), screen.resolution());
+        let lines : Vec<&str> = ascii.lines().collect();
+
+        assert_eq!(lines.len(), 32);
+        assert_eq!(lines[0], format!("#{}", " ".repeat(63)));
+        assert_eq!(lines[31], format!("{}#", " ".repeat(63)));
+    }
+
+    #[test]
+    fn set_pos_clips_or_wraps() {
+        let mut screen = Screen::new();
+
+        assert_eq!(screen.set_pos(64, 32, 1, ClipMode::Clip), 0);
+        assert!(screen.back().iter().flatten().all(|&pixel| pixel == 0));
+
+        screen.set_pos(65, 33, 1, ClipMode::Wrap);
+        assert_eq!(screen.back()[1][1], 1);
+    }
+
+    #[test]
+    fn hires_uses_the_whole_buffer() {
+        let mut screen = Screen::new();
+        screen.set_pos(3, 4, 1, ClipMode::Clip);
+
+        screen.set_hires(true);
+        assert_eq!(screen.resolution(), (128, 64));
+        assert_eq!(screen.back()[4][3], 0);
+
+        assert_eq!(screen.set_pos(127, 63, 1, ClipMode::Clip), 0);
+        assert_eq!(screen.back()[63][127], 1);
+
+        screen.set_pos(129, 65, 1, ClipMode::Wrap);
+        assert_eq!(screen.back()[1][1], 1);
+    }
+
+    #[test]
+    fn scroll_clears_what_moves_in() {
+        let mut screen = Screen::new();
+        screen.set_pos(0, 0, 1, ClipMode::Clip);
+        screen.set_pos(63, 31, 1, ClipMode::Clip);
+
+        screen.scroll(4, 2, ALL_PLANES);
+        assert_eq!(screen.back()[2][4], 1);
+        assert_eq!(screen.back()[0][0], 0);
+        assert_eq!(screen.back().iter().flatten().filter(|&&pixel| pixel == 1).count(), 1);
+
+        screen.scroll(-4, 0, ALL_PLANES);
+        assert_eq!(screen.back()[2][0], 1);
+    }
+
+    #[test]
+    fn planes_are_drawn_cleared_and_scrolled_on_their_own() {
+        let mut screen = Screen::new();
+        screen.set_pos(0, 0, 0b01, ClipMode::Clip);
+        screen.set_pos(0, 0, 0b10, ClipMode::Clip);
+        screen.set_pos(1, 0, 0b10, ClipMode::Clip);
+        assert_eq!(screen.back()[0][..3], [0b11, 0b10, 0]);
+
+        // Only the bits of the flipped planes count as collisions
+        assert_eq!(screen.set_pos(1, 0, 0b01, ClipMode::Clip), 0);
+        assert_eq!(screen.set_pos(1, 0, 0b11, ClipMode::Clip), 1);
+        assert_eq!(screen.back()[0][1], 0);
+
+        screen.scroll(1, 0, 0b10);
+        assert_eq!(screen.back()[0][..3], [0b01, 0b10, 0]);
+
+        screen.clear(0b01);
+        assert_eq!(screen.back()[0][..3], [0, 0b10, 0]);
+    }
+}
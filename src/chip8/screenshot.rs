@@ -0,0 +1,135 @@
+// Screenshots of the game area as PNG files. The images are tiny and mostly flat colors, so
+// they're written without any compression (zlib stored blocks), which keeps the encoder small
+// enough to not need an image library
+
+use crate::chip8::screen::{Framebuffer, LORES_HEIGHT, LORES_WIDTH};
+use crate::theme::Rgb;
+
+const PNG_SIGNATURE : [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// The most a stored deflate block can hold
+const MAX_STORED_BLOCK : usize = 0xFFFF;
+
+/// Black and white, with grays for the second XO-CHIP bit-plane, for the screenshots without a theme
+pub const GRAYSCALE : [Rgb; 4] = [(0, 0, 0), (255, 255, 255), (85, 85, 85), (170, 170, 170)];
+
+/// Encodes the screen as a PNG of the game area, with every CHIP-8 pixel taking scale x scale
+/// image pixels in the 64x32 mode (half that in the 128x64 one, like in the window).
+/// The palette has a color per pixel value, 0 being the background
+pub fn screenshot_png(screen : &Framebuffer, resolution : (usize, usize), scale : u32, palette : [Rgb; 4]) -> Vec<u8> {
+    let (width, height) = resolution;
+    let image_width = LORES_WIDTH * scale as usize;
+    let image_height = LORES_HEIGHT * scale as usize;
+
+    let mut rgb = Vec::with_capacity(image_width * image_height * 3);
+    for y in 0..image_height {
+        for x in 0..image_width {
+            let pixel = screen[y * height / image_height][x * width / image_width];
+            let (r, g, b) = palette[pixel as usize & 3];
+
+            rgb.extend_from_slice(&[r, g, b]);
+        }
+    }
+
+    encode_png(image_width as u32, image_height as u32, &rgb)
+}
+
+/// Encodes 8-bit RGB pixels, row by row, as a PNG
+fn encode_png(width : u32, height : u32, rgb : &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bits per channel, RGB, no interlacing
+
+    // Every row starts with its filter type, 0 is none
+    let mut rows = Vec::with_capacity(rgb.len() + height as usize);
+    for row in rgb.chunks(width as usize * 3) {
+        rows.push(0);
+        rows.extend_from_slice(row);
+    }
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&rows));
+    write_chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+fn write_chunk(png : &mut Vec<u8>, kind : &[u8; 4], data : &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let mut checked = kind.to_vec();
+    checked.extend_from_slice(data);
+    png.extend_from_slice(&crc32(&checked).to_be_bytes());
+}
+
+/// Wraps the data in a zlib stream without compressing it
+fn zlib_stored(data : &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+
+    let blocks : Vec<&[u8]> = data.chunks(MAX_STORED_BLOCK).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        let len = block.len() as u16;
+
+        stream.push((i + 1 == blocks.len()) as u8); // the last block is marked as final
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(! len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32(data : &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    ! crc
+}
+
+fn adler32(data : &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_the_reference_values() {
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn screenshots_cover_the_game_area() {
+        let mut screen = [[0; 128]; 64];
+        screen[0][0] = 1;
+        let png = screenshot_png(&screen, (64, 32), 2, GRAYSCALE);
+
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(png[16..24], [0, 0, 0, 128, 0, 0, 0, 64]); // 64x32 scaled by 2
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+
+        // The first row is the filter type, then the 2 image pixels of the set one
+        let idat = 8 + 25 + 8 + 2 + 5;
+        assert_eq!(png[idat..idat + 10], [0, 255, 255, 255, 255, 255, 255, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,65 @@
+// A tiny built-in ROM used to check the whole fetch/decode/draw pipeline without needing
+// an external ROM file. It clears the screen, sets a few registers and draws the "A" glyph
+// from the built-in font at (10, 5), then loops forever
+
+use crate::chip8::cpu::Cpu;
+use crate::chip8::screen::Framebuffer;
+
+pub const SELFTEST_ROM : [u8; 14] = [
+    0x00, 0xe0, // 0x200: CLS
+    0x60, 0x0a, // 0x202: LD V0, 0x0a   (x coordinate)
+    0x61, 0x05, // 0x204: LD V1, 0x05   (y coordinate)
+    0x62, 0x0a, // 0x206: LD V2, 0x0a   (the glyph to draw)
+    0xf2, 0x29, // 0x208: LD F, V2
+    0xd0, 0x15, // 0x20a: DRW V0, V1, 5
+    0x12, 0x0c, // 0x20c: JP 0x20c
+];
+
+// Enough cycles to reach the final jump
+const SELFTEST_CYCLES : usize = 8;
+
+// Where the glyph should end up, and what it looks like (the font's "A")
+const GLYPH_X : usize = 10;
+const GLYPH_Y : usize = 5;
+const GLYPH : [u8; 5] = [0xF0, 0x90, 0xF0, 0x90, 0x90];
+
+/// Loads the self-test ROM into the cpu, runs it and checks the resulting screen and registers.
+/// Returns a description of the first mismatch found, if any
+pub fn run_selftest(cpu : &mut Cpu) -> Result<(), String> {
+    cpu.load_rom_from_bytes(&SELFTEST_ROM);
+
+    for _ in 0..SELFTEST_CYCLES {
+        cpu.cycle();
+    }
+
+    if cpu.pc() != 0x20c {
+        return Err(format!("expected the PC to be stuck at 0x20c, but it is {:#06x}", cpu.pc()));
+    }
+
+    if cpu.v()[0x0f] != 0 {
+        return Err("VF is set, but nothing should have collided".to_string());
+    }
+
+    check_screen(cpu.screen())
+}
+
+/// Checks that the screen contains the glyph at the expected position and nothing else
+pub fn check_screen(screen : &Framebuffer) -> Result<(), String> {
+    for (y, row) in screen.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            let in_glyph = (GLYPH_X..GLYPH_X + 8).contains(&x) && (GLYPH_Y..GLYPH_Y + GLYPH.len()).contains(&y);
+
+            let expected = if in_glyph {
+                (GLYPH[y - GLYPH_Y] >> (7 - (x - GLYPH_X))) & 0b00000001
+            } else {
+                0
+            };
+
+            if pixel != expected {
+                return Err(format!("pixel ({}, {}) is {}, expected {}", x, y, pixel, expected));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,16 +1,120 @@
 /// https://github.com/starrhorne/chip8-rust/blob/master/src/drivers/audio_driver.rs
 /// https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/audio-squarewave.rs
 
-use sdl2;
+#[cfg(feature = "sdl")]
 use sdl2::audio::{AudioDevice, AudioCallback, AudioSpecDesired};
 
+#[cfg(feature = "sdl")]
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+#[cfg(feature = "sdl")]
+use log::warn;
+
+#[cfg(feature = "sdl")]
+use crate::config;
+
+// XO-CHIP plays its pattern at 4000 bits per second at this pitch, every 48 above or below
+// it doubles or halves the rate
+const XO_CHIP_BASE_PITCH: f32 = 64.0;
+const XO_CHIP_BASE_RATE: f32 = 4000.0;
+
+// How much every press of the volume keys changes the volume
+#[cfg(feature = "sdl")]
+const VOLUME_STEP: f32 = 0.05;
+
+/// XO-CHIP audio, a 128 bit pattern played in a loop at a programmable rate instead of the beep.
+/// Loaded with F002 and FX3A while the sound timer keeps deciding when there's sound
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioPattern {
+    pub pattern: [u8; 16],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    /// Playback rate in bits per second
+    pub fn rate(&self) -> f32 {
+        XO_CHIP_BASE_RATE * 2f32.powf((self.pitch as f32 - XO_CHIP_BASE_PITCH) / 48.0)
+    }
+
+    /// The bits are played starting from the MSB of the first byte
+    #[cfg(feature = "sdl")]
+    fn bit(&self, index: usize) -> bool {
+        (self.pattern[index / 8] >> (7 - index % 8)) & 1 == 1
+    }
+}
+
+impl Default for AudioPattern {
+    fn default() -> Self {
+        AudioPattern {
+            pattern: [0; 16],
+            pitch: XO_CHIP_BASE_PITCH as u8,
+        }
+    }
+}
+
+/// Shape of the beep, with --beep-wave. The square wave is the classic (and harshest) one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    /// The value of the wave at a phase between 0 and 1, between -1 and 1
+    #[cfg(feature = "sdl")]
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<Waveform, String> {
+        match src {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            _ => Err(format!("'{}' is not a waveform, expected square, sine or triangle", src))
+        }
+    }
+}
+
+#[cfg(feature = "sdl")]
 pub struct Sound {
-    device: AudioDevice<SquareWave>
+    device: Option<AudioDevice<Wave>>, // None if there's no audio, in which case beeping does nothing
+    enabled: bool, // master switch, toggled by the mute key
+    volume: f32, // changed with the volume keys, muting leaves it alone
 }
 
+#[cfg(feature = "sdl")]
 impl Sound {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
-        let audio_subsystem = sdl_context.audio().unwrap();
+    /// Opens the audio device, if that fails (e.g. on machines without sound hardware)
+    /// a warning is printed and the VM runs silently. When not enabled it starts muted.
+    /// The beep is played as set up with the --beep-* flags
+    pub fn new(sdl_context: &sdl2::Sdl, enabled: bool, config: &config::Config) -> Self {
+        match Sound::open_device(sdl_context, config) {
+            Ok(device) => Sound { device: Some(device), enabled, volume: config.beep_volume() },
+            Err(e) => {
+                warn!("Could not open the audio device, running without sound: {}", e);
+                Sound::disabled()
+            }
+        }
+    }
+
+    /// A sound subsystem that never opens the audio device
+    pub fn disabled() -> Self {
+        Sound { device: None, enabled: false, volume: 0.0 }
+    }
+
+    fn open_device(sdl_context: &sdl2::Sdl, config: &config::Config) -> Result<AudioDevice<Wave>, String> {
+        let audio_subsystem = sdl_context.audio()?;
 
         let desired_spec = AudioSpecDesired {
             freq: Some(44100),
@@ -18,43 +122,169 @@ impl Sound {
             samples: None, // default sample size
         };
 
-        let device = audio_subsystem
+        audio_subsystem
             .open_playback(None, &desired_spec, |spec| {
                 // initialize the audio callback
-                SquareWave {
-                    phase_inc: 240.0 / spec.freq as f32,
+                Wave {
+                    waveform: config.beep_wave(),
+                    phase_inc: config.beep_freq() / spec.freq as f32,
                     phase: 0.0,
-                    volume: 0.25,
+                    volume: config.beep_volume(),
+                    sample_rate: spec.freq as f32,
+                    pattern: None,
+                    position: 0.0,
                 }
             })
-            .unwrap();
+    }
 
-        Sound { device: device }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Mutes or unmutes the sound, muting also silences a beep that is already playing
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+
+        if ! enabled {
+            self.stop_beep();
+        }
     }
 
+    /// Turns the volume up (or down, for negative steps) by steps of VOLUME_STEP, between 0.0 and 1.0.
+    /// Returns the new volume
+    pub fn change_volume(&mut self, steps: i32) -> f32 {
+        // Rounded to the step, so going up and down again lands on the same values
+        let volume = ((self.volume / VOLUME_STEP).round() + steps as f32) * VOLUME_STEP;
+        self.volume = volume.clamp(0.0, 1.0);
+
+        if let Some(device) = &mut self.device {
+            device.lock().volume = self.volume;
+        }
+
+        self.volume
+    }
+
+    /// Does nothing while muted
     pub fn beep(&self) {
-        self.device.resume();
+        if ! self.enabled {
+            return;
+        }
+
+        if let Some(device) = &self.device {
+            device.resume();
+        }
+    }
+
+    /// Plays the XO-CHIP pattern instead of the beep from now on
+    pub fn set_pattern(&mut self, pattern: AudioPattern) {
+        if let Some(device) = &mut self.device {
+            device.lock().pattern = Some(pattern);
+        }
+    }
+
+    /// Goes back to the beep, e.g. after a reset
+    pub fn clear_pattern(&mut self) {
+        if let Some(device) = &mut self.device {
+            device.lock().clear_pattern();
+        }
     }
 
     pub fn stop_beep(&self) {
-        self.device.pause();
+        if let Some(device) = &self.device {
+            device.pause();
+        }
     }
 }
 
-struct SquareWave {
+#[cfg(feature = "sdl")]
+struct Wave {
+    waveform: Waveform,
     phase_inc: f32,
     phase: f32,
     volume: f32,
+    sample_rate: f32,
+    pattern: Option<AudioPattern>, // XO-CHIP audio, replaces the beep once set
+    position: f32, // bit of the pattern being played, with the fraction until the next one
+}
+
+#[cfg(feature = "sdl")]
+impl Wave {
+    fn clear_pattern(&mut self) {
+        self.pattern = None;
+        self.position = 0.0;
+    }
 }
 
-impl AudioCallback for SquareWave {
+#[cfg(feature = "sdl")]
+impl AudioCallback for Wave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = self.volume * if self.phase < 0.5 { 1.0 } else { -1.0 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+        match self.pattern {
+            // Play the pattern bits as the high and low parts of the wave
+            Some(pattern) => {
+                let position_inc = pattern.rate() / self.sample_rate;
+
+                for x in out.iter_mut() {
+                    *x = self.volume * if pattern.bit(self.position as usize % 128) { 1.0 } else { -1.0 };
+                    self.position = (self.position + position_inc) % 128.0;
+                }
+            },
+            // Generate the beep
+            None => {
+                for x in out.iter_mut() {
+                    *x = self.volume * self.waveform.sample(self.phase);
+                    self.phase = (self.phase + self.phase_inc) % 1.0;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sdl"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waveforms_go_from_minus_one_to_one() {
+        for waveform in [Waveform::Square, Waveform::Sine, Waveform::Triangle] {
+            let samples : Vec<f32> = (0..100).map(|i| waveform.sample(i as f32 / 100.0)).collect();
+
+            assert!(samples.iter().all(|sample| (-1.0..=1.0).contains(sample)), "{:?}", waveform);
+            assert!(samples.iter().any(|&sample| sample > 0.99) && samples.iter().any(|&sample| sample < -0.99), "{:?}", waveform);
         }
+
+        assert_eq!(Waveform::Triangle.sample(0.5), 1.0);
+        assert_eq!("sine".parse(), Ok(Waveform::Sine));
+    }
+
+    #[test]
+    fn volume_keys_stay_between_silent_and_full() {
+        let mut sound = Sound::disabled();
+
+        assert!((sound.change_volume(5) - 0.25).abs() < 1e-6);
+        assert_eq!(sound.change_volume(100), 1.0);
+        assert_eq!(sound.change_volume(-100), 0.0);
+    }
+
+    #[test]
+    fn clearing_the_pattern_brings_the_beep_back() {
+        let mut wave = Wave {
+            waveform: Waveform::Square,
+            phase_inc: 0.25,
+            phase: 0.0,
+            volume: 1.0,
+            sample_rate: 4000.0,
+            pattern: Some(AudioPattern::default()), // all zeroes, silent
+            position: 0.0,
+        };
+
+        let mut out = [0.0; 4];
+        wave.callback(&mut out);
+        assert_eq!(out, [-1.0; 4]);
+
+        wave.clear_pattern();
+        wave.callback(&mut out);
+        assert_eq!(out, [1.0, 1.0, -1.0, -1.0]);
     }
 }
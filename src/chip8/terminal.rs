@@ -0,0 +1,235 @@
+// Runs the VM in the terminal instead of an SDL window, for headless servers and SSH sessions.
+// The screen is printed with block characters, going back to the top left corner on every frame,
+// and the keys are read from stdin in raw mode. Terminals don't report key releases, so a key
+// counts as held for a little while after its last press (or repeat, while holding it down)
+
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sdl2::keyboard::Keycode;
+
+use crate::chip8::cpu::Cpu;
+use crate::chip8::keymap::Keymap;
+use crate::chip8::quirks::Quirks;
+use crate::chip8::renderer::{CpuState, FrameHook, Renderer};
+use crate::chip8::screen::{Framebuffer, Screen};
+use crate::chip8::screenshot;
+
+// How long a key stays pressed after the last byte for it, long enough to cover the delay
+// before the terminal starts repeating a key that's held down
+const KEY_HOLD : Duration = Duration::from_millis(500);
+
+const ESCAPE : u8 = 0x1b;
+const CTRL_C : u8 = 0x03;
+
+// What each pixel value is printed as, the XO-CHIP colors get lighter and darker shades
+const PIXEL_CHARS : [char; 4] = [' ', '█', '░', '▓'];
+
+/// Prints the screen to stdout, as `█` for set pixels and spaces for unset ones (and shades
+/// for the other bit-plane)
+pub struct TerminalGraphics {
+    screen : Screen,
+    quirks : Rc<RefCell<Quirks>>,
+    frame_hook : Option<FrameHook>,
+    printed : Option<Framebuffer>, // the last frame printed, nothing is printed until it changes
+    message : String, // shown under the screen
+}
+
+impl TerminalGraphics {
+    pub fn new(quirks : Rc<RefCell<Quirks>>) -> TerminalGraphics {
+        write_out("\x1b[2J\x1b[?25l"); // clear the terminal and hide the cursor
+
+        TerminalGraphics {
+            screen : Screen::new(),
+            quirks,
+            frame_hook : None,
+            printed : None,
+            message : String::new(),
+        }
+    }
+
+    fn print(&mut self) {
+        let frame = *self.screen.displayed();
+        if self.printed == Some(frame) {
+            return;
+        }
+
+        let (width, height) = self.screen.resolution();
+        let mut text = String::from("\x1b[H"); // back to the top left corner
+        for row in frame[..height].iter() {
+            text.extend(row[..width].iter().map(|&pixel| PIXEL_CHARS[pixel as usize & 3]));
+            text.push_str("\x1b[K\r\n"); // raw mode doesn't go back to the start of the line by itself
+        }
+        text.push_str(&self.message);
+        text.push_str("\x1b[J"); // clears what's left of a bigger previous frame
+
+        write_out(&text);
+        self.printed = Some(frame);
+    }
+}
+
+impl Drop for TerminalGraphics {
+    fn drop(&mut self) {
+        write_out("\x1b[?25h\r\n"); // show the cursor again
+    }
+}
+
+impl Renderer for TerminalGraphics {
+    fn screen(&self) -> &Framebuffer {
+        self.screen.back()
+    }
+
+    fn resolution(&self) -> (usize, usize) {
+        self.screen.resolution()
+    }
+
+    fn set_hires(&mut self, hires : bool) {
+        self.screen.set_hires(hires);
+    }
+
+    fn clear_screen(&mut self, planes : u8) {
+        self.screen.clear(planes);
+    }
+
+    fn scroll(&mut self, dx : isize, dy : isize, planes : u8) {
+        self.screen.scroll(dx, dy, planes);
+    }
+
+    fn set_pos(&mut self, x : usize, y : usize, val : u8) -> u8 {
+        let clip_mode = self.quirks.borrow().clip_mode;
+        self.screen.set_pos(x, y, val, clip_mode)
+    }
+
+    fn draw(&mut self, _state : &CpuState) {
+        self.screen.present();
+        self.print();
+
+        if let Some(hook) = self.frame_hook.as_mut() {
+            hook(self.screen.displayed());
+        }
+    }
+
+    fn refresh(&mut self, _state : &CpuState) {
+        self.print();
+    }
+
+    fn restore_screen(&mut self, screen : &Framebuffer) {
+        self.screen.restore(screen);
+        self.print();
+    }
+
+    fn show_message(&mut self, text : &str) {
+        self.message = text.to_string();
+        self.printed = None; // so it shows up on the next print
+    }
+
+    fn screenshot(&self) -> Vec<u8> {
+        screenshot::screenshot_png(self.screen.displayed(), self.screen.resolution(), 1, screenshot::GRAYSCALE)
+    }
+
+    fn set_frame_hook(&mut self, hook : FrameHook) {
+        self.frame_hook = Some(hook);
+    }
+}
+
+/// Reads the keys from stdin, which is switched to raw mode until this is dropped
+pub struct TerminalInput {
+    keymap : Keymap,
+    bytes : Receiver<Vec<u8>>, // what was typed, as it came in from the reading thread
+    held : [Option<Instant>; 16], // when each key was last typed
+    saved_mode : Option<String>, // the terminal settings to go back to
+}
+
+impl TerminalInput {
+    pub fn new(keymap : Keymap) -> TerminalInput {
+        let saved_mode = stty(&["-g"]);
+        stty(&["raw", "-echo"]);
+
+        // Reading blocks, so it's done in a thread that's just left behind when quitting
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+            while let Ok(len) = io::stdin().read(&mut buffer) {
+                if len == 0 || tx.send(buffer[..len].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        TerminalInput {
+            keymap,
+            bytes : rx,
+            held : [None; 16],
+            saved_mode,
+        }
+    }
+
+    /// Presses the keys typed since the last call and releases the ones that haven't been typed
+    /// for a while. Returns true if the user wants to quit, with Escape or Ctrl+C
+    pub fn poll(&mut self, cpu : &mut Cpu) -> bool {
+        let mut wants_to_quit = false;
+
+        for bytes in self.bytes.try_iter() {
+            match bytes.as_slice() {
+                [ESCAPE] => wants_to_quit = true,
+                [ESCAPE, ..] => {}, // arrows and other special keys come as escape sequences
+                _ => {
+                    for &byte in bytes.iter() {
+                        wants_to_quit |= byte == CTRL_C;
+
+                        let key = Keycode::from_i32(byte.to_ascii_lowercase() as i32)
+                            .and_then(|keycode| self.keymap.key_for(keycode));
+                        if let Some(key) = key {
+                            self.held[key] = Some(Instant::now());
+                            cpu.key_down(key);
+                        }
+                    }
+                }
+            }
+        }
+
+        for (key, held) in self.held.iter_mut().enumerate() {
+            if held.is_some_and(|since| since.elapsed() >= KEY_HOLD) {
+                *held = None;
+                cpu.key_up(key);
+            }
+        }
+
+        wants_to_quit
+    }
+}
+
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        match self.saved_mode.as_deref() {
+            Some(mode) => stty(&[mode]),
+            None => stty(&["sane"])
+        };
+    }
+}
+
+/// Writes straight to stdout, there's nowhere to report it if the terminal is gone
+fn write_out(text : &str) {
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(text.as_bytes()).and_then(|_| stdout.flush());
+}
+
+/// Runs stty on the terminal stdin is attached to, returning what it printed if it worked
+fn stty(args : &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
@@ -1,61 +1,206 @@
-use std::{thread, time};
+use std::thread;
 use std::sync::Mutex;
 use std::sync::Arc;
 use std::sync::mpsc::{TryRecvError};
+use std::time::{Duration, Instant};
+
+use log::info;
+
+// The timers tick at 60hz
+pub const TIMER_PERIOD : Duration = Duration::from_nanos(16666667);
 
 // a 60hz timer supposed to run in a thread, which updates the CPU timers
 pub struct Timer {
     timers : Arc<Mutex<(u8, u8)>>, // Shared timers between the CPU and this timer thread
     rx : std::sync::mpsc::Receiver<()>, // Receiving end of the channel between the main thread and this timer thread
-    must_beep : Arc<Mutex<bool>>    // We cannot bring the audio subsystem here due to sdl2
+    must_beep : Arc<Mutex<bool>>,   // We cannot bring the audio subsystem here due to sdl2
                                     // being limited to one thread, so as a workaround we set
                                     // off a flag
+    pause : Arc<Mutex<bool>>, // Shared pause flag, the timers are frozen while it's set
+    next_tick : Instant // Deadline of the next tick, always moved by exactly one period so the sleeps don't add up to drift
 }
 
 impl Timer {
-    pub fn new(timers : Arc<Mutex<(u8, u8)>>, rx : std::sync::mpsc::Receiver<()>, must_beep : Arc<Mutex<bool>>) -> Timer {
+    pub fn new(timers : Arc<Mutex<(u8, u8)>>, rx : std::sync::mpsc::Receiver<()>, must_beep : Arc<Mutex<bool>>, pause : Arc<Mutex<bool>>) -> Timer {
         Timer {
-            timers : timers,
-            rx : rx,
-            must_beep : must_beep
+            timers,
+            rx,
+            must_beep,
+            pause,
+            next_tick : Instant::now() + TIMER_PERIOD
         }
     }
 
-    /// Intended to be run as a thread, updates the timers emulating ~60hz cycles
+    /// Intended to be run as a thread, updates the timers at 60hz. It sleeps until the next
+    /// deadline instead of for a fixed period, which would run slow by however long the rest
+    /// of the loop takes
     pub fn run(&mut self) {
         loop {
             // Check if we should end
             match self.rx.try_recv() {
                 Ok(_) | Err(TryRecvError::Disconnected) => {
-                    println!("Terminating timer subsystem...");
+                    info!("Terminating timer subsystem...");
                     break;
                 }
 
                 Err(TryRecvError::Empty) => {}
             }
-    
 
-            if let Ok(mut timers) = self.timers.lock() {
-                let (mut delay_timer, mut sound_timer) = *timers;
+            // While paused the timers are frozen and the beep is silenced, it will
+            // resume on the next tick after unpausing if the sound timer is still running
+            if *self.pause.lock().unwrap() {
+                * self.must_beep.lock().unwrap() = false;
+                thread::sleep(TIMER_PERIOD);
+                self.next_tick = Instant::now() + TIMER_PERIOD; // the paused time isn't caught up on
+                continue;
+            }
 
-                if delay_timer > 0 {
-                    delay_timer -= 1;
-                }
-                
-                if sound_timer > 0 {
-                    sound_timer -= 1;
-                    // The system should beep once the sound timer gets to 0
-                    if sound_timer != 0 {
-                        * self.must_beep.lock().unwrap() = true;
-                    } else {
-                        * self.must_beep.lock().unwrap() = false;
+            // Usually once, but the ticks that were missed (e.g. the thread didn't get to run) are caught up on
+            let ticks = ticks_due(&mut self.next_tick, Instant::now());
+            if ticks > 0 {
+                if let Ok(mut timers) = self.timers.lock() {
+                    for _ in 0..ticks {
+                        * self.must_beep.lock().unwrap() = tick(&mut timers);
                     }
                 }
-
-                *timers = (delay_timer, sound_timer);
             }
-            
-            thread::sleep(time::Duration::from_nanos(16666667)); // It should tick at 60hz, this is...approximate
+
+            thread::sleep(self.next_tick.saturating_duration_since(Instant::now()));
+        }
+    }
+}
+
+/// Alternative to the timer thread, meant to be updated from the main loop. It keeps track of the
+/// elapsed time and ticks the timers once for every 60hz period that has gone by since the last update,
+/// so the beep flag can be a plain bool instead of a shared one
+pub struct InlineTimer {
+    timers : Arc<Mutex<(u8, u8)>>, // Shared with the CPU, but only accessed from the main thread
+    pause : Arc<Mutex<bool>>,
+    must_beep : bool,
+    accumulated : Duration, // Time that has passed but hasn't been turned into ticks yet
+    last_update : Instant
+}
+
+impl InlineTimer {
+    pub fn new(timers : Arc<Mutex<(u8, u8)>>, pause : Arc<Mutex<bool>>) -> InlineTimer {
+        InlineTimer {
+            timers,
+            pause,
+            must_beep : false,
+            accumulated : Duration::from_secs(0),
+            last_update : Instant::now()
+        }
+    }
+
+    /// Ticks the timers as many times as needed to catch up with the wall clock.
+    /// Returns true if the system should be beeping
+    pub fn update(&mut self) -> bool {
+        let now = Instant::now();
+        self.accumulated += now - self.last_update;
+        self.last_update = now;
+
+        // Same as the threaded timer, nothing moves while paused
+        if *self.pause.lock().unwrap() {
+            self.accumulated = Duration::from_secs(0);
+            self.must_beep = false;
+            return false;
+        }
+
+        while self.accumulated >= TIMER_PERIOD {
+            self.accumulated -= TIMER_PERIOD;
+            self.must_beep = tick(&mut self.timers.lock().unwrap());
+        }
+
+        self.must_beep
+    }
+}
+
+/// Ticks the timers every `cycles_per_frame` executed cycles instead of following the wall clock,
+/// so the timers only depend on the instructions that ran. Owned and updated by the cpu
+pub struct CycleTimer {
+    timers : Arc<Mutex<(u8, u8)>>,
+    cycles_per_frame : u32,
+    cycles : u32, // executed since the last tick
+    frames : u64 // ticks so far
+}
+
+impl CycleTimer {
+    pub fn new(timers : Arc<Mutex<(u8, u8)>>, cycles_per_frame : u32) -> CycleTimer {
+        CycleTimer {
+            timers,
+            cycles_per_frame,
+            cycles : 0,
+            frames : 0
         }
     }
+
+    /// How many frames have gone by, going by the cycles rather than the clock
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Counts an executed cycle, ticking the timers once enough of them have run
+    pub fn update(&mut self) {
+        self.cycles += 1;
+
+        if self.cycles >= self.cycles_per_frame {
+            self.cycles = 0;
+            self.frames += 1;
+            tick(&mut self.timers.lock().unwrap());
+        }
+    }
+}
+
+/// How many ticks were due by `now`, moving the deadline past them
+fn ticks_due(next_tick : &mut Instant, now : Instant) -> u32 {
+    let mut ticks = 0;
+
+    while *next_tick <= now {
+        *next_tick += TIMER_PERIOD;
+        ticks += 1;
+    }
+
+    ticks
+}
+
+/// Decrements the (delay, sound) timers once, returns true if the system should be beeping
+/// until the next tick
+pub(crate) fn tick(timers : &mut (u8, u8)) -> bool {
+    let (delay_timer, sound_timer) = *timers;
+
+    // The buzzer sounds the whole time the sound timer is nonzero, so this has to look at it
+    // before decrementing. Checking afterwards was off by one: the period in which the timer
+    // went from 1 to 0 was silent, and setting it to 1 didn't beep at all
+    let must_beep = sound_timer > 0;
+
+    let delay_timer = delay_timer.saturating_sub(1);
+    let sound_timer = sound_timer.saturating_sub(1);
+
+    *timers = (delay_timer, sound_timer);
+
+    must_beep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_sound_timer_of_one_beeps_for_a_tick() {
+        let mut timers = (0, 1);
+
+        assert!(tick(&mut timers));
+        assert_eq!(timers, (0, 0));
+        assert!(! tick(&mut timers));
+    }
+
+    #[test]
+    fn late_ticks_are_caught_up_without_moving_the_deadlines() {
+        let start = Instant::now();
+        let mut next_tick = start;
+
+        assert_eq!(ticks_due(&mut next_tick, start + TIMER_PERIOD * 7 / 2), 4);
+        assert_eq!(next_tick, start + TIMER_PERIOD * 4);
+        assert_eq!(ticks_due(&mut next_tick, start + TIMER_PERIOD * 7 / 2), 0);
+    }
 }
@@ -0,0 +1,162 @@
+// Reference execution traces, for finding the first instruction where the VM stops behaving like
+// another emulator running the same ROM. A trace has a line per executed instruction with the PC
+// and the opcode in hex (the 0x prefix is optional). Anything after them, such as the mnemonic,
+// is ignored:
+//
+//      # Comments and empty lines are ignored
+//      0x0200 6001  LD V0, 0x01
+//      0x0202 1200  JP 0x200
+//
+// --trace writes the same format, so a trace of this VM can be compared against another one
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+use log::warn;
+
+use crate::chip8::disasm;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReferenceTrace {
+    steps : Vec<(usize, u16)>, // (pc, opcode)
+    next : usize, // index of the next step to compare
+}
+
+impl ReferenceTrace {
+    pub fn from_path(path : &str) -> Result<ReferenceTrace, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read trace file {}: {}", path, e))?;
+
+        ReferenceTrace::parse(&contents).map_err(|e| format!("Invalid trace file {}: {}", path, e))
+    }
+
+    /// Parses the contents of a trace file
+    pub fn parse(contents : &str) -> Result<ReferenceTrace, String> {
+        let mut steps = Vec::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let (pc, opcode) = match (fields.next(), fields.next()) {
+                (Some(pc), Some(opcode)) => (pc, opcode),
+                _ => return Err(format!("line {}: expected `<pc> <opcode>`", number + 1))
+            };
+
+            let pc = usize::from_str_radix(pc.trim_start_matches("0x"), 16)
+                .ok()
+                .filter(|&pc| pc <= 0xFFF)
+                .ok_or_else(|| format!("line {}: '{}' is not an address", number + 1, pc))?;
+            let opcode = u16::from_str_radix(opcode.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("line {}: '{}' is not an opcode", number + 1, opcode))?;
+
+            steps.push((pc, opcode));
+        }
+
+        Ok(ReferenceTrace { steps, next : 0 })
+    }
+
+    /// Returns the next (pc, opcode) the VM is expected to run, or None once the trace is over
+    pub fn next_step(&mut self) -> Option<(usize, u16)> {
+        let step = self.steps.get(self.next).copied();
+        self.next += 1;
+
+        step
+    }
+
+    /// How many steps have been compared so far
+    pub fn position(&self) -> usize {
+        self.next.min(self.steps.len())
+    }
+
+    /// Makes the given step the next one to compare, e.g. after undoing instructions
+    pub fn rewind_to(&mut self, position : usize) {
+        self.next = position;
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+/// Writes a line per executed instruction, buffered so tracing doesn't slow the VM down much
+pub struct TraceLog {
+    file : BufWriter<File>,
+}
+
+impl TraceLog {
+    pub fn create(path : &str) -> io::Result<TraceLog> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "# <pc> <opcode>  <mnemonic>")?;
+
+        Ok(TraceLog { file })
+    }
+
+    pub fn log(&mut self, pc : usize, instr : u16) -> io::Result<()> {
+        writeln!(self.file, "{:#06x} {:04X}  {}", pc, instr, disasm::disassemble(instr))
+    }
+
+    /// Adds a comment, which is skipped when the trace is read back
+    pub fn note(&mut self, text : &str) -> io::Result<()> {
+        writeln!(self.file, "# {}", text)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for TraceLog {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("Could not write the end of the trace: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_steps_and_ignores_the_rest() {
+        let mut trace = ReferenceTrace::parse("# test\n0x0200 6001  LD V0, 0x01\n\n202 0x1200\n").unwrap();
+
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace.next_step(), Some((0x200, 0x6001)));
+        assert_eq!(trace.next_step(), Some((0x202, 0x1200)));
+        assert_eq!(trace.next_step(), None);
+        assert_eq!(trace.position(), 2);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(ReferenceTrace::parse("0x200").is_err());
+        assert!(ReferenceTrace::parse("0x1000 6001").is_err());
+        assert!(ReferenceTrace::parse("0x200 LD").is_err());
+    }
+
+    #[test]
+    fn written_traces_can_be_read_back() {
+        let path = std::env::temp_dir().join("chip-8-vm-trace-log-test.txt");
+        let path = path.to_str().unwrap();
+
+        let mut log = TraceLog::create(path).unwrap();
+        log.log(0x200, 0x6001).unwrap();
+        log.note("Reset").unwrap();
+        log.log(0x202, 0x1200).unwrap();
+        log.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(path).unwrap(), "# <pc> <opcode>  <mnemonic>\n0x0200 6001  LD V0, 0x01\n# Reset\n0x0202 1200  JP 0x200\n");
+        assert_eq!(ReferenceTrace::from_path(path).unwrap().len(), 2);
+
+        fs::remove_file(path).unwrap();
+    }
+}
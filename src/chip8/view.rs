@@ -0,0 +1,22 @@
+use crate::config;
+
+/// Display options that can be toggled from the keyboard while the game runs. They're shared
+/// between the keypad and the graphics subsystems behind an Rc<RefCell<>>
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ViewOptions {
+    pub invert : bool, // swap the foreground and background colors
+    pub sprite_preview : bool, // show the sprite at I on the panel
+    pub debug_panel : bool, // show the registers, stack and instruction history next to the game
+    pub fullscreen : bool,
+}
+
+impl ViewOptions {
+    pub fn from_config(config : &config::Config) -> ViewOptions {
+        ViewOptions {
+            invert : config.invert(),
+            sprite_preview : false,
+            debug_panel : ! config.no_debug_panel(),
+            fullscreen : config.fullscreen(),
+        }
+    }
+}
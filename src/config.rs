@@ -1,27 +1,720 @@
 use structopt::StructOpt;
+use structopt::clap;
+use log::LevelFilter;
+
+use std::env;
+
+use crate::chip8::{ClipMode, InputReplay, DisasmFormat, Profile, ReferenceTrace, Waveform};
+#[cfg(feature = "sdl")]
+use crate::chip8::Keymap;
+use crate::config_file::ConfigFile;
+use crate::theme::{self, Rgb, Theme};
 
 #[derive(StructOpt)]
 #[structopt(name = "A CHIP-8 VM implementation", about = "CHIP-8 VM. Pass `-h` to see all optional flags")]
 pub struct Config {
-    rom_path : String,
+    #[structopt(required_unless_one = &["selftest", "list-keys"])]
+    rom_path : Option<String>,
+    #[structopt(name = "config", help = "Read flags from a TOML config file, with keys named like the flags (e.g. `scale = 10`). The command line overrides them", long)]
+    config_file : Option<String>,
     #[structopt(name = "wrapping_enabled", help = "Enable sprite wrapping on the borders of the screen (needed by some games, such as BLITZ)", short, long)]
     wrapping_enabled : bool,
-    #[structopt(name = "font_path",  help = "Path to the font needed to display information", short, long, default_value = "font.ttf")]
-    font_path : String
+    #[structopt(name = "profile", help = "Set the quirks to match a platform: cosmac-vip, chip48, schip or xo-chip. Quirk flags override it", long, possible_values = &["cosmac-vip", "chip48", "schip", "xo-chip"])]
+    profile : Option<Profile>,
+    #[structopt(name = "clip-mode", help = "What happens to sprites crossing the borders of the screen: wrap or clip. --wrapping_enabled is the same as wrap", long, possible_values = &["wrap", "clip"], conflicts_with = "wrapping_enabled")]
+    clip_mode : Option<ClipMode>,
+    #[structopt(name = "shift-quirk", help = "Make 8XY6 and 8XYE shift VY into VX, like the COSMAC VIP, instead of shifting VX in place like SUPER-CHIP", long)]
+    shift_quirk : bool,
+    #[structopt(name = "jump-quirk", help = "Read BNNN as BXNN and jump to XNN + VX, like SUPER-CHIP, instead of NNN + V0", long)]
+    jump_quirk : bool,
+    #[structopt(name = "load-store-quirk", help = "Make FX55 and FX65 increment I past the last register, like the original CHIP-8, instead of leaving it unchanged", long)]
+    load_store_quirk : bool,
+    #[structopt(name = "vf-reset", help = "Reset VF to 0 after 8XY1, 8XY2 and 8XY3 (OR, AND and XOR), like the COSMAC VIP", long)]
+    vf_reset : bool,
+    #[structopt(name = "vblank-quirk", help = "Make DXYN wait for the next 60hz frame before drawing, like the COSMAC VIP waited for the vertical blank, so there's at most a draw per frame", long)]
+    vblank_quirk : bool,
+    #[structopt(name = "no-wrap-start", help = "Don't wrap the starting coordinates of sprites around the screen in clip mode, so sprites placed off-screen aren't drawn at all", long)]
+    no_wrap_start : bool,
+    #[structopt(name = "font_path",  help = "Path to a TTF font for the debug panel, instead of the built-in one", short, long)]
+    font_path : Option<String>,
+    #[structopt(name = "terminal", help = "Run in the terminal instead of opening a window, printing the screen with block characters. Escape or Ctrl+C quits", long)]
+    terminal : bool,
+    #[structopt(name = "show-fps", help = "Show the measured frames per second in the window title", long)]
+    show_fps : bool,
+    #[structopt(name = "no-debug-panel", help = "Don't show the registers, stack and instruction history, the window only fits the game", long)]
+    no_debug_panel : bool,
+    #[structopt(name = "scale", help = "Size of each CHIP-8 pixel on the window, the whole window scales along", long, default_value = "15", parse(try_from_str = parse_scale))]
+    scale : u32,
+    #[structopt(name = "fullscreen", help = "Start in fullscreen (can be toggled with F11), the game keeps its proportions", long)]
+    fullscreen : bool,
+    #[structopt(name = "window-pos", help = "Place the window at x,y on the screen instead of centering it", long, parse(try_from_str = parse_window_pos))]
+    window_pos : Option<(i32, i32)>,
+    #[structopt(name = "window-centered", help = "Center the window on the screen (the default)", long, conflicts_with = "window-pos")]
+    window_centered : bool,
+    #[structopt(name = "invert", help = "Start with inverted display colors (can be toggled with the I key)", long)]
+    invert : bool,
+    #[structopt(name = "rainbow", help = "Slowly cycle the foreground color through all hues, purely cosmetic", long)]
+    rainbow : bool,
+    #[structopt(name = "pause-dim", help = "How much the game area darkens while paused, from 0 (not at all) to 255 (black)", long, default_value = "160")]
+    pause_dim : u8,
+    #[structopt(name = "step", help = "Start paused, to go through the ROM one instruction at a time with N", long)]
+    step : bool,
+    #[structopt(name = "break", help = "Pause when the PC reaches this address, in hex (e.g. 0x2a4). Can be repeated", long, number_of_values = 1, parse(try_from_str = parse_breakpoint))]
+    breakpoints : Vec<usize>,
+    #[structopt(name = "frame-timing", help = "Run the cycles of each 60hz frame in a burst, then tick the timers and sleep until the next frame, instead of sleeping after every cycle", long, conflicts_with = "inline-timers")]
+    frame_timing : bool,
+    #[structopt(name = "inline-timers", help = "Update the timers from the main loop instead of a separate thread", long)]
+    inline_timers : bool,
+    #[structopt(name = "freq", help = "How many cycles run per second to begin with, it can still be changed with the Up and Down arrows [default: ~550]", long, parse(try_from_str = parse_freq))]
+    freq : Option<u64>,
+    #[structopt(name = "deterministic-timers", help = "Tick the timers every --cycles-per-frame executed cycles instead of at 60hz of real time, for reproducible runs", long)]
+    deterministic_timers : bool,
+    #[structopt(name = "cycles-per-frame", help = "How many cycles make up a 60hz frame, used by --deterministic-timers", long, default_value = "9", parse(try_from_str = parse_cycles_per_frame))]
+    cycles_per_frame : u32,
+    #[structopt(name = "disable-keys", help = "Comma-separated list of CHIP-8 keys (0-F) that are never seen as pressed", long, use_delimiter = true, parse(try_from_str = parse_key))]
+    disabled_keys : Vec<usize>,
+    #[cfg(feature = "sdl")]
+    #[structopt(name = "keymap", help = "File binding the CHIP-8 keys to other keyboard keys, as `<key> = <key name>` lines (see --list-keys for the names)", long, parse(try_from_str = Keymap::from_path))]
+    keymap : Option<Keymap>,
+    #[structopt(name = "patch", help = "Write a byte into memory after loading the ROM, as <addr>=<byte> in hex (e.g. 0x2a4=0x09). Can be repeated", long, number_of_values = 1, parse(try_from_str = parse_patch))]
+    patches : Vec<(usize, u8)>,
+    #[structopt(name = "load", help = "Load a file into memory after the ROM, as <path>@<addr> with the address in hex (e.g. sprites.bin@0x600). Can be repeated", long, number_of_values = 1, parse(try_from_str = parse_load))]
+    loads : Vec<(String, usize)>,
+    #[structopt(name = "theme", help = "Color theme, either a built-in one (purple, green, amber) or the path to a theme file", long, default_value = "purple", parse(try_from_str = Theme::from_name_or_path))]
+    theme : Theme,
+    #[structopt(name = "fg-color", help = "Color of the set pixels as RRGGBB hex (e.g. 33ff33), overriding the theme", long, parse(try_from_str = theme::parse_hex_color))]
+    fg_color : Option<Rgb>,
+    #[structopt(name = "bg-color", help = "Color of the unset pixels and the panel background as RRGGBB hex, overriding the theme", long, parse(try_from_str = theme::parse_hex_color))]
+    bg_color : Option<Rgb>,
+    #[structopt(name = "list-keys", help = "Print which keyboard keys map to the CHIP-8 keypad and the special keys, and exit", long)]
+    list_keys : bool,
+    #[structopt(name = "selftest", help = "Run a built-in test ROM, check that it draws what it should and exit", long)]
+    selftest : bool,
+    #[structopt(name = "check", help = "Run the ROM without a window and compare the final registers and memory against an .expected file", long)]
+    check : Option<String>,
+    #[structopt(name = "rewind-depth", help = "How many executed instructions can be undone with Backspace, each of them keeps a ~12KB snapshot of the machine", long, default_value = "0")]
+    rewind_depth : usize,
+    #[structopt(name = "reset-clears-rpl", help = "Clear the RPL user flags (FX75/FX85) when resetting with F1, instead of keeping them like the HP48 does across program runs", long)]
+    reset_clears_rpl : bool,
+    #[structopt(name = "compare-trace", help = "Compare every executed instruction against a reference trace (a `<pc> <opcode>` line per step), stopping at the first difference", long, parse(try_from_str = ReferenceTrace::from_path))]
+    compare_trace : Option<ReferenceTrace>,
+    #[structopt(name = "trace", help = "Write every executed instruction to a file, with its address, opcode and mnemonic. The format is the one --compare-trace reads", long)]
+    trace : Option<String>,
+    #[structopt(name = "record", help = "Record the keypad state of every cycle to a file, to replay the run later with --replay", long)]
+    record : Option<String>,
+    #[structopt(name = "replay", help = "Press the keys recorded with --record instead of reading the keyboard, the special keys still work. Use the same --seed for the same run", long, conflicts_with = "record", parse(try_from_str = InputReplay::from_path))]
+    replay : Option<InputReplay>,
+    #[structopt(name = "disasm", help = "Print the disassembly of the ROM and exit", long)]
+    disasm : bool,
+    #[structopt(name = "disasm-format", help = "Output format of --disasm: plain, or json with an object per instruction (address, bytes, mnemonic, operands, is_data)", long, default_value = "plain", possible_values = &["plain", "json"])]
+    disasm_format : DisasmFormat,
+    #[structopt(name = "cycles", help = "How many cycles --check runs the ROM for", long, default_value = "1000")]
+    cycles : u64,
+    #[structopt(name = "max-cycles", help = "Quit after running this many cycles and print the final state, for unattended runs", long)]
+    max_cycles : Option<u64>,
+    #[structopt(name = "max-seconds", help = "Quit after this many seconds and print the final state, for unattended runs", long)]
+    max_seconds : Option<u64>,
+    #[structopt(name = "seed", help = "Seed for the random numbers of CXNN, so runs can be reproduced. Random every time if missing", long)]
+    seed : Option<u64>,
+    #[structopt(name = "panel-font-size", help = "Point size of the debug panel text, smaller sizes render faster but take up less space", long, default_value = "128", parse(try_from_str = parse_font_size))]
+    panel_font_size : u16,
+    #[structopt(name = "no-audio", help = "Don't open the audio device at all, the VM runs silently", long)]
+    no_audio : bool,
+    #[structopt(name = "mute", help = "Start with the sound muted (can be toggled with the M key)", long)]
+    mute : bool,
+    #[structopt(name = "beep-freq", help = "Pitch of the beep in hz", long, default_value = "240", parse(try_from_str = parse_beep_freq))]
+    beep_freq : f32,
+    #[structopt(name = "beep-volume", help = "Volume of the beep, from 0.0 (silent) to 1.0", long, default_value = "0.25", parse(try_from_str = parse_beep_volume))]
+    beep_volume : f32,
+    #[structopt(name = "beep-wave", help = "Shape of the beep: square, or the softer sine and triangle", long, default_value = "square", possible_values = &["square", "sine", "triangle"])]
+    beep_wave : Waveform,
+    #[structopt(name = "strict", help = "Report instructions that are ignored or look like ROM bugs, and stop at unknown opcodes", long)]
+    strict : bool,
+    #[structopt(name = "log-level", help = "Minimum level of the diagnostics shown: off, error, warn, info, debug or trace", long, default_value = "info")]
+    log_level : LevelFilter
 }
 
 impl Config {
-    pub fn rom_path(&self) -> &str {
-        &self.rom_path
+    /// Parses the command line, along with the --config file if there's one.
+    /// Exits with the usage if anything is wrong, like structopt does
+    pub fn load() -> Config {
+        let matches = Config::clap().get_matches_from(env::args_os());
+        let mut config = Config::from_clap(&matches);
+
+        if let Some(path) = matches.value_of("config") {
+            ConfigFile::from_path(path)
+                .and_then(|file| config.merge_file(file, &matches).map_err(|e| format!("In the config file {}: {}", path, e)))
+                .unwrap_or_else(|e| clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit());
+        }
+
+        config
+    }
+
+    /// Takes the settings of a config file, except for the flags the command line sets too.
+    /// The values go through the same checks as on the command line
+    fn merge_file(&mut self, file : ConfigFile, matches : &clap::ArgMatches) -> Result<(), String> {
+        let unset = |name : &str| matches.occurrences_of(name) == 0;
+
+        if let Some(wrapping_enabled) = file.wrapping_enabled.filter(|_| unset("wrapping_enabled")) {
+            self.wrapping_enabled = wrapping_enabled;
+        }
+        if let Some(profile) = file.profile.filter(|_| unset("profile")) {
+            self.profile = Some(profile.parse()?);
+        }
+        if let Some(clip_mode) = file.clip_mode.filter(|_| unset("clip-mode")) {
+            self.clip_mode = Some(clip_mode.parse()?);
+        }
+        if let Some(shift_quirk) = file.shift_quirk.filter(|_| unset("shift-quirk")) {
+            self.shift_quirk = shift_quirk;
+        }
+        if let Some(jump_quirk) = file.jump_quirk.filter(|_| unset("jump-quirk")) {
+            self.jump_quirk = jump_quirk;
+        }
+        if let Some(load_store_quirk) = file.load_store_quirk.filter(|_| unset("load-store-quirk")) {
+            self.load_store_quirk = load_store_quirk;
+        }
+        if let Some(vf_reset) = file.vf_reset.filter(|_| unset("vf-reset")) {
+            self.vf_reset = vf_reset;
+        }
+        if let Some(vblank_quirk) = file.vblank_quirk.filter(|_| unset("vblank-quirk")) {
+            self.vblank_quirk = vblank_quirk;
+        }
+        if let Some(no_wrap_start) = file.no_wrap_start.filter(|_| unset("no-wrap-start")) {
+            self.no_wrap_start = no_wrap_start;
+        }
+        if let Some(font_path) = file.font_path.filter(|_| unset("font_path")) {
+            self.font_path = Some(font_path);
+        }
+        if let Some(terminal) = file.terminal.filter(|_| unset("terminal")) {
+            self.terminal = terminal;
+        }
+        if let Some(show_fps) = file.show_fps.filter(|_| unset("show-fps")) {
+            self.show_fps = show_fps;
+        }
+        if let Some(no_debug_panel) = file.no_debug_panel.filter(|_| unset("no-debug-panel")) {
+            self.no_debug_panel = no_debug_panel;
+        }
+        if let Some(scale) = file.scale.filter(|_| unset("scale")) {
+            self.scale = parse_scale(&scale.to_string())?;
+        }
+        if let Some(fullscreen) = file.fullscreen.filter(|_| unset("fullscreen")) {
+            self.fullscreen = fullscreen;
+        }
+        if let Some(window_pos) = file.window_pos.filter(|_| unset("window-pos")) {
+            self.window_pos = Some(parse_window_pos(&window_pos)?);
+        }
+        if let Some(window_centered) = file.window_centered.filter(|_| unset("window-centered")) {
+            self.window_centered = window_centered;
+        }
+        if let Some(invert) = file.invert.filter(|_| unset("invert")) {
+            self.invert = invert;
+        }
+        if let Some(rainbow) = file.rainbow.filter(|_| unset("rainbow")) {
+            self.rainbow = rainbow;
+        }
+        if let Some(pause_dim) = file.pause_dim.filter(|_| unset("pause-dim")) {
+            self.pause_dim = pause_dim;
+        }
+        if let Some(step) = file.step.filter(|_| unset("step")) {
+            self.step = step;
+        }
+        if let Some(breakpoints) = file.breakpoints.filter(|_| unset("break")) {
+            self.breakpoints = breakpoints.iter().map(|addr| parse_breakpoint(addr)).collect::<Result<_, _>>()?;
+        }
+        if let Some(frame_timing) = file.frame_timing.filter(|_| unset("frame-timing")) {
+            self.frame_timing = frame_timing;
+        }
+        if let Some(inline_timers) = file.inline_timers.filter(|_| unset("inline-timers")) {
+            self.inline_timers = inline_timers;
+        }
+        if let Some(freq) = file.freq.filter(|_| unset("freq")) {
+            self.freq = Some(parse_freq(&freq.to_string())?);
+        }
+        if let Some(deterministic_timers) = file.deterministic_timers.filter(|_| unset("deterministic-timers")) {
+            self.deterministic_timers = deterministic_timers;
+        }
+        if let Some(cycles_per_frame) = file.cycles_per_frame.filter(|_| unset("cycles-per-frame")) {
+            self.cycles_per_frame = parse_cycles_per_frame(&cycles_per_frame.to_string())?;
+        }
+        if let Some(disabled_keys) = file.disabled_keys.filter(|_| unset("disable-keys")) {
+            self.disabled_keys = disabled_keys.iter().map(|key| parse_key(key)).collect::<Result<_, _>>()?;
+        }
+        #[cfg(feature = "sdl")]
+        if let Some(keymap) = file.keymap.filter(|_| unset("keymap")) {
+            self.keymap = Some(Keymap::from_path(&keymap)?);
+        }
+        if let Some(patches) = file.patches.filter(|_| unset("patch")) {
+            self.patches = patches.iter().map(|patch| parse_patch(patch)).collect::<Result<_, _>>()?;
+        }
+        if let Some(loads) = file.loads.filter(|_| unset("load")) {
+            self.loads = loads.iter().map(|load| parse_load(load)).collect::<Result<_, _>>()?;
+        }
+        if let Some(theme) = file.theme.filter(|_| unset("theme")) {
+            self.theme = Theme::from_name_or_path(&theme)?;
+        }
+        if let Some(fg_color) = file.fg_color.filter(|_| unset("fg-color")) {
+            self.fg_color = Some(theme::parse_hex_color(&fg_color)?);
+        }
+        if let Some(bg_color) = file.bg_color.filter(|_| unset("bg-color")) {
+            self.bg_color = Some(theme::parse_hex_color(&bg_color)?);
+        }
+        if let Some(rewind_depth) = file.rewind_depth.filter(|_| unset("rewind-depth")) {
+            self.rewind_depth = rewind_depth;
+        }
+        if let Some(reset_clears_rpl) = file.reset_clears_rpl.filter(|_| unset("reset-clears-rpl")) {
+            self.reset_clears_rpl = reset_clears_rpl;
+        }
+        if let Some(compare_trace) = file.compare_trace.filter(|_| unset("compare-trace")) {
+            self.compare_trace = Some(ReferenceTrace::from_path(&compare_trace)?);
+        }
+        if let Some(trace) = file.trace.filter(|_| unset("trace")) {
+            self.trace = Some(trace);
+        }
+        if let Some(record) = file.record.filter(|_| unset("record")) {
+            self.record = Some(record);
+        }
+        if let Some(replay) = file.replay.filter(|_| unset("replay")) {
+            self.replay = Some(InputReplay::from_path(&replay)?);
+        }
+        if let Some(disasm_format) = file.disasm_format.filter(|_| unset("disasm-format")) {
+            self.disasm_format = disasm_format.parse()?;
+        }
+        if let Some(cycles) = file.cycles.filter(|_| unset("cycles")) {
+            self.cycles = cycles;
+        }
+        if let Some(max_cycles) = file.max_cycles.filter(|_| unset("max-cycles")) {
+            self.max_cycles = Some(max_cycles);
+        }
+        if let Some(max_seconds) = file.max_seconds.filter(|_| unset("max-seconds")) {
+            self.max_seconds = Some(max_seconds);
+        }
+        if let Some(seed) = file.seed.filter(|_| unset("seed")) {
+            self.seed = Some(seed);
+        }
+        if let Some(panel_font_size) = file.panel_font_size.filter(|_| unset("panel-font-size")) {
+            self.panel_font_size = parse_font_size(&panel_font_size.to_string())?;
+        }
+        if let Some(no_audio) = file.no_audio.filter(|_| unset("no-audio")) {
+            self.no_audio = no_audio;
+        }
+        if let Some(mute) = file.mute.filter(|_| unset("mute")) {
+            self.mute = mute;
+        }
+        if let Some(beep_freq) = file.beep_freq.filter(|_| unset("beep-freq")) {
+            self.beep_freq = parse_beep_freq(&beep_freq.to_string())?;
+        }
+        if let Some(beep_volume) = file.beep_volume.filter(|_| unset("beep-volume")) {
+            self.beep_volume = parse_beep_volume(&beep_volume.to_string())?;
+        }
+        if let Some(beep_wave) = file.beep_wave.filter(|_| unset("beep-wave")) {
+            self.beep_wave = beep_wave.parse()?;
+        }
+        if let Some(strict) = file.strict.filter(|_| unset("strict")) {
+            self.strict = strict;
+        }
+        if let Some(log_level) = file.log_level.filter(|_| unset("log-level")) {
+            self.log_level = log_level.parse().map_err(|_| format!("'{}' is not a log level, expected off, error, warn, info, debug or trace", log_level))?;
+        }
+
+        Ok(())
+    }
+
+    /// Only missing when running the self-test or listing the keys
+    pub fn rom_path(&self) -> Option<&str> {
+        self.rom_path.as_deref()
+    }
+
+    /// Already merged in by load()
+    pub fn config_file(&self) -> Option<&str> {
+        self.config_file.as_deref()
+    }
+
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile
+    }
+
+    /// Only set when asked for, either directly or through the old wrapping flag
+    pub fn clip_mode(&self) -> Option<ClipMode> {
+        match self.clip_mode {
+            Some(clip_mode) => Some(clip_mode),
+            None if self.wrapping_enabled => Some(ClipMode::Wrap),
+            None => None
+        }
+    }
+
+    pub fn shift_quirk(&self) -> bool {
+        self.shift_quirk
+    }
+
+    pub fn jump_quirk(&self) -> bool {
+        self.jump_quirk
+    }
+
+    pub fn load_store_quirk(&self) -> bool {
+        self.load_store_quirk
+    }
+
+    pub fn vf_reset(&self) -> bool {
+        self.vf_reset
+    }
+
+    pub fn vblank_quirk(&self) -> bool {
+        self.vblank_quirk
+    }
+
+    pub fn no_wrap_start(&self) -> bool {
+        self.no_wrap_start
+    }
+
+    pub fn font_path(&self) -> Option<&str> {
+        self.font_path.as_deref()
+    }
+
+    pub fn show_fps(&self) -> bool {
+        self.show_fps
+    }
+
+    pub fn no_debug_panel(&self) -> bool {
+        self.no_debug_panel
+    }
+
+    pub fn fullscreen(&self) -> bool {
+        self.fullscreen
+    }
+
+    /// Only set when the window isn't centered
+    pub fn window_pos(&self) -> Option<(i32, i32)> {
+        if self.window_centered {
+            None
+        } else {
+            self.window_pos
+        }
+    }
+
+    pub fn invert(&self) -> bool {
+        self.invert
+    }
+
+    pub fn rainbow(&self) -> bool {
+        self.rainbow
+    }
+
+    pub fn terminal(&self) -> bool {
+        self.terminal
+    }
+
+    pub fn step(&self) -> bool {
+        self.step
+    }
+
+    /// The --freq frequency in hz, if there's one
+    pub fn freq(&self) -> Option<u64> {
+        self.freq
+    }
+
+    pub fn breakpoints(&self) -> &[usize] {
+        &self.breakpoints
+    }
+
+    pub fn pause_dim(&self) -> u8 {
+        self.pause_dim
+    }
+
+    pub fn frame_timing(&self) -> bool {
+        self.frame_timing
+    }
+
+    pub fn inline_timers(&self) -> bool {
+        self.inline_timers
+    }
+
+    pub fn deterministic_timers(&self) -> bool {
+        self.deterministic_timers
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    pub fn disabled_keys(&self) -> &[usize] {
+        &self.disabled_keys
+    }
+
+    /// The keymap from --keymap, or the default QWERTY one
+    #[cfg(feature = "sdl")]
+    pub fn keymap(&self) -> Keymap {
+        self.keymap.clone().unwrap_or_default()
+    }
+
+    pub fn patches(&self) -> &[(usize, u8)] {
+        &self.patches
+    }
+
+    pub fn loads(&self) -> &[(String, usize)] {
+        &self.loads
+    }
+
+    /// The theme along with the --fg-color and --bg-color overrides
+    pub fn theme(&self) -> Theme {
+        self.theme.with_colors(self.fg_color, self.bg_color)
+    }
+
+    pub fn list_keys(&self) -> bool {
+        self.list_keys
+    }
+
+    pub fn selftest(&self) -> bool {
+        self.selftest
+    }
+
+    pub fn check(&self) -> Option<&str> {
+        self.check.as_deref()
+    }
+
+    pub fn rewind_depth(&self) -> usize {
+        self.rewind_depth
+    }
+
+    pub fn reset_clears_rpl(&self) -> bool {
+        self.reset_clears_rpl
+    }
+
+    pub fn compare_trace(&self) -> Option<&ReferenceTrace> {
+        self.compare_trace.as_ref()
+    }
+
+    pub fn trace(&self) -> Option<&str> {
+        self.trace.as_deref()
+    }
+
+    pub fn record(&self) -> Option<&str> {
+        self.record.as_deref()
     }
 
-    pub fn wrapping_enabled(&self) -> bool {
-        self.wrapping_enabled
+    pub fn replay(&self) -> Option<&InputReplay> {
+        self.replay.as_ref()
     }
 
-    pub fn font_path(&self) -> &str {
-        &self.font_path
+    pub fn disasm(&self) -> bool {
+        self.disasm
+    }
+
+    pub fn disasm_format(&self) -> DisasmFormat {
+        self.disasm_format
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn max_cycles(&self) -> Option<u64> {
+        self.max_cycles
+    }
+
+    pub fn max_seconds(&self) -> Option<u64> {
+        self.max_seconds
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn panel_font_size(&self) -> u16 {
+        self.panel_font_size
+    }
+
+    pub fn no_audio(&self) -> bool {
+        self.no_audio
+    }
+
+    pub fn mute(&self) -> bool {
+        self.mute
+    }
+
+    pub fn beep_freq(&self) -> f32 {
+        self.beep_freq
+    }
+
+    /// Anything louder than 1.0 would clip
+    pub fn beep_volume(&self) -> f32 {
+        self.beep_volume.clamp(0.0, 1.0)
+    }
+
+    pub fn beep_wave(&self) -> Waveform {
+        self.beep_wave
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        self.log_level
     }
 }
 
+impl Default for Config {
+    /// Every flag at its default and no ROM, for using the VM as a library without a command line
+    fn default() -> Self {
+        // The self-test flag only gets the parser past the missing ROM path
+        let mut config = Config::from_iter(&["chip-8-vm", "--selftest"]);
+        config.selftest = false;
+
+        config
+    }
+}
+
+/// Parses a single hex digit into a CHIP-8 key
+fn parse_key(src : &str) -> Result<usize, String> {
+    match usize::from_str_radix(src.trim(), 16) {
+        Ok(key) if key <= 0xF => Ok(key),
+        _ => Err(format!("'{}' is not a CHIP-8 key, expected a hex digit between 0 and F", src))
+    }
+}
+
+/// Parses an <addr>=<byte> memory patch, the address has to be inside the program space
+fn parse_patch(src : &str) -> Result<(usize, u8), String> {
+    let hex_digits = |value : &str| value.trim().trim_start_matches("0x").to_string();
+
+    let (addr, byte) = src.split_once('=')
+        .ok_or_else(|| format!("'{}' is not a patch, expected <addr>=<byte>", src))?;
+
+    let addr = usize::from_str_radix(&hex_digits(addr), 16)
+        .map_err(|_| format!("'{}' is not a hex address", addr))?;
+    let byte = u8::from_str_radix(&hex_digits(byte), 16)
+        .map_err(|_| format!("'{}' is not a hex byte", byte))?;
+
+    if ! (0x200..=0xFFF).contains(&addr) {
+        return Err(format!("{:#06x} is outside of the program space (0x200-0xfff)", addr));
+    }
+
+    Ok((addr, byte))
+}
+
+/// Parses a <path>@<addr> extra file to load, the address has to be inside the program space.
+/// The last @ is used, so paths can contain them
+fn parse_load(src : &str) -> Result<(String, usize), String> {
+    let (path, addr) = src.rsplit_once('@')
+        .ok_or_else(|| format!("'{}' is not a file to load, expected <path>@<addr>", src))?;
+
+    let addr = usize::from_str_radix(addr.trim().trim_start_matches("0x"), 16)
+        .map_err(|_| format!("'{}' is not a hex address", addr))?;
 
+    if ! (0x200..=0xFFF).contains(&addr) {
+        return Err(format!("{:#06x} is outside of the program space (0x200-0xfff)", addr));
+    }
+
+    Ok((path.to_string(), addr))
+}
+
+/// Parses a hex breakpoint address, anywhere in memory
+fn parse_breakpoint(src : &str) -> Result<usize, String> {
+    let addr = usize::from_str_radix(src.trim().trim_start_matches("0x"), 16)
+        .map_err(|_| format!("'{}' is not a hex address", src))?;
+
+    if addr > 0xFFF {
+        return Err(format!("{:#06x} is outside of memory (0x000-0xfff)", addr));
+    }
+
+    Ok(addr)
+}
+
+/// Parses an x,y window position, negative coordinates are fine on multi-monitor setups
+fn parse_window_pos(src : &str) -> Result<(i32, i32), String> {
+    let (x, y) = src.split_once(',')
+        .ok_or_else(|| format!("'{}' is not a window position, expected x,y", src))?;
+
+    match (x.trim().parse::<i32>(), y.trim().parse::<i32>()) {
+        (Ok(x), Ok(y)) => Ok((x, y)),
+        _ => Err(format!("'{}' is not a window position, expected x,y", src))
+    }
+}
+
+fn parse_font_size(src : &str) -> Result<u16, String> {
+    match src.parse::<u16>() {
+        Ok(size) if size >= 1 => Ok(size),
+        _ => Err(format!("'{}' is not a valid font size, expected a number between 1 and {}", src, u16::MAX))
+    }
+}
+
+fn parse_scale(src : &str) -> Result<u32, String> {
+    match src.parse::<u32>() {
+        Ok(scale) if scale >= 1 => Ok(scale),
+        _ => Err(format!("'{}' is not a valid scale, expected at least 1", src))
+    }
+}
+
+// Any faster and the period would round down to 0 nanoseconds
+const MAX_FREQ : u64 = 1_000_000_000;
+
+fn parse_freq(src : &str) -> Result<u64, String> {
+    match src.parse::<u64>() {
+        Ok(freq) if (1..=MAX_FREQ).contains(&freq) => Ok(freq),
+        _ => Err(format!("'{}' is not a valid frequency, expected a number of hz between 1 and {}", src, MAX_FREQ))
+    }
+}
+
+// Past what anyone can hear, and still below half the sample rate
+const MAX_BEEP_FREQ : f32 = 20_000.0;
+
+fn parse_beep_freq(src : &str) -> Result<f32, String> {
+    match src.parse::<f32>() {
+        Ok(freq) if (1.0..=MAX_BEEP_FREQ).contains(&freq) => Ok(freq),
+        _ => Err(format!("'{}' is not a valid beep frequency, expected a number of hz between 1 and {}", src, MAX_BEEP_FREQ))
+    }
+}
+
+fn parse_beep_volume(src : &str) -> Result<f32, String> {
+    match src.parse::<f32>() {
+        Ok(volume) if volume.is_finite() => Ok(volume),
+        _ => Err(format!("'{}' is not a valid volume, expected a number between 0.0 and 1.0", src))
+    }
+}
+
+fn parse_cycles_per_frame(src : &str) -> Result<u32, String> {
+    match src.parse::<u32>() {
+        Ok(cycles) if cycles >= 1 => Ok(cycles),
+        _ => Err(format!("'{}' is not a valid number of cycles, expected at least 1", src))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(args : &[&str], file : &str) -> Result<Config, String> {
+        let matches = Config::clap().get_matches_from(args);
+        let mut config = Config::from_clap(&matches);
+        config.merge_file(ConfigFile::parse(file)?, &matches)?;
+
+        Ok(config)
+    }
+
+    #[test]
+    fn the_command_line_wins_over_the_file() {
+        let config = merged(&["chip-8-vm", "rom.ch8", "--scale", "4", "--break", "0x300"], "\
+            scale = 10\n\
+            window-pos = \"-100,-50\"\n\
+            break = [\"0x2a4\", \"0x2b0\"]\n\
+            shift-quirk = true\n\
+            beep-wave = \"sine\"\n").unwrap();
+
+        assert_eq!(config.scale(), 4);
+        assert_eq!(config.breakpoints(), [0x300]);
+        assert_eq!(config.window_pos(), Some((-100, -50)));
+        assert!(config.shift_quirk());
+        assert_eq!(config.beep_wave(), Waveform::Sine);
+    }
+
+    #[test]
+    fn file_values_are_checked_like_flags() {
+        assert!(merged(&["chip-8-vm", "rom.ch8"], "scale = 0").is_err());
+        assert!(merged(&["chip-8-vm", "rom.ch8"], "patch = [\"0x100=0x01\"]").is_err());
+        assert!(merged(&["chip-8-vm", "rom.ch8"], "profile = \"gameboy\"").is_err());
+        assert!(merged(&["chip-8-vm", "rom.ch8", "--scale", "4"], "scale = 0").is_ok());
+    }
+}
@@ -0,0 +1,135 @@
+use serde::Deserialize;
+
+use std::fs;
+
+// Config files hold flags, so a setup that's used often doesn't have to be typed every time.
+// They're TOML, with a key per flag named as on the command line:
+//
+//      # Comments and empty lines are ignored
+//      scale = 10
+//      theme = "green"
+//      shift-quirk = true
+//      font_path = "/usr/share/fonts/TTF/DejaVuSans.ttf"
+//      patch = ["0x2a4=0x09", "0x2a6=0x00"]
+//
+// Switches are booleans, numbers are plain TOML numbers, and the values the command line parses
+// (addresses, colors, paths, ...) are strings written the same way as there. The flags that can
+// be repeated take an array. The ROM and the one-off commands (--check, --disasm, --selftest and
+// --list-keys) stay on the command line
+
+/// The settings of a config file, left as None when the file doesn't mention them
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(rename = "wrapping_enabled")]
+    pub wrapping_enabled : Option<bool>,
+    pub profile : Option<String>,
+    pub clip_mode : Option<String>,
+    pub shift_quirk : Option<bool>,
+    pub jump_quirk : Option<bool>,
+    pub load_store_quirk : Option<bool>,
+    pub vf_reset : Option<bool>,
+    pub vblank_quirk : Option<bool>,
+    pub no_wrap_start : Option<bool>,
+    #[serde(rename = "font_path")]
+    pub font_path : Option<String>,
+    pub terminal : Option<bool>,
+    pub show_fps : Option<bool>,
+    pub no_debug_panel : Option<bool>,
+    pub scale : Option<u32>,
+    pub fullscreen : Option<bool>,
+    pub window_pos : Option<String>,
+    pub window_centered : Option<bool>,
+    pub invert : Option<bool>,
+    pub rainbow : Option<bool>,
+    pub pause_dim : Option<u8>,
+    pub step : Option<bool>,
+    #[serde(rename = "break")]
+    pub breakpoints : Option<Vec<String>>,
+    pub frame_timing : Option<bool>,
+    pub inline_timers : Option<bool>,
+    pub freq : Option<u64>,
+    pub deterministic_timers : Option<bool>,
+    pub cycles_per_frame : Option<u32>,
+    #[serde(rename = "disable-keys")]
+    pub disabled_keys : Option<Vec<String>>,
+    pub keymap : Option<String>,
+    #[serde(rename = "patch")]
+    pub patches : Option<Vec<String>>,
+    #[serde(rename = "load")]
+    pub loads : Option<Vec<String>>,
+    pub theme : Option<String>,
+    pub fg_color : Option<String>,
+    pub bg_color : Option<String>,
+    pub rewind_depth : Option<usize>,
+    pub reset_clears_rpl : Option<bool>,
+    pub compare_trace : Option<String>,
+    pub trace : Option<String>,
+    pub record : Option<String>,
+    pub replay : Option<String>,
+    pub disasm_format : Option<String>,
+    pub cycles : Option<u64>,
+    pub max_cycles : Option<u64>,
+    pub max_seconds : Option<u64>,
+    pub seed : Option<u64>,
+    pub panel_font_size : Option<u16>,
+    pub no_audio : Option<bool>,
+    pub mute : Option<bool>,
+    pub beep_freq : Option<f32>,
+    pub beep_volume : Option<f32>,
+    pub beep_wave : Option<String>,
+    pub strict : Option<bool>,
+    pub log_level : Option<String>
+}
+
+impl ConfigFile {
+    pub fn from_path(path : &str) -> Result<ConfigFile, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read the config file {}: {}", path, e))?;
+
+        ConfigFile::parse(&text).map_err(|e| format!("In the config file {}: {}", path, e))
+    }
+
+    pub fn parse(text : &str) -> Result<ConfigFile, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_read_by_flag_name() {
+        let file = ConfigFile::parse("\
+            # A comment\n\
+            scale = 10 # big\n\
+            theme = \"green # not a comment\"\n\
+            shift-quirk = true\n\
+            rainbow = false\n\
+            font_path = \"font.ttf\"\n\
+            window-pos = \"-100,50\"\n\
+            beep-volume = 1\n\
+            patch = [\"0x2a4=0x09\", \"0x2a6=0x00\",]\n").unwrap();
+
+        assert_eq!(file, ConfigFile {
+            scale : Some(10),
+            theme : Some(String::from("green # not a comment")),
+            shift_quirk : Some(true),
+            rainbow : Some(false),
+            font_path : Some(String::from("font.ttf")),
+            window_pos : Some(String::from("-100,50")),
+            beep_volume : Some(1.0),
+            patches : Some(vec![String::from("0x2a4=0x09"), String::from("0x2a6=0x00")]),
+            ..ConfigFile::default()
+        });
+    }
+
+    #[test]
+    fn broken_files_say_where() {
+        assert!(ConfigFile::parse("scale = 10\nscale = 5").unwrap_err().contains("duplicate field `scale`"));
+        assert!(ConfigFile::parse("theme = \"green").unwrap_err().contains("line 1"));
+        assert!(ConfigFile::parse("scale = \"big\"").unwrap_err().contains("line 1"));
+        assert!(ConfigFile::parse("scael = 10").unwrap_err().contains("unknown field `scael`"));
+    }
+}
@@ -0,0 +1,5 @@
+// The VM can also be used as a library, main.rs is just the default frontend for it
+pub mod chip8;
+pub mod config;
+pub mod config_file;
+pub mod theme;
@@ -1,65 +1,103 @@
 use sdl2;
 
-//#[path = "cpu/cpu.rs"] // Another way to do it
-mod chip8;
-mod config;
-
-use std::{thread, time};
-//use std::time::SystemTime;
-use std::sync::{Arc, Mutex};
-use std::rc::Rc;
-use std::cell::RefCell;
-use std::sync::mpsc::{self};
-use structopt::StructOpt;
+use chip_8_vm::{chip8, config};
+
+use log::info;
 
 fn main() {
-    // https://jackson-s.me/2019/07/13/Chip-8-Instruction-Scheduling-and-Frequency.html
-    // we run the main loop at 550hz (~1.82ms), and the timers at 60Hz
-    
-    let freq_period : Rc<RefCell<u64>> = Rc::new(RefCell::new(1820000)); // Shared with they keypad, inside the cpu
-    let config = config::Config::from_args();
-
-    // SDL2
-    let sdl_context = sdl2::init().unwrap();
-    let ttf_context = sdl2::ttf::init().unwrap();
-
-    // Timers and pause shared variables
-    let timers : Arc<Mutex<(u8, u8)>> = Arc::new(Mutex::new((0,0)));
-    let pause : Rc<RefCell<bool>> = Rc::new(RefCell::new(false));
-
-    // Cpu
-    let mut cpu = chip8::Cpu::new(&sdl_context, &config, Arc::clone(&timers), Rc::clone(&pause), Rc::clone(&freq_period), ttf_context);
-    let mut wants_to_quit = false;
-    
-    // Timer loop and beep flag
-    let (tx, rx) = mpsc::channel();
-
-    let must_beep = Arc::new(Mutex::new(false));
-
-    let must_beep_inner = Arc::clone(&must_beep);
-    let handler = thread::spawn(move || {
-        let mut timer_subsystem = chip8::Timer::new(Arc::clone(&timers), rx, must_beep_inner);
-        timer_subsystem.run();
-    });
-
-    // Sound subsystem
-    let sound_subsystem = chip8::Sound::new(&sdl_context);
-
-    while ! (cpu.finished() || wants_to_quit) {
-        wants_to_quit = cpu.poll_keypad();
-        
-        cpu.cycle();    
-        
-        if * must_beep.lock().unwrap() {
-            sound_subsystem.beep();
+    let config = config::Config::load();
+
+    // RUST_LOG still takes precedence, for finer grained filtering
+    env_logger::Builder::new()
+        .filter_level(config.log_level())
+        .parse_default_env()
+        .init();
+
+    if config.list_keys() {
+        print!("{}", chip8::describe_keys(&config.keymap()));
+        return;
+    }
+
+    if config.disasm() {
+        let path = config.rom_path().unwrap_or_else(|| {
+            eprintln!("--disasm needs a ROM path");
+            std::process::exit(2);
+        });
+        let rom = std::fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", path, e);
+            std::process::exit(2);
+        });
+
+        print!("{}", chip8::format_listing(&chip8::disassemble_rom(&rom), config.disasm_format()));
+        return;
+    }
+
+    // The self-test and the checks don't need a window, so they also work without a display server
+    if config.selftest() {
+        let mut emulator = chip8::Emulator::new_headless(&config).unwrap_or_else(|e| fail(&e));
+        let cpu = emulator.cpu_mut();
+
+        match chip8::run_selftest(cpu) {
+            Ok(()) => println!("Self-test passed"),
+            Err(e) => {
+                eprintln!("Self-test failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(path) = config.check() {
+        let expected = chip8::Expected::from_path(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        });
+        let mut emulator = chip8::Emulator::new_headless(&config).unwrap_or_else(|e| fail(&e));
+        let cpu = emulator.cpu_mut();
+
+        for cycle in 0..config.cycles() {
+            if cycle % config.cycles_per_frame() as u64 == 0 {
+                cpu.frame_started();
+            }
+            cpu.cycle();
+        }
+
+        let diffs = expected.diff(cpu);
+        if diffs.is_empty() {
+            println!("Check passed after {} cycles", config.cycles());
         } else {
-            sound_subsystem.stop_beep();
+            eprintln!("Check failed after {} cycles:", config.cycles());
+            for diff in diffs {
+                eprintln!("    {}", diff);
+            }
+            std::process::exit(1);
         }
+        return;
+    }
+
+    // SDL2, unless running in the terminal
+    let sdl_context;
+    let mut emulator = if config.terminal() {
+        chip8::Emulator::new_terminal(&config)
+    } else {
+        sdl_context = sdl2::init().unwrap();
+        let ttf_context = sdl2::ttf::init().unwrap();
+
+        chip8::Emulator::new(&sdl_context, &config, ttf_context)
+    }.unwrap_or_else(|e| fail(&e));
+    emulator.run();
 
-        thread::sleep(time::Duration::from_nanos(*freq_period.borrow()));
+    if emulator.limit_reached() {
+        println!("Stopped after {} cycles", emulator.cycles());
+        emulator.cpu().dump_state();
     }
 
-    let _ = tx.send(()); // Tell the timer subsystem to stop
-    handler.join().unwrap();
-    println!("Terminating VM...");
+    drop(emulator); // Stops the timer thread
+    info!("Terminating VM...");
+}
+
+/// Prints the error and exits with a nonzero status
+fn fail(message : &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
@@ -0,0 +1,160 @@
+use std::fs;
+
+// A theme groups every color used when drawing, so it can be shared as a single file
+// Theme files contain `key = value` lines, where values are RRGGBB hex colors:
+//
+//      # Comments and empty lines are ignored
+//      foreground = c62bf8
+//      background = 000000
+//      panel_text = c23938
+//      panel_background = 000000
+//      palette = 000000, c62bf8, 5e1577, ffffff
+//
+// Missing keys keep the value of the default (purple) theme. The palette holds the four
+// XO-CHIP colors, indexed by the value of both bit-planes
+
+/// A color as its red, green and blue components, converted by each frontend to its own type
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    foreground : Rgb,
+    background : Rgb,
+    panel_text : Rgb,
+    panel_background : Rgb,
+    palette : [Rgb; 4]
+}
+
+// The original colors, I like purple
+const PURPLE : Theme = Theme {
+    foreground : (198, 43, 248),
+    background : (0, 0, 0),
+    panel_text : (194, 57, 56),
+    panel_background : (0, 0, 0),
+    palette : [(0, 0, 0), (198, 43, 248), (94, 21, 119), (255, 255, 255)]
+};
+
+// Green phosphor monitor
+const GREEN : Theme = Theme {
+    foreground : (51, 255, 51),
+    background : (0, 17, 0),
+    panel_text : (51, 255, 51),
+    panel_background : (0, 17, 0),
+    palette : [(0, 17, 0), (51, 255, 51), (17, 119, 17), (170, 255, 170)]
+};
+
+// Amber phosphor monitor
+const AMBER : Theme = Theme {
+    foreground : (255, 176, 0),
+    background : (26, 13, 0),
+    panel_text : (255, 176, 0),
+    panel_background : (26, 13, 0),
+    palette : [(26, 13, 0), (255, 176, 0), (128, 80, 0), (255, 224, 160)]
+};
+
+impl Theme {
+    /// Returns the built-in theme with that name, or loads the theme file at that path
+    pub fn from_name_or_path(src : &str) -> Result<Theme, String> {
+        match src {
+            "purple" => Ok(PURPLE),
+            "green" => Ok(GREEN),
+            "amber" => Ok(AMBER),
+            path => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read theme file {}: {}", path, e))?;
+
+                Theme::parse(&contents).map_err(|e| format!("Invalid theme file {}: {}", path, e))
+            }
+        }
+    }
+
+    /// Parses the contents of a theme file
+    fn parse(contents : &str) -> Result<Theme, String> {
+        let mut theme = PURPLE;
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("line {}: expected `key = value`", number + 1))
+            };
+
+            let color = |value| parse_hex_color(value).map_err(|e| format!("line {}: {}", number + 1, e));
+
+            match key {
+                "foreground" => theme.foreground = color(value)?,
+                "background" => theme.background = color(value)?,
+                "panel_text" => theme.panel_text = color(value)?,
+                "panel_background" => theme.panel_background = color(value)?,
+                "palette" => {
+                    let colors : Vec<&str> = value.split(',').collect();
+                    if colors.len() != 4 {
+                        return Err(format!("line {}: the palette needs exactly 4 colors", number + 1));
+                    }
+
+                    for (i, value) in colors.into_iter().enumerate() {
+                        theme.palette[i] = color(value.trim())?;
+                    }
+                },
+                _ => return Err(format!("line {}: unknown key `{}`", number + 1, key))
+            }
+        }
+
+        Ok(theme)
+    }
+
+    /// Replaces the pixel colors, such as with --fg-color and --bg-color. The background is also
+    /// used for the panel, and both go into the matching XO-CHIP palette entries
+    pub fn with_colors(mut self, foreground : Option<Rgb>, background : Option<Rgb>) -> Theme {
+        if let Some(color) = foreground {
+            self.foreground = color;
+            self.palette[1] = color;
+        }
+
+        if let Some(color) = background {
+            self.background = color;
+            self.panel_background = color;
+            self.palette[0] = color;
+        }
+
+        self
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.foreground
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.background
+    }
+
+    pub fn panel_text(&self) -> Rgb {
+        self.panel_text
+    }
+
+    pub fn panel_background(&self) -> Rgb {
+        self.panel_background
+    }
+
+    pub fn palette(&self) -> [Rgb; 4] {
+        self.palette
+    }
+}
+
+/// Parses an RRGGBB hex string (an optional leading # is allowed) into a color
+pub fn parse_hex_color(src : &str) -> Result<Rgb, String> {
+    let hex = src.trim_start_matches('#');
+
+    if hex.len() != 6 || ! hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("'{}' is not a RRGGBB hex color", src));
+    }
+
+    // Safe to unwrap, we already checked every digit
+    let component = |i : usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+
+    Ok((component(0), component(2), component(4)))
+}
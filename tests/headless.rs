@@ -0,0 +1,1188 @@
+// Runs small programs on a cpu without any window, checking the registers and the screen
+
+use chip_8_vm::chip8::{self, Cpu};
+use chip_8_vm::config::Config;
+
+use std::sync::{Arc, Mutex};
+use std::rc::Rc;
+use std::cell::RefCell;
+use structopt::StructOpt;
+
+fn config(args : &[&str]) -> Config {
+    // The self-test flag is only there so no ROM path is needed
+    let mut all_args = vec!["chip-8-vm", "--selftest"];
+    all_args.extend_from_slice(args);
+
+    Config::from_iter(all_args)
+}
+
+fn headless_cpu(config : &Config) -> Cpu<'_> {
+    Cpu::new_headless(config, Arc::new(Mutex::new((0, 0))), Arc::new(Mutex::new(false)), Rc::new(RefCell::new(0))).unwrap()
+}
+
+/// Loads the program and runs one cycle per instruction
+fn run(cpu : &mut Cpu, program : &[u16]) {
+    let rom : Vec<u8> = program.iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    cpu.load_rom_from_bytes(&rom);
+
+    for _ in program {
+        cpu.cycle();
+    }
+}
+
+/// Like run, with some data written into memory after loading the program
+fn run_with_data(cpu : &mut Cpu, program : &[u16], addr : usize, data : &[u8]) {
+    let rom : Vec<u8> = program.iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    cpu.load_rom_from_bytes(&rom);
+    cpu.write_memory(addr, data);
+
+    for _ in program {
+        cpu.cycle();
+    }
+}
+
+#[test]
+fn selftest_passes() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    assert_eq!(chip8::run_selftest(&mut cpu), Ok(()));
+}
+
+#[test]
+fn instruction_log_starts_with_the_latest_opcode() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    run(&mut cpu, &[0x6001, 0x6102, 0x6203]);
+
+    assert_eq!(cpu.instruction_log(), &[0x6203, 0x6102, 0x6001]);
+}
+
+#[test]
+fn drawing_twice_collides_and_erases() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    // LD F, V0 (glyph 0), then draw it twice at (0, 0)
+    run(&mut cpu, &[0xf029, 0xd005, 0xd005]);
+
+    assert_eq!(cpu.v()[0xf], 1);
+    assert!(cpu.screen().iter().flatten().all(|&pixel| pixel == 0));
+}
+
+#[test]
+fn fx65_wraps_around_the_end_of_memory() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    // LD I, 0xffe and LD V5, [I], which reads 0xffe, 0xfff and then the start of the "0" glyph
+    run(&mut cpu, &[0xaffe, 0xf565]);
+
+    assert_eq!(cpu.v()[..6], [0x00, 0x00, 0xf0, 0x90, 0x90, 0x90]);
+}
+
+#[test]
+fn fx55_wraps_around_the_end_of_memory() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    // Store V0-V5 at 0xffe, clear them, and load them back
+    run(&mut cpu, &[
+        0x6001, 0x6102, 0x6203, 0x6304, 0x6405, 0x6506,
+        0xaffe, 0xf555,
+        0x6000, 0x6100, 0x6200, 0x6300, 0x6400, 0x6500,
+        0xaffe, 0xf565,
+    ]);
+
+    assert_eq!(cpu.v()[..6], [1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn shifting_vf_keeps_the_flag() {
+    let config = config(&[]);
+
+    // LD VF, 0x03 and SHR VF, the shifted out bit wins over the shifted value
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0x6f03, 0x8f06]);
+    assert_eq!(cpu.v()[0xf], 1);
+
+    // LD VF, 0x40 and SHL VF
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0x6f40, 0x8f0e]);
+    assert_eq!(cpu.v()[0xf], 0);
+}
+
+#[test]
+fn sys_calls_are_ignored() {
+    let config = config(&["--strict"]);
+    let mut cpu = headless_cpu(&config);
+
+    run(&mut cpu, &[0x0123]);
+
+    assert_eq!(cpu.pc(), 0x202);
+    assert_eq!(cpu.v(), &[0; 16]);
+}
+
+#[test]
+fn unknown_opcodes_stop_strict_runs() {
+    // 5121 isn't anything, then LD V0, 0x01
+    let lenient = config(&[]);
+    let mut cpu = headless_cpu(&lenient);
+    run(&mut cpu, &[0x5121, 0x6001]);
+    assert_eq!(cpu.v()[0], 1);
+
+    let strict = config(&["--strict"]);
+    let mut cpu = headless_cpu(&strict);
+    run(&mut cpu, &[0x5121, 0x6001]);
+    assert!(cpu.finished());
+    assert_eq!(cpu.pc(), 0x200);
+    assert_eq!(cpu.v()[0], 0);
+}
+
+#[test]
+fn broken_stack_use_stops_the_cpu() {
+    let config = config(&[]);
+
+    // RET without a CALL
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0x00ee]);
+    assert!(cpu.finished());
+    assert_eq!(cpu.pc(), 0x200);
+
+    // CALL 0x200 forever, until the stack is full
+    let mut cpu = headless_cpu(&config);
+    let rom = [0x22, 0x00];
+    cpu.load_rom_from_bytes(&rom);
+    for _ in 0..20 {
+        cpu.cycle();
+    }
+    assert!(cpu.finished());
+    assert_eq!(cpu.stack().len(), 16);
+}
+
+#[test]
+fn expected_state_is_compared() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    // LD V0, 0xff, ADD V0, 0x01 (wraps without touching VF), LD I, 0x300 and LD [I], V0
+    run(&mut cpu, &[0x60ff, 0x7001, 0xa300, 0xf055]);
+
+    let expected = chip8::Expected::parse("pc = 0x208\nv0 = 0\nvf = 0\n[0x300] = 0").unwrap();
+    assert_eq!(expected.diff(&cpu), Vec::<String>::new());
+
+    let expected = chip8::Expected::parse("v0 = 1\n[0x300] = 1").unwrap();
+    assert_eq!(expected.diff(&cpu), vec!["v0: expected 0x01, got 0x00", "[0x300]: expected 0x01, got 0x00"]);
+}
+
+#[test]
+#[cfg(feature = "sdl")]
+fn emulator_runs_a_frame_worth_of_cycles() {
+    let config = config(&[]);
+    let mut emulator = chip8::Emulator::new_headless(&config).unwrap();
+
+    // ADD V0, 0x01 and JP 0x200, at 550hz that's 10 cycles per 60hz frame
+    emulator.cpu_mut().load_rom_from_bytes(&[0x70, 0x01, 0x12, 0x00]);
+
+    assert!(emulator.run_one_frame());
+    assert_eq!(emulator.cpu().v()[0], 5);
+}
+
+#[test]
+#[cfg(feature = "sdl")]
+fn frame_timing_runs_a_burst_then_ticks_the_timers() {
+    let config = config(&["--frame-timing"]);
+    let mut emulator = chip8::Emulator::new_headless(&config).unwrap();
+
+    // LD V2, 3, LD DT, V2, then LD V1, DT and JP 0x204 forever. 550hz fits 9 cycles in a
+    // frame, and the timers only tick once it's over
+    emulator.cpu_mut().load_rom_from_bytes(&[0x62, 0x03, 0xf2, 0x15, 0xf1, 0x07, 0x12, 0x04]);
+
+    assert!(emulator.run_one_frame());
+    assert_eq!(emulator.cycles(), 9);
+    assert_eq!(emulator.cpu().v()[1], 3);
+
+    assert!(emulator.run_one_frame());
+    assert_eq!(emulator.cycles(), 18);
+    assert_eq!(emulator.cpu().v()[1], 2);
+}
+
+#[test]
+fn clip_mode_decides_what_happens_at_the_borders() {
+    // LD V0, 60, LD V1, 30, LD F, V2 (glyph 0) and DRW V0, V1, 5
+    let program = [0x603c, 0x611e, 0xf229, 0xd015];
+
+    let clip = config(&["--clip-mode", "clip"]);
+    let mut cpu = headless_cpu(&clip);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.last_draw().clipped_rows, 3);
+    assert_eq!(cpu.screen()[0][60], 0);
+
+    // The old flag still means wrap
+    let wrap = config(&["--wrapping_enabled"]);
+    let mut cpu = headless_cpu(&wrap);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.last_draw().clipped_rows, 0);
+    assert_eq!(cpu.screen()[0][60], 1);
+}
+
+#[test]
+fn input_resets_the_cycles_since_input() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    run(&mut cpu, &[0x6001, 0x6102, 0x6203]);
+    assert_eq!(cpu.cycles_since_input(), 3);
+
+    cpu.key_down(0x5);
+    assert_eq!(cpu.cycles_since_input(), 0);
+
+    cpu.cycle();
+    cpu.key_down(0x5); // already pressed, nothing changes
+    assert_eq!(cpu.cycles_since_input(), 1);
+}
+
+#[test]
+fn deterministic_timers_follow_the_cycle_count() {
+    let config = config(&["--deterministic-timers", "--cycles-per-frame", "2"]);
+    let mut cpu = headless_cpu(&config);
+
+    // LD V0, 2 and LD DT, V0 (tick after this cycle), LD V1, DT,
+    // then a filler instruction (tick after it) and LD V2, DT
+    run(&mut cpu, &[0x6002, 0xf015, 0xf107, 0x6300, 0xf207]);
+
+    assert_eq!(cpu.v()[1], 1);
+    assert_eq!(cpu.v()[2], 0);
+}
+
+#[test]
+fn display_wait_draws_at_the_start_of_the_next_frame() {
+    let config = config(&["--deterministic-timers", "--cycles-per-frame", "4", "--vblank-quirk"]);
+    let mut cpu = headless_cpu(&config);
+
+    // DRW V0, V0, 5 keeps waiting through the rest of the first frame
+    run(&mut cpu, &[0xd005]);
+    for _ in 0..3 {
+        cpu.cycle();
+    }
+    assert_eq!(cpu.pc(), 0x200);
+    assert_eq!(cpu.screen()[0][0], 0);
+    assert!(cpu.instruction_log().is_empty());
+
+    // It's only logged once, when it actually draws
+    cpu.cycle();
+    assert_eq!(cpu.pc(), 0x202);
+    assert_eq!(cpu.screen()[0][0], 1);
+    assert_eq!(cpu.instruction_log().iter().collect::<Vec<_>>(), [&0xd005]);
+}
+
+#[test]
+fn display_wait_follows_the_frames_that_run() {
+    let config = config(&["--vblank-quirk"]);
+    let pause = Arc::new(Mutex::new(false));
+    let mut cpu = Cpu::new_headless(&config, Arc::new(Mutex::new((0, 0))), Arc::clone(&pause), Rc::new(RefCell::new(0))).unwrap();
+
+    // DRW V0, V0, 5 twice, it doesn't matter how long it takes until the next frame starts
+    cpu.load_rom_from_bytes(&[0xd0, 0x05, 0xd0, 0x05]);
+    cpu.cycle();
+    std::thread::sleep(std::time::Duration::from_millis(40));
+    cpu.cycle();
+    assert_eq!(cpu.pc(), 0x200);
+
+    cpu.frame_started();
+    cpu.cycle();
+    assert_eq!(cpu.pc(), 0x202);
+    assert_eq!(cpu.screen()[0][0], 1);
+
+    // A step runs it right away
+    *pause.lock().unwrap() = true;
+    cpu.request_step();
+    cpu.cycle();
+    assert_eq!(cpu.pc(), 0x204);
+    assert_eq!(cpu.screen()[0][0], 0);
+}
+
+#[test]
+fn add_immediate_wraps_without_touching_vf() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    // LD VF, 0x55, LD V0, 0xff and ADD V0, 0x01
+    run(&mut cpu, &[0x6f55, 0x60ff, 0x7001]);
+
+    assert_eq!(cpu.v()[0], 0x00);
+    assert_eq!(cpu.v()[0xf], 0x55);
+}
+
+/// Runs LD V0, a, LD V1, b and the given 8XY_ instruction, returns (V0, VF)
+fn alu(a : u8, b : u8, instr : u16) -> (u8, u8) {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    run(&mut cpu, &[0x6000 | a as u16, 0x6100 | b as u16, instr]);
+
+    (cpu.v()[0], cpu.v()[0xf])
+}
+
+#[test]
+fn add_sets_vf_on_carry() {
+    // ADD V0, V1
+    assert_eq!(alu(0xff, 0x01, 0x8014), (0x00, 1));
+    assert_eq!(alu(0xfe, 0x01, 0x8014), (0xff, 0));
+}
+
+#[test]
+fn sub_clears_vf_on_borrow() {
+    // SUB V0, V1
+    assert_eq!(alu(0x00, 0x01, 0x8015), (0xff, 0));
+    assert_eq!(alu(0x01, 0x01, 0x8015), (0x00, 1));
+
+    // SUBN V0, V1
+    assert_eq!(alu(0x01, 0x00, 0x8017), (0xff, 0));
+    assert_eq!(alu(0x01, 0x01, 0x8017), (0x00, 1));
+}
+
+#[test]
+fn flags_win_when_vf_is_the_destination() {
+    let config = config(&[]);
+
+    // LD VF, 0xff, LD V1, 0x01 and ADD VF, V1, the carry overwrites the sum
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0x6fff, 0x6101, 0x8f14]);
+    assert_eq!(cpu.v()[0xf], 1);
+
+    // LD VF, 0x00, LD V1, 0x01 and SUB VF, V1, the borrow overwrites the difference
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0x6f00, 0x6101, 0x8f15]);
+    assert_eq!(cpu.v()[0xf], 0);
+}
+
+#[test]
+fn bcd_stores_hundreds_tens_and_ones() {
+    for &(value, digits) in &[(254u8, [2, 5, 4]), (7, [0, 0, 7]), (100, [1, 0, 0])] {
+        // LD V0, value, LD I, 0x300 and LD B, V0
+        let config = config(&[]);
+        let mut cpu = headless_cpu(&config);
+        run(&mut cpu, &[0x6000 | value as u16, 0xa300, 0xf033]);
+
+        assert_eq!(cpu.memory()[0x300..0x303], digits, "{}", value);
+        assert_eq!(cpu.i(), 0x300);
+    }
+}
+
+/// Runs LD V0, a, LD V1, b and the given skip instruction, returns whether it skipped
+fn skips(a : u8, b : u8, instr : u16) -> bool {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+    cpu.key_down(0x3);
+
+    run(&mut cpu, &[0x6000 | a as u16, 0x6100 | b as u16, instr]);
+
+    cpu.pc() == 0x208
+}
+
+#[test]
+fn skip_instructions_compare_registers_and_keys() {
+    // SE V0, 0x12 and SNE V0, 0x12
+    assert!(skips(0x12, 0, 0x3012));
+    assert!(! skips(0x13, 0, 0x3012));
+    assert!(skips(0x13, 0, 0x4012));
+    assert!(! skips(0x12, 0, 0x4012));
+
+    // SE V0, V1 and SNE V0, V1
+    assert!(skips(0x12, 0x12, 0x5010));
+    assert!(! skips(0x12, 0x13, 0x5010));
+    assert!(skips(0x12, 0x13, 0x9010));
+    assert!(! skips(0x12, 0x12, 0x9010));
+
+    // SKP V0 and SKNP V0, with only key 3 pressed
+    assert!(skips(0x3, 0, 0xe09e));
+    assert!(! skips(0x4, 0, 0xe09e));
+    assert!(skips(0x4, 0, 0xe0a1));
+    assert!(! skips(0x3, 0, 0xe0a1));
+}
+
+#[test]
+fn drawing_sets_vf_only_on_collisions() {
+    let config = config(&[]);
+
+    // LD I, 0x300, DRW V0, V0, 1 twice, with the second sprite overlapping the first by a pixel
+    let mut cpu = headless_cpu(&config);
+    run_with_data(&mut cpu, &[0xa300, 0xd001, 0xa301, 0xd001], 0x300, &[0b11000000, 0b01100000]);
+    assert_eq!(cpu.v()[0xf], 1);
+    assert_eq!(&cpu.screen()[0][..4], &[1, 0, 1, 0]);
+
+    // Drawing the first sprite twice collides and erases it, then one that overlaps nothing clears VF again
+    let mut cpu = headless_cpu(&config);
+    run_with_data(&mut cpu, &[0xa300, 0xd001, 0xd001, 0xa301, 0xd001], 0x300, &[0b11000000, 0b00110000]);
+    assert_eq!(cpu.v()[0xf], 0);
+    assert_eq!(&cpu.screen()[0][..4], &[0, 0, 1, 1]);
+}
+
+#[test]
+fn cosmac_vip_profile_stalls_after_drawing() {
+    // LD F, V0 (glyph 0), DRW V0, V0, 5 and LD V1, 1
+    let program = [0xf029, 0xd005, 0x6101];
+
+    let default = config(&[]);
+    let mut cpu = headless_cpu(&default);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.v()[1], 1);
+
+    // The five rows take a cycle each before the next instruction runs
+    let vip = config(&["--profile", "cosmac-vip"]);
+    let mut cpu = headless_cpu(&vip);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.v()[1], 0);
+
+    for _ in 0..4 {
+        cpu.cycle();
+    }
+    assert_eq!(cpu.v()[1], 0);
+
+    cpu.cycle();
+    assert_eq!(cpu.v()[1], 1);
+}
+
+#[test]
+fn sprite_origin_wraps_in_clip_mode() {
+    // LD V0, 70, LD V1, 40, LD F, V2 (glyph 0) and DRW V0, V1, 5, which starts at (6, 8)
+    let program = [0x6046, 0x6128, 0xf229, 0xd015];
+
+    let default = config(&[]);
+    let mut cpu = headless_cpu(&default);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.screen()[8][6], 1);
+    assert_eq!(cpu.last_draw().drawn, 14);
+
+    let no_wrap = config(&["--no-wrap-start"]);
+    let mut cpu = headless_cpu(&no_wrap);
+    run(&mut cpu, &program);
+    assert!(cpu.screen().iter().flatten().all(|&pixel| pixel == 0));
+}
+
+#[test]
+fn sound_timer_makes_it_beep_until_cleared() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+    assert!(! cpu.is_beeping());
+
+    // LD V0, 5 and LD ST, V0, nothing ticks the timers here
+    run(&mut cpu, &[0x6005, 0xf018]);
+    assert!(cpu.is_beeping());
+
+    cpu.clear_beep();
+    assert!(! cpu.is_beeping());
+}
+
+#[test]
+#[cfg(feature = "sdl")]
+fn emulator_stops_at_the_cycle_limit() {
+    let config = config(&["--max-cycles", "7"]);
+    let mut emulator = chip8::Emulator::new_headless(&config).unwrap();
+
+    // ADD V0, 0x01 and JP 0x200, forever
+    emulator.cpu_mut().load_rom_from_bytes(&[0x70, 0x01, 0x12, 0x00]);
+    emulator.run();
+
+    assert!(emulator.limit_reached());
+    assert_eq!(emulator.cycles(), 7);
+    assert_eq!(emulator.cpu().v()[0], 4);
+}
+
+#[test]
+fn extra_files_are_loaded_at_their_address() {
+    let path = std::env::temp_dir().join("chip-8-vm-load-test.bin");
+    std::fs::write(&path, [0xaa, 0xbb, 0xcc]).unwrap();
+
+    let load = format!("{}@0x600", path.display());
+    let config = config(&["--load", &load]);
+    let cpu = headless_cpu(&config);
+
+    assert_eq!(cpu.memory()[0x5ff..0x604], [0x00, 0xaa, 0xbb, 0xcc, 0x00]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn xo_chip_audio_loads_the_pattern_and_pitch() {
+    // LD I, 0x000 (the "0" and "1" glyphs), AUDIO, LD V0, 112 and PITCH V0
+    let program = [0xa000, 0xf002, 0x6070, 0xf03a];
+
+    let xo_chip = config(&["--profile", "xo-chip"]);
+    let mut cpu = headless_cpu(&xo_chip);
+    run(&mut cpu, &program);
+
+    let pattern = cpu.audio_pattern_changed().unwrap();
+    assert_eq!(pattern.pattern[..6], [0xf0, 0x90, 0x90, 0x90, 0xf0, 0x20]);
+    assert_eq!(pattern.pitch, 112);
+    assert_eq!(pattern.rate(), 8000.0);
+    assert_eq!(cpu.audio_pattern_changed(), None);
+
+    // Other platforms don't have those instructions
+    let default = config(&[]);
+    let mut cpu = headless_cpu(&default);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.audio_pattern_changed(), None);
+}
+
+#[test]
+fn reset_brings_the_beep_back_after_xo_chip_audio() {
+    // LD I, 0x000 and AUDIO
+    let path = std::env::temp_dir().join("chip-8-vm-reset-audio.ch8");
+    std::fs::write(&path, [0xa0, 0x00, 0xf0, 0x02]).unwrap();
+    let xo_chip = Config::from_iter(&["chip-8-vm", "--profile", "xo-chip", path.to_str().unwrap()]);
+
+    let mut cpu = headless_cpu(&xo_chip);
+    cpu.cycle();
+    cpu.cycle();
+    assert!(cpu.audio_pattern_changed().is_some());
+
+    cpu.reset(true).unwrap();
+    assert!(cpu.audio_pattern_cleared());
+    assert!(! cpu.audio_pattern_cleared());
+    assert_eq!(cpu.audio_pattern_changed(), None);
+
+    // Without a pattern there's nothing to clear, the beep was never replaced
+    cpu.reset(true).unwrap();
+    assert!(! cpu.audio_pattern_cleared());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+// The built-in font as it should look, one string per row
+const GLYPHS : [[&str; 5]; 16] = [
+    ["####", "#..#", "#..#", "#..#", "####"], // 0
+    ["..#.", ".##.", "..#.", "..#.", ".###"], // 1
+    ["####", "...#", "####", "#...", "####"], // 2
+    ["####", "...#", "####", "...#", "####"], // 3
+    ["#..#", "#..#", "####", "...#", "...#"], // 4
+    ["####", "#...", "####", "...#", "####"], // 5
+    ["####", "#...", "####", "#..#", "####"], // 6
+    ["####", "...#", "..#.", ".#..", ".#.."], // 7
+    ["####", "#..#", "####", "#..#", "####"], // 8
+    ["####", "#..#", "####", "...#", "####"], // 9
+    ["####", "#..#", "####", "#..#", "#..#"], // A
+    ["###.", "#..#", "###.", "#..#", "###."], // B
+    ["####", "#...", "#...", "#...", "####"], // C
+    ["###.", "#..#", "#..#", "#..#", "###."], // D
+    ["####", "#...", "####", "#...", "####"], // E
+    ["####", "#...", "####", "#...", "#..."], // F
+];
+
+#[test]
+fn font_glyphs_draw_the_expected_digits() {
+    let config = config(&[]);
+
+    for (digit, glyph) in GLYPHS.iter().enumerate() {
+        // LD V0, digit, LD F, V0 and DRW V1, V1, 5 at (0, 0)
+        let mut cpu = headless_cpu(&config);
+        run(&mut cpu, &[0x6000 | digit as u16, 0xf029, 0xd115]);
+
+        let drawn : Vec<String> = cpu.screen()[..5].iter()
+            .map(|row| row[..8].iter().map(|&pixel| if pixel == 1 { '#' } else { '.' }).collect())
+            .collect();
+        let expected : Vec<String> = glyph.iter().map(|row| format!("{}....", row)).collect();
+
+        assert_eq!(drawn, expected, "glyph {:X}", digit);
+    }
+}
+
+#[test]
+fn reference_trace_stops_at_the_first_difference() {
+    let path = std::env::temp_dir().join("chip-8-vm-trace-test.txt");
+    // The reference emulator skipped the third instruction
+    std::fs::write(&path, "0x0200 6001\n0x0202 6102\n0x0206 6304\n").unwrap();
+
+    let trace = path.display().to_string();
+    let config = config(&["--compare-trace", &trace]);
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0x6001, 0x6102, 0x6203, 0x6304]);
+
+    assert!(cpu.finished());
+    assert_eq!(cpu.pc(), 0x204);
+    assert_eq!(cpu.v()[..4], [1, 2, 0, 0]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn traces_can_be_compared_against_on_the_next_run() {
+    let path = std::env::temp_dir().join("chip-8-vm-trace-log.txt");
+    let trace = path.display().to_string();
+    // SE V1, 0x02 skips LD V2, so 4 cycles get to LD V3
+    let rom = [0x60, 0x01, 0x61, 0x02, 0x31, 0x02, 0x62, 0x03, 0x63, 0x04];
+
+    let tracing = config(&["--trace", &trace]);
+    let mut cpu = headless_cpu(&tracing);
+    cpu.load_rom_from_bytes(&rom);
+    for _ in 0..4 {
+        cpu.cycle();
+    }
+    drop(cpu); // writes out the rest of the trace
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    assert!(written.ends_with("0x0204 3102  SE V1, 0x02\n0x0208 6304  LD V3, 0x04\n"), "{}", written);
+
+    let comparing = config(&["--compare-trace", &trace]);
+    let mut cpu = headless_cpu(&comparing);
+    cpu.load_rom_from_bytes(&rom);
+    for _ in 0..4 {
+        cpu.cycle();
+    }
+    assert!(! cpu.finished());
+    assert_eq!(cpu.v()[..4], [1, 2, 0, 4]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Runs LD VF, 0x55 and each of OR, AND and XOR V0, V1 on its own cpu, returns VF after each of them
+fn vf_after_logic(args : &[&str]) -> Vec<u8> {
+    let config = config(args);
+
+    [0x8011, 0x8012, 0x8013].iter().map(|&instr| {
+        let mut cpu = headless_cpu(&config);
+        run(&mut cpu, &[0x6f55, instr]);
+
+        cpu.v()[0xf]
+    }).collect()
+}
+
+#[test]
+fn profiles_decide_whether_logic_resets_vf() {
+    assert_eq!(vf_after_logic(&["--profile", "cosmac-vip"]), [0, 0, 0]);
+    assert_eq!(vf_after_logic(&["--profile", "chip48"]), [0x55, 0x55, 0x55]);
+    assert_eq!(vf_after_logic(&["--profile", "schip"]), [0x55, 0x55, 0x55]);
+    assert_eq!(vf_after_logic(&[]), [0x55, 0x55, 0x55]);
+    assert_eq!(vf_after_logic(&["--vf-reset"]), [0, 0, 0]);
+}
+
+#[test]
+fn step_back_undoes_instructions() {
+    let config = config(&["--rewind-depth", "2"]);
+    let mut cpu = headless_cpu(&config);
+
+    // LD V0, 1, LD F, V0 (glyph 1), DRW V0, V0, 5
+    run(&mut cpu, &[0x6001, 0xf029, 0xd005]);
+    assert_eq!(cpu.screen()[1][3], 1);
+
+    assert!(cpu.step_back());
+    assert_eq!(cpu.pc(), 0x204);
+    assert!(cpu.screen().iter().flatten().all(|&pixel| pixel == 0));
+    assert_eq!(cpu.instruction_log(), &[0xf029, 0x6001]);
+
+    assert!(cpu.step_back());
+    assert_eq!(cpu.pc(), 0x202);
+    assert_eq!(cpu.i(), 0);
+
+    // Only two instructions are kept
+    assert!(! cpu.step_back());
+    assert_eq!(cpu.v()[0], 1);
+}
+
+#[test]
+fn step_back_rewinds_the_run_counters() {
+    let path = std::env::temp_dir().join("chip-8-vm-rewind-trace.txt");
+    std::fs::write(&path, "200 6001\n202 6102\n204 6203\n").unwrap();
+    let config = config(&["--rewind-depth", "4", "--compare-trace", path.to_str().unwrap()]);
+    let mut cpu = headless_cpu(&config);
+
+    // LD V0, 1, LD V1, 2 and LD V2, 3, the same as the trace
+    cpu.load_rom_from_bytes(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03]);
+    cpu.cycle();
+    cpu.cycle();
+    assert_eq!(cpu.cycles(), 2);
+
+    // Going back and running it again counts the same cycles and follows the trace from the same step
+    assert!(cpu.step_back());
+    assert_eq!(cpu.cycles(), 1);
+    cpu.cycle();
+    cpu.cycle();
+    assert_eq!(cpu.cycles(), 3);
+    assert!(! cpu.finished());
+    assert_eq!(cpu.v()[2], 3);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn captured_frames_follow_a_moving_sprite() {
+    let config = config(&["--cycles-per-frame", "4"]);
+    let mut cpu = headless_cpu(&config);
+
+    // CLS, DRW V0, V1, 1 (the top row of the "0" glyph, 4 pixels), ADD V0, 1 and JP 0x200,
+    // which moves the sprite one pixel to the right every frame
+    cpu.load_rom_from_bytes(&[0x00, 0xe0, 0xd0, 0x11, 0x70, 0x01, 0x12, 0x00]);
+    let frames = cpu.run_and_capture(5);
+
+    assert_eq!(frames.len(), 5);
+    for (x, frame) in frames.iter().enumerate() {
+        let lit : Vec<usize> = (0..64).filter(|&col| frame[0][col] == 1).collect();
+        assert_eq!(lit, (x..x + 4).collect::<Vec<usize>>(), "frame {}", x);
+    }
+}
+
+#[test]
+fn the_screen_is_presented_once_per_render() {
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+
+    let presented = Rc::new(RefCell::new(0));
+    let counter = Rc::clone(&presented);
+    cpu.set_frame_hook(Box::new(move |_| *counter.borrow_mut() += 1));
+
+    // DRW V0, V0, 5 twice, which only change the screen, then CLS
+    cpu.load_rom_from_bytes(&[0xd0, 0x05, 0xd0, 0x05, 0x00, 0xe0]);
+    cpu.cycle();
+    cpu.cycle();
+    assert_eq!(*presented.borrow(), 0);
+
+    cpu.render();
+    assert_eq!(*presented.borrow(), 1);
+
+    // Nothing new to present, and CLS is shown without waiting for a draw
+    cpu.render();
+    assert_eq!(*presented.borrow(), 1);
+    cpu.cycle();
+    cpu.render();
+    assert_eq!(*presented.borrow(), 2);
+}
+
+#[test]
+fn shift_quirk_shifts_vy_into_vx() {
+    // LD V0, 0x10, LD V1, 0x81, SHR V0, V1 and SHL V2, V1 (V2 starts at 0)
+    let program = [0x6010, 0x6181, 0x8016, 0x821e];
+
+    let default = config(&[]);
+    let mut cpu = headless_cpu(&default);
+    run(&mut cpu, &program);
+    assert_eq!((cpu.v()[0], cpu.v()[2], cpu.v()[0xf]), (0x08, 0x00, 0));
+
+    let quirk = config(&["--shift-quirk"]);
+    let mut cpu = headless_cpu(&quirk);
+    run(&mut cpu, &program);
+    assert_eq!((cpu.v()[0], cpu.v()[2], cpu.v()[0xf]), (0x40, 0x02, 1));
+}
+
+#[test]
+fn load_store_quirk_increments_i() {
+    // LD I, 0x300, LD [I], V2 and LD V1, [I]
+    let program = [0xa300, 0xf255, 0xf165];
+
+    let default = config(&[]);
+    let mut cpu = headless_cpu(&default);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.i(), 0x300);
+
+    let quirk = config(&["--load-store-quirk"]);
+    let mut cpu = headless_cpu(&quirk);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.i(), 0x305);
+}
+
+#[test]
+fn jump_quirk_adds_vx() {
+    // LD V0, 0x10, LD V3, 0x20 and JP V0, 0x300
+    let program = [0x6010, 0x6320, 0xb300];
+
+    let default = config(&[]);
+    let mut cpu = headless_cpu(&default);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.pc(), 0x310);
+
+    let quirk = config(&["--jump-quirk"]);
+    let mut cpu = headless_cpu(&quirk);
+    run(&mut cpu, &program);
+    assert_eq!(cpu.pc(), 0x320);
+}
+
+#[test]
+fn hires_mode_draws_on_128x64() {
+    // HIGH, LD V0, 0x7c, LD V1, 0x3f, LD V2, 0x0f, LD F, V2 and DRW V0, V1, 1
+    let program = [0x00ff, 0x607c, 0x613f, 0x620f, 0xf229, 0xd011];
+
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &program);
+
+    assert_eq!(cpu.resolution(), (128, 64));
+    assert_eq!(&cpu.screen()[63][124..128], &[1, 1, 1, 1]);
+    assert_eq!(cpu.v()[0x0f], 0);
+
+    // LOW goes back to 64x32 and clears the screen
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[&program[..], &[0x00fe]].concat());
+    assert_eq!(cpu.resolution(), (64, 32));
+    assert!(cpu.screen().iter().flatten().all(|&pixel| pixel == 0));
+}
+
+#[test]
+fn scroll_opcodes_move_the_screen() {
+    // LD F, V0 (the "0" glyph), DRW V0, V0, 1, then SCD 3 and SCR
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0xf029, 0xd001, 0x00c3, 0x00fb]);
+
+    assert!(cpu.screen()[0].iter().all(|&pixel| pixel == 0));
+    assert_eq!(&cpu.screen()[3][..9], &[0, 0, 0, 0, 1, 1, 1, 1, 0]);
+
+    // SCL moves it past the left border, and what comes in is cleared
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0xf029, 0xd001, 0x00fc]);
+    assert!(cpu.screen().iter().flatten().all(|&pixel| pixel == 0));
+}
+
+#[test]
+fn rpl_flags_keep_registers() {
+    // LD V0, 0x11, LD V7, 0x77, LD R, VF, LD V0, 0x00, LD V7, 0x00 and LD VF, R
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0x6011, 0x6777, 0xff75, 0x6000, 0x6700, 0xff85]);
+
+    // Only 8 flags, so VF is clamped to V7
+    assert_eq!(cpu.v()[0], 0x11);
+    assert_eq!(cpu.v()[7], 0x77);
+}
+
+#[test]
+fn big_font_is_separate_from_the_small_one() {
+    // LD V0, 0x08, LD HF, V0 and DRW V1, V1, 10 at (0, 0)
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0x6008, 0xf030, 0xd11a]);
+
+    assert_eq!(cpu.i(), 0x050 + 8 * 10);
+    let rows : Vec<u8> = cpu.screen()[..10].iter()
+        .map(|row| row[..8].iter().fold(0, |byte, &pixel| byte << 1 | pixel))
+        .collect();
+    assert_eq!(rows, [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C]);
+
+    // LD F, V0 still points at the small font
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0x6008, 0xf029]);
+    assert_eq!(cpu.i(), 8 * 5);
+}
+
+#[test]
+#[cfg(feature = "sdl")]
+fn unloadable_roms_are_reported() {
+    let missing = std::env::temp_dir().join("chip-8-vm-missing-rom.ch8");
+    let missing = missing.to_str().unwrap();
+    let config = Config::from_iter(&["chip-8-vm", missing]);
+
+    let error = chip8::Emulator::new_headless(&config).err().unwrap();
+    assert!(error.starts_with(&format!("Could not load ROM: {}: ", missing)), "{}", error);
+
+    // Anything past the end of memory would be lost
+    let path = std::env::temp_dir().join("chip-8-vm-oversized-rom.ch8");
+    std::fs::write(&path, vec![0; 4096 - 0x200 + 1]).unwrap();
+    let config = Config::from_iter(&["chip-8-vm", path.to_str().unwrap()]);
+
+    let error = chip8::Emulator::new_headless(&config).err().unwrap();
+    assert!(error.contains("3585 bytes long"), "{}", error);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn addresses_wrap_at_the_end_of_memory() {
+    // LD I, 0xfff, LD V0, 0xff, LD B, V0 and DRW V1, V1, 3 over the wrapped digits
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0xafff, 0x60ff, 0xf033, 0xd113]);
+
+    assert_eq!(cpu.memory()[0xfff], 2);
+    assert_eq!(cpu.memory()[0x000..2], [5, 5]);
+    assert_eq!(cpu.screen()[0][6], 1);
+
+    // ADD I, V0 wraps I too, and jumping past the end wraps the PC
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0xafff, 0x6002, 0xf01e, 0xbfff]);
+    assert_eq!(cpu.i(), 0x001);
+    assert_eq!(cpu.pc(), 0x001);
+}
+
+#[test]
+fn core_runs_without_sdl() {
+    let config = Config::default();
+    let mut core = chip8::core::Core::new(&config).unwrap();
+
+    // LD V0, 0x02, LD ST, V0, SKP V0 (with key 2 pressed) and LD F, V0 (skipped)
+    core.load_rom(&[0x60, 0x02, 0xf0, 0x18, 0xe0, 0x9e, 0xf0, 0x29]);
+    core.set_key(2, true);
+    for _ in 0..3 {
+        core.step();
+    }
+
+    assert_eq!(core.cpu().pc(), 0x208);
+    assert_eq!(core.resolution(), (64, 32));
+    assert!(core.framebuffer().iter().flatten().all(|&pixel| pixel == 0));
+
+    assert!(core.should_beep());
+    core.tick_timers();
+    core.tick_timers();
+    assert!(! core.should_beep());
+}
+
+#[test]
+fn color_overrides_replace_the_theme_colors() {
+    let config = config(&["--theme", "green", "--fg-color", "#ffb000"]);
+    assert_eq!(config.theme().foreground(), (0xff, 0xb0, 0x00));
+    assert_eq!(config.theme().background(), (0, 17, 0)); // still green's
+
+    // Bad colors are reported when parsing the flags, before anything runs
+    assert!(Config::from_iter_safe(&["chip-8-vm", "--selftest", "--bg-color", "00ff0"]).is_err());
+}
+
+#[test]
+fn reset_keeps_the_rpl_flags_unless_asked_to_clear_them() {
+    // LD V0, 0x05 and LD R, V0, then LD V0, R once reset
+    let path = std::env::temp_dir().join("chip-8-vm-reset-rpl.ch8");
+    let config = Config::from_iter(&["chip-8-vm", path.to_str().unwrap()]);
+    let mut restored = Vec::new();
+
+    for &keep_rpl in &[true, false] {
+        std::fs::write(&path, [0x60, 0x05, 0xf0, 0x75]).unwrap();
+        let mut cpu = headless_cpu(&config);
+        cpu.reset(true).unwrap();
+        cpu.cycle();
+        cpu.cycle();
+
+        std::fs::write(&path, [0xf0, 0x85]).unwrap();
+        cpu.reset(keep_rpl).unwrap();
+        cpu.cycle();
+        restored.push(cpu.v()[0]);
+    }
+
+    assert_eq!(restored, [5, 0]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reset_reloads_the_rom() {
+    // HIGH, LD V0, 0x05, LD DT, V0, LD F, V0 and DRW V0, V0, 5
+    let path = std::env::temp_dir().join("chip-8-vm-reset.ch8");
+    std::fs::write(&path, [0x00, 0xff, 0x60, 0x05, 0xf0, 0x15, 0xf0, 0x29, 0xd0, 0x05]).unwrap();
+    let config = Config::from_iter(&["chip-8-vm", path.to_str().unwrap()]);
+
+    let timers = Arc::new(Mutex::new((0, 0)));
+    let mut cpu = Cpu::new_headless(&config, Arc::clone(&timers), Arc::new(Mutex::new(false)), Rc::new(RefCell::new(0))).unwrap();
+    for _ in 0..5 {
+        cpu.cycle();
+    }
+    assert_eq!(cpu.resolution(), (128, 64));
+    assert_eq!(*timers.lock().unwrap(), (5, 0));
+
+    // A rebuilt ROM is picked up, LD V1, 0x01
+    std::fs::write(&path, [0x61, 0x01]).unwrap();
+    cpu.reset(true).unwrap();
+
+    assert_eq!(cpu.pc(), 0x200);
+    assert_eq!(cpu.v(), &[0; 16]);
+    assert_eq!(cpu.i(), 0);
+    assert!(cpu.instruction_log().is_empty());
+    assert_eq!(cpu.resolution(), (64, 32));
+    assert!(cpu.screen().iter().flatten().all(|&pixel| pixel == 0));
+    assert_eq!(*timers.lock().unwrap(), (0, 0));
+    assert_eq!(cpu.memory()[0x200..0x204], [0x61, 0x01, 0x00, 0x00]);
+
+    // Nothing changes if the ROM is gone
+    std::fs::remove_file(&path).unwrap();
+    cpu.cycle();
+    assert!(cpu.reset(true).is_err());
+    assert_eq!(cpu.v()[1], 1);
+}
+
+#[test]
+fn xo_chip_draws_and_clears_the_selected_planes() {
+    let config = config(&["--profile", "xo-chip"]);
+    let mut cpu = headless_cpu(&config);
+
+    // PLANE 3, LD I, 0x210 and DRW V0, V0, 1, with a sprite row for each plane at 0x210.
+    // Then PLANE 2 and CLS only clear the second plane, and PLANE 0 and DRW draw nothing
+    let program : Vec<u8> = [0xf301u16, 0xa210, 0xd001, 0xf201, 0x00e0, 0xf001, 0xd001].iter().flat_map(|instr| instr.to_be_bytes()).collect();
+    cpu.load_rom_from_bytes(&program);
+    cpu.write_memory(0x210, &[0xc0, 0x80]);
+
+    for _ in 0..3 {
+        cpu.cycle();
+    }
+    assert_eq!(cpu.screen()[0][..3], [0b11, 0b01, 0]);
+
+    for _ in 0..4 {
+        cpu.cycle();
+    }
+    assert_eq!(cpu.screen()[0][..3], [0b01, 0b01, 0]);
+    assert_eq!(cpu.v()[0xf], 0);
+}
+
+#[test]
+fn save_states_restore_the_machine() {
+    let path = std::env::temp_dir().join("chip-8-vm-save-state.state");
+    let path = path.to_str().unwrap();
+
+    // LD V0, 0x05, LD F, V0, DRW V0, V0, 5 and CALL 0x20a, which holds LD V1, 0x01 and RET
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+    run_with_data(&mut cpu, &[0x6005, 0xf029, 0xd005, 0x220a], 0x20a, &[0x61, 0x01, 0x00, 0xee]);
+    cpu.save_state(path).unwrap();
+
+    let mut other = headless_cpu(&config);
+    other.load_state(path).unwrap();
+    assert_eq!(other.pc(), cpu.pc());
+    assert_eq!(other.v(), cpu.v());
+    assert_eq!(other.i(), cpu.i());
+    assert_eq!(other.memory()[..], cpu.memory()[..]);
+    assert_eq!(other.screen(), cpu.screen());
+
+    // RET goes back to where the saved CALL came from
+    other.cycle();
+    other.cycle();
+    assert_eq!(other.v()[1], 1);
+    assert_eq!(other.pc(), 0x208);
+
+    // Truncated or unrelated files leave the cpu untouched
+    let bytes = std::fs::read(path).unwrap();
+    std::fs::write(path, &bytes[..bytes.len() - 1]).unwrap();
+    let error = other.load_state(path).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    std::fs::write(path, b"not a save state at all").unwrap();
+    assert!(other.load_state(path).is_err());
+    assert_eq!(other.pc(), 0x208);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn seeded_runs_are_reproducible() {
+    // RND V0..V3, 0xFF
+    fn random_registers(args : &[&str]) -> Vec<u8> {
+        let config = config(args);
+        let mut cpu = headless_cpu(&config);
+        run(&mut cpu, &[0xc0ff, 0xc1ff, 0xc2ff, 0xc3ff]);
+
+        cpu.v()[..4].to_vec()
+    }
+
+    assert_eq!(random_registers(&["--seed", "42"]), random_registers(&["--seed", "42"]));
+    assert_ne!(random_registers(&["--seed", "42"]), random_registers(&["--seed", "43"]));
+}
+
+#[test]
+fn steps_run_one_instruction_while_paused() {
+    let config = config(&[]);
+    let pause = Arc::new(Mutex::new(true));
+    let mut cpu = Cpu::new_headless(&config, Arc::new(Mutex::new((0, 0))), Arc::clone(&pause), Rc::new(RefCell::new(0))).unwrap();
+
+    // LD V0, 0x01 and LD V1, 0x02
+    cpu.load_rom_from_bytes(&[0x60, 0x01, 0x61, 0x02]);
+    cpu.cycle();
+    assert_eq!(cpu.pc(), 0x200);
+
+    cpu.request_step();
+    cpu.cycle();
+    cpu.cycle();
+    assert_eq!(cpu.pc(), 0x202);
+    assert_eq!(cpu.v()[..2], [1, 0]);
+
+    // Steps aren't kept for later while running
+    *pause.lock().unwrap() = false;
+    cpu.request_step();
+    cpu.cycle();
+    *pause.lock().unwrap() = true;
+    cpu.cycle();
+    assert_eq!(cpu.pc(), 0x204);
+}
+
+#[test]
+fn breakpoints_pause_before_the_instruction() {
+    let config = config(&["--break", "0x202"]);
+    let pause = Arc::new(Mutex::new(false));
+    let mut cpu = Cpu::new_headless(&config, Arc::new(Mutex::new((0, 0))), Arc::clone(&pause), Rc::new(RefCell::new(0))).unwrap();
+
+    // LD V0, 0x01, LD V1, 0x02 and JP 0x202
+    cpu.load_rom_from_bytes(&[0x60, 0x01, 0x61, 0x02, 0x12, 0x02]);
+    for _ in 0..3 {
+        cpu.cycle();
+    }
+    assert!(*pause.lock().unwrap());
+    assert_eq!(cpu.pc(), 0x202);
+    assert_eq!(cpu.v()[1], 0);
+
+    // Stopping there isn't a cycle, the count is the same as without the breakpoint
+    assert_eq!(cpu.cycles(), 1);
+
+    // Resuming runs it, until the jump comes back to it
+    *pause.lock().unwrap() = false;
+    cpu.cycle();
+    cpu.cycle();
+    assert_eq!(cpu.v()[1], 2);
+    cpu.cycle();
+    assert!(*pause.lock().unwrap());
+    assert_eq!(cpu.pc(), 0x202);
+    assert_eq!(cpu.cycles(), 3);
+
+    assert!(Config::from_iter_safe(&["chip-8-vm", "--selftest", "--break", "0x1000"]).is_err());
+}
+
+#[test]
+fn frequencies_are_validated() {
+    assert_eq!(config(&["--freq", "700"]).freq(), Some(700));
+    assert_eq!(config(&[]).freq(), None);
+    assert!(Config::from_iter_safe(&["chip-8-vm", "--selftest", "--freq", "0"]).is_err());
+}
+
+#[test]
+fn screenshots_are_written_as_png() {
+    let path = std::env::temp_dir().join("chip-8-vm-screenshot.png");
+    let path = path.to_str().unwrap();
+
+    // LD F, V0 and DRW V0, V0, 5, the 0 glyph at (0, 0)
+    let config = config(&[]);
+    let mut cpu = headless_cpu(&config);
+    run(&mut cpu, &[0xf029, 0xd005]);
+    cpu.save_screenshot(path).unwrap();
+
+    let png = std::fs::read(path).unwrap();
+    assert_eq!(png[1..4], *b"PNG");
+    assert_eq!(png[16..24], [0, 0, 0, 64, 0, 0, 0, 32]); // headless screenshots aren't scaled
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn replays_press_the_recorded_keys_at_the_same_cycle() {
+    let path = std::env::temp_dir().join("chip-8-vm-replay-test.txt");
+    let path = path.to_str().unwrap();
+
+    // LD V0, 5, then SKP V0 and JP 0x202 until key 5 is pressed, then LD V1, 1 and JP 0x208 forever
+    let rom = [0x60, 0x05, 0xe0, 0x9e, 0x12, 0x02, 0x61, 0x01, 0x12, 0x08];
+
+    let recording = config(&["--record", path]);
+    let mut cpu = headless_cpu(&recording);
+    cpu.load_rom_from_bytes(&rom);
+    for _ in 0..5 {
+        cpu.cycle();
+    }
+    cpu.key_down(0x5);
+    for _ in 0..5 {
+        cpu.cycle();
+    }
+    assert_eq!(cpu.v()[1], 1);
+    drop(cpu); // writes out the rest of the recording
+
+    let replaying = config(&["--replay", path]);
+    let mut cpu = headless_cpu(&replaying);
+    cpu.load_rom_from_bytes(&rom);
+
+    // The SKP in the 6th cycle sees the key, so LD V1 runs in the 7th
+    for _ in 0..6 {
+        cpu.cycle();
+    }
+    assert_eq!(cpu.v()[1], 0);
+    cpu.cycle();
+    assert_eq!(cpu.v()[1], 1);
+
+    std::fs::remove_file(path).unwrap();
+}